@@ -0,0 +1,16 @@
+// lists the AirPlay receivers on the local network, with the coding each one asks for
+use std::time::Duration;
+
+use raop_play::{discovery, RaopParams};
+
+#[tokio::main(basic_scheduler)]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    for receiver in discovery::browse(Duration::from_secs(2)).await? {
+        match RaopParams::from_receiver(&receiver) {
+            Ok(params) => println!("{} on port {}: {} coding with {}-bit samples", receiver.name, receiver.port, params.codec().name(), params.codec().sample_size()),
+            Err(err) => println!("{} on port {}: {}", receiver.name, receiver.port, err),
+        }
+    }
+
+    Ok(())
+}
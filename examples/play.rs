@@ -0,0 +1,44 @@
+// streams a WAVE, FLAC or raw s16le file to a receiver, titled with the file name
+//
+//     cargo run --example play -- 192.168.1.10 song.wav
+use std::env;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use raop_play::input::{self, ResampleQuality};
+use raop_play::{AudioFormat, RaopClient, RaopParams, TrackMetadata, Volume};
+
+#[tokio::main(basic_scheduler)]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = env::args().collect::<Vec<_>>();
+
+    if args.len() != 3 {
+        return Err("usage: play <server-ip> <filename>".into());
+    }
+
+    let remote = SocketAddr::new(args[1].parse()?, 5000);
+    let params = RaopParams::new();
+
+    let input = input::open(&args[2], AudioFormat::from_name("s16le", 2)?, 44100)?;
+    input::check_format(input.format())?;
+    let input = input::resample(input, params.codec().sample_rate(), ResampleQuality::Medium);
+
+    let mut client = RaopClient::connect(params, remote).await?;
+    client.set_volume(Volume::from_percent(50)).await?;
+
+    let meta_data = TrackMetadata { title: Some(args[2].clone()), ..TrackMetadata::new() };
+    client.set_meta_data(meta_data.to_listing_item()).await?;
+
+    // the receiver may have picked another coding than the one asked for
+    let mut chunks = input::spawn(input, client.codec().input_format(), client.codec().chunk_length(), 16);
+    let mut playtime = Duration::new(0, 0);
+
+    while let Some(chunk) = chunks.recv().await {
+        client.accept_frames().await?;
+        client.send_chunk(&chunk?, &mut playtime).await?;
+    }
+
+    client.teardown().await?;
+
+    Ok(())
+}
@@ -0,0 +1,205 @@
+use std::error::Error;
+use std::fmt::{self, Formatter, Display};
+
+use byteorder::{BE, ByteOrder};
+
+const JPEG_MAGIC: &[u8] = &[0xFF, 0xD8, 0xFF];
+const PNG_MAGIC: &[u8] = &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+// larger images are passed to the scaler, if one is set
+pub const MAX_ARTWORK_DIMENSION: u32 = 1024;
+
+pub type ArtworkScaler = Box<dyn Fn(&Artwork, u32) -> Result<Artwork, Box<dyn Error + Send + Sync>> + Send + Sync>;
+
+#[derive(Debug)]
+pub enum ArtworkError {
+    UnknownFormat,
+    ContentTypeMismatch(String),
+    InvalidImage,
+}
+
+impl Display for ArtworkError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for ArtworkError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArtworkType {
+    Jpeg,
+    Png,
+}
+
+impl ArtworkType {
+    pub fn detect(data: &[u8]) -> Option<ArtworkType> {
+        if data.starts_with(JPEG_MAGIC) {
+            Some(ArtworkType::Jpeg)
+        } else if data.starts_with(PNG_MAGIC) {
+            Some(ArtworkType::Png)
+        } else {
+            None
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ArtworkType::Jpeg => "image/jpeg",
+            ArtworkType::Png => "image/png",
+        }
+    }
+}
+
+fn png_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    // the IHDR chunk always comes first
+    if data.get(12..16)? != b"IHDR" { return None; }
+
+    Some((BE::read_u32(data.get(16..20)?), BE::read_u32(data.get(20..24)?)))
+}
+
+fn jpeg_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let mut pos = 2;
+
+    loop {
+        if *data.get(pos)? != 0xFF { return None; }
+
+        // markers may be preceded by any number of fill bytes
+        while *data.get(pos + 1)? == 0xFF { pos += 1; }
+
+        let marker = data[pos + 1];
+        pos += 2;
+
+        match marker {
+            0x01 | 0xD0..=0xD7 => continue,
+            0xD8..=0xDA => return None,
+            _ => {},
+        }
+
+        let len = BE::read_u16(data.get(pos..pos + 2)?) as usize;
+
+        // start of frame, except DHT, JPG and DAC which share the range
+        if let 0xC0..=0xCF = marker {
+            if marker != 0xC4 && marker != 0xC8 && marker != 0xCC {
+                let height = BE::read_u16(data.get(pos + 3..pos + 5)?);
+                let width = BE::read_u16(data.get(pos + 5..pos + 7)?);
+                return Some((width as u32, height as u32));
+            }
+        }
+
+        pos += len;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Artwork {
+    data: Vec<u8>,
+    kind: ArtworkType,
+    width: u32,
+    height: u32,
+}
+
+impl Artwork {
+    pub fn new(data: Vec<u8>, content_type: Option<&str>) -> Result<Artwork, ArtworkError> {
+        let kind = ArtworkType::detect(&data).ok_or(ArtworkError::UnknownFormat)?;
+
+        if let Some(content_type) = content_type {
+            if !content_type.eq_ignore_ascii_case(kind.content_type()) {
+                return Err(ArtworkError::ContentTypeMismatch(content_type.to_owned()));
+            }
+        }
+
+        let (width, height) = match kind {
+            ArtworkType::Jpeg => jpeg_dimensions(&data),
+            ArtworkType::Png => png_dimensions(&data),
+        }.ok_or(ArtworkError::InvalidImage)?;
+
+        Ok(Artwork { data, kind, width, height })
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn kind(&self) -> ArtworkType {
+        self.kind
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        self.kind.content_type()
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn is_oversized(&self, max_dimension: u32) -> bool {
+        self.width > max_dimension || self.height > max_dimension
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Artwork, ArtworkError, ArtworkType};
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut data = super::PNG_MAGIC.to_vec();
+        data.extend_from_slice(&[0, 0, 0, 13]);
+        data.extend_from_slice(b"IHDR");
+        data.extend_from_slice(&width.to_be_bytes());
+        data.extend_from_slice(&height.to_be_bytes());
+        data.extend_from_slice(&[8, 2, 0, 0, 0]);
+        data
+    }
+
+    fn jpeg(width: u16, height: u16) -> Vec<u8> {
+        let mut data = vec![0xFF, 0xD8];
+        // APP0 and a padded DHT before the frame header
+        data.extend_from_slice(&[0xFF, 0xE0, 0x00, 0x07, b'J', b'F', b'I', b'F', 0x00]);
+        data.extend_from_slice(&[0xFF, 0xFF, 0xC4, 0x00, 0x03, 0x00]);
+        data.extend_from_slice(&[0xFF, 0xC2, 0x00, 0x11, 0x08]);
+        data.extend_from_slice(&height.to_be_bytes());
+        data.extend_from_slice(&width.to_be_bytes());
+        data.extend_from_slice(&[0x03, 0x01, 0x22, 0x00, 0x02, 0x11, 0x01, 0x03, 0x11, 0x01]);
+        data.extend_from_slice(&[0xFF, 0xDA]);
+        data
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(ArtworkType::detect(&png(1, 1)), Some(ArtworkType::Png));
+        assert_eq!(ArtworkType::detect(&jpeg(1, 1)), Some(ArtworkType::Jpeg));
+        assert_eq!(ArtworkType::detect(b"GIF89a"), None);
+    }
+
+    #[test]
+    fn test_dimensions() {
+        let artwork = Artwork::new(png(600, 400), None).unwrap();
+        assert_eq!(artwork.content_type(), "image/png");
+        assert_eq!(artwork.dimensions(), (600, 400));
+        assert!(!artwork.is_oversized(super::MAX_ARTWORK_DIMENSION));
+
+        let artwork = Artwork::new(jpeg(3000, 2000), Some("image/jpeg")).unwrap();
+        assert_eq!(artwork.content_type(), "image/jpeg");
+        assert_eq!(artwork.dimensions(), (3000, 2000));
+        assert!(artwork.is_oversized(super::MAX_ARTWORK_DIMENSION));
+    }
+
+    #[test]
+    fn test_reject() {
+        match Artwork::new(png(600, 400), Some("image/jpeg")) {
+            Err(ArtworkError::ContentTypeMismatch(content_type)) => assert_eq!(content_type, "image/jpeg"),
+            result => panic!("expected a content type mismatch, got {:?}", result),
+        }
+
+        match Artwork::new(b"GIF89a".to_vec(), None) {
+            Err(ArtworkError::UnknownFormat) => {},
+            result => panic!("expected an unknown format, got {:?}", result),
+        }
+
+        match Artwork::new(vec![0xFF, 0xD8, 0xFF, 0xE0, 0x00], None) {
+            Err(ArtworkError::InvalidImage) => {},
+            result => panic!("expected an invalid image, got {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use std::error::Error;
+use std::fmt::{self, Formatter, Display};
+
+use byteorder::{BE, ByteOrder, LE};
+
+#[derive(Debug)]
+pub enum AudioFormatError {
+    UnknownSampleFormat(String),
+    InvalidChannels(u16),
+}
+
+impl Display for AudioFormatError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for AudioFormatError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SampleType {
+    // unsigned, with silence at 128
+    U8,
+    S16,
+    // packed into three bytes
+    S24,
+    S32,
+    F32,
+    F64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioFormat {
+    pub sample_type: SampleType,
+    pub endianness: Endianness,
+    pub channels: u16,
+}
+
+fn float_to_sample(value: f64) -> i32 {
+    (value * 2_147_483_648.0).clamp(-2_147_483_648.0, 2_147_483_647.0) as i32
+}
+
+impl AudioFormat {
+    pub const fn new(sample_type: SampleType, endianness: Endianness, channels: u16) -> AudioFormat {
+        AudioFormat { sample_type, endianness, channels }
+    }
+
+    // parses names like s16le or f32be, as used by sox and ffmpeg
+    pub fn from_name(name: &str, channels: u16) -> Result<AudioFormat, AudioFormatError> {
+        if channels == 0 {
+            return Err(AudioFormatError::InvalidChannels(channels));
+        }
+
+        let (sample_type, endianness) = match name {
+            "u8" => (SampleType::U8, Endianness::Little),
+            "s16le" => (SampleType::S16, Endianness::Little),
+            "s16be" => (SampleType::S16, Endianness::Big),
+            "s24le" => (SampleType::S24, Endianness::Little),
+            "s24be" => (SampleType::S24, Endianness::Big),
+            "s32le" => (SampleType::S32, Endianness::Little),
+            "s32be" => (SampleType::S32, Endianness::Big),
+            "f32le" => (SampleType::F32, Endianness::Little),
+            "f32be" => (SampleType::F32, Endianness::Big),
+            "f64le" => (SampleType::F64, Endianness::Little),
+            "f64be" => (SampleType::F64, Endianness::Big),
+            _ => return Err(AudioFormatError::UnknownSampleFormat(name.to_owned())),
+        };
+
+        Ok(AudioFormat { sample_type, endianness, channels })
+    }
+
+    pub fn bytes_per_sample(&self) -> usize {
+        match self.sample_type {
+            SampleType::U8 => 1,
+            SampleType::S16 => 2,
+            SampleType::S24 => 3,
+            SampleType::S32 | SampleType::F32 => 4,
+            SampleType::F64 => 8,
+        }
+    }
+
+    pub fn bytes_per_frame(&self) -> usize {
+        self.bytes_per_sample() * self.channels as usize
+    }
+
+    pub fn bits_per_sample(&self) -> u16 {
+        self.bytes_per_sample() as u16 * 8
+    }
+
+    pub fn is_float(&self) -> bool {
+        self.sample_type == SampleType::F32 || self.sample_type == SampleType::F64
+    }
+
+    // reads one sample, left justified into 32 bits
+    pub fn read_sample(&self, bytes: &[u8]) -> i32 {
+        match (self.sample_type, self.endianness) {
+            (SampleType::U8, _) => (i32::from(bytes[0]) - 128) << 24,
+            (SampleType::S16, Endianness::Little) => i32::from(LE::read_i16(bytes)) << 16,
+            (SampleType::S16, Endianness::Big) => i32::from(BE::read_i16(bytes)) << 16,
+            (SampleType::S24, Endianness::Little) => LE::read_i24(bytes) << 8,
+            (SampleType::S24, Endianness::Big) => BE::read_i24(bytes) << 8,
+            (SampleType::S32, Endianness::Little) => LE::read_i32(bytes),
+            (SampleType::S32, Endianness::Big) => BE::read_i32(bytes),
+            (SampleType::F32, Endianness::Little) => float_to_sample(f64::from(LE::read_f32(bytes))),
+            (SampleType::F32, Endianness::Big) => float_to_sample(f64::from(BE::read_f32(bytes))),
+            (SampleType::F64, Endianness::Little) => float_to_sample(LE::read_f64(bytes)),
+            (SampleType::F64, Endianness::Big) => float_to_sample(BE::read_f64(bytes)),
+        }
+    }
+
+    // writes one left justified sample, dropping the bits that don't fit
+    pub fn write_sample(&self, sample: i32, output: &mut Vec<u8>) {
+        let mut bytes = [0u8; 8];
+        let len = self.bytes_per_sample();
+
+        match (self.sample_type, self.endianness) {
+            (SampleType::U8, _) => bytes[0] = ((sample >> 24) + 128) as u8,
+            (SampleType::S16, Endianness::Little) => LE::write_i16(&mut bytes, (sample >> 16) as i16),
+            (SampleType::S16, Endianness::Big) => BE::write_i16(&mut bytes, (sample >> 16) as i16),
+            (SampleType::S24, Endianness::Little) => LE::write_i24(&mut bytes, sample >> 8),
+            (SampleType::S24, Endianness::Big) => BE::write_i24(&mut bytes, sample >> 8),
+            (SampleType::S32, Endianness::Little) => LE::write_i32(&mut bytes, sample),
+            (SampleType::S32, Endianness::Big) => BE::write_i32(&mut bytes, sample),
+            (SampleType::F32, Endianness::Little) => LE::write_f32(&mut bytes, sample as f32 / 2_147_483_648.0),
+            (SampleType::F32, Endianness::Big) => BE::write_f32(&mut bytes, sample as f32 / 2_147_483_648.0),
+            (SampleType::F64, Endianness::Little) => LE::write_f64(&mut bytes, f64::from(sample) / 2_147_483_648.0),
+            (SampleType::F64, Endianness::Big) => BE::write_f64(&mut bytes, f64::from(sample) / 2_147_483_648.0),
+        }
+
+        output.extend_from_slice(&bytes[0..len]);
+    }
+}
+
+impl Display for AudioFormat {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let name = match self.sample_type {
+            SampleType::U8 => "u8",
+            SampleType::S16 => "s16",
+            SampleType::S24 => "s24",
+            SampleType::S32 => "s32",
+            SampleType::F32 => "f32",
+            SampleType::F64 => "f64",
+        };
+
+        let endianness = match (self.sample_type, self.endianness) {
+            (SampleType::U8, _) => "",
+            (_, Endianness::Little) => "le",
+            (_, Endianness::Big) => "be",
+        };
+
+        write!(f, "{}{}, {} channels", name, endianness, self.channels)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AudioFormat, Endianness, SampleType};
+
+    #[test]
+    fn test_from_name() {
+        let format = AudioFormat::from_name("s24le", 1).unwrap();
+        assert_eq!(format, AudioFormat::new(SampleType::S24, Endianness::Little, 1));
+        assert_eq!(format.bytes_per_frame(), 3);
+        assert_eq!(format.to_string(), "s24le, 1 channels");
+
+        assert_eq!(AudioFormat::from_name("f32be", 2).unwrap().bytes_per_frame(), 8);
+        assert!(AudioFormat::from_name("s20le", 2).is_err());
+        assert!(AudioFormat::from_name("s16le", 0).is_err());
+    }
+
+    #[test]
+    fn test_read_write_sample() {
+        let cases: &[(&str, &[u8], i32)] = &[
+            ("u8", &[0x81], 1 << 24),
+            ("s16le", &[0x34, 0x12], 0x1234 << 16),
+            ("s16be", &[0x12, 0x34], 0x1234 << 16),
+            ("s24le", &[0x56, 0x34, 0x92], -0x6DCBAA << 8),
+            ("s24be", &[0x12, 0x34, 0x56], 0x0012_3456 << 8),
+            ("s32be", &[0x80, 0x00, 0x00, 0x00], i32::MIN),
+            ("f32le", &[0x00, 0x00, 0x00, 0xBF], -1 << 30),
+            ("f64be", &[0x3F, 0xD0, 0, 0, 0, 0, 0, 0], 1 << 29),
+        ];
+
+        for (name, bytes, sample) in cases {
+            let format = AudioFormat::from_name(name, 2).unwrap();
+            assert_eq!(format.read_sample(bytes), *sample, "{}", name);
+
+            let mut output = Vec::new();
+            format.write_sample(*sample, &mut output);
+            assert_eq!(&output[..], *bytes, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use alac_encoder::{AlacEncoder, FormatDescription, MAX_ESCAPE_HEADER_BYTES};
+
+use super::{AudioEncoder, fallbacks};
+use crate::audio_format::{AudioFormat, Endianness, SampleType};
+use crate::frames::Frames;
+use crate::sample_rate::SampleRate;
+
+// ALAC element tags
+const ID_SCE: u32 = 0;
+const ID_CPE: u32 = 1;
+const ID_END: u32 = 7;
+
+struct BitWriter {
+    data: Vec<u8>,
+    cache: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn new(capacity: usize) -> BitWriter {
+        BitWriter { data: Vec::with_capacity(capacity), cache: 0, bits: 0 }
+    }
+
+    fn write(&mut self, value: u32, n: u32) {
+        self.cache = (self.cache << n) | (u64::from(value) & ((1 << n) - 1));
+        self.bits += n;
+
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.data.push((self.cache >> self.bits) as u8);
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.write(0, 8 - self.bits);
+        }
+
+        self.data
+    }
+}
+
+fn alac_sdp(encoder: &dyn AudioEncoder) -> String {
+    format!(
+        "m=audio 0 RTP/AVP 96\r\na=rtpmap:96 AppleLossless\r\na=fmtp:96 {} 0 {} 40 10 14 {} 255 0 0 {}\r\n",
+        encoder.chunk_length(),
+        encoder.sample_size(),
+        encoder.channels(),
+        encoder.sample_rate(),
+    )
+}
+
+// 16-bit ALAC, compressed by alac-encoder
+pub struct AlacCodec {
+    encoder: Box<AlacEncoder>,
+    input_format: FormatDescription,
+    sample_rate: SampleRate,
+}
+
+impl AlacCodec {
+    pub fn new(chunk_length: Frames, sample_rate: SampleRate, channels: u8) -> AlacCodec {
+        let input_format = FormatDescription::pcm::<i16>(u64::from(sample_rate) as f64, channels as u32);
+        let output_format = FormatDescription::alac(u64::from(sample_rate) as f64, u64::from(chunk_length) as u32, channels as u32);
+        AlacCodec { encoder: Box::new(AlacEncoder::new(&output_format)), input_format, sample_rate }
+    }
+}
+
+impl AudioEncoder for AlacCodec {
+    fn name(&self) -> &str {
+        "ALAC"
+    }
+
+    fn chunk_length(&self) -> Frames {
+        (self.encoder.frames() as u64).into()
+    }
+
+    fn sample_rate(&self) -> SampleRate {
+        self.sample_rate
+    }
+
+    fn sample_size(&self) -> u32 {
+        self.encoder.bit_depth() as u32
+    }
+
+    fn channels(&self) -> u8 {
+        self.encoder.channels() as u8
+    }
+
+    fn sdp(&self) -> String {
+        alac_sdp(self)
+    }
+
+    fn encode_chunk(&mut self, sample: &[u8]) -> Vec<u8> {
+        let max_size = sample.len() + MAX_ESCAPE_HEADER_BYTES;
+        let mut encoded = Vec::with_capacity(max_size);
+
+        unsafe { encoded.set_len(max_size); }
+        let size = self.encoder.encode(&self.input_format, sample, &mut encoded);
+        unsafe { encoded.set_len(size); }
+
+        encoded
+    }
+
+    fn fallbacks(&self) -> Vec<Box<dyn AudioEncoder>> {
+        fallbacks(self, true)
+    }
+}
+
+// the encoder only compresses 16-bit audio, wider samples are sent as uncompressed ALAC frames
+pub struct UncompressedAlacCodec {
+    chunk_length: Frames,
+    sample_rate: SampleRate,
+    sample_size: u32,
+    channels: u8,
+}
+
+impl UncompressedAlacCodec {
+    pub fn new(chunk_length: Frames, sample_rate: SampleRate, sample_size: u32, channels: u8) -> UncompressedAlacCodec {
+        UncompressedAlacCodec { chunk_length, sample_rate, sample_size, channels }
+    }
+}
+
+impl AudioEncoder for UncompressedAlacCodec {
+    fn name(&self) -> &str {
+        "ALAC"
+    }
+
+    fn chunk_length(&self) -> Frames {
+        self.chunk_length
+    }
+
+    fn sample_rate(&self) -> SampleRate {
+        self.sample_rate
+    }
+
+    fn sample_size(&self) -> u32 {
+        self.sample_size
+    }
+
+    fn channels(&self) -> u8 {
+        self.channels
+    }
+
+    fn sdp(&self) -> String {
+        alac_sdp(self)
+    }
+
+    fn encode_chunk(&mut self, sample: &[u8]) -> Vec<u8> {
+        let format = AudioFormat::new(SampleType::S24, Endianness::Little, u16::from(self.channels));
+        let frames = sample.len() / format.bytes_per_frame();
+        let partial = frames as u64 != u64::from(self.chunk_length);
+
+        let mut writer = BitWriter::new(sample.len() + MAX_ESCAPE_HEADER_BYTES);
+
+        // element header, unused bits, partial frame, bytes shifted and the escape flag for uncompressed samples
+        writer.write(if self.channels == 1 { ID_SCE } else { ID_CPE }, 3);
+        writer.write(0, 4);
+        writer.write(0, 12);
+        writer.write(partial as u32, 1);
+        writer.write(0, 2);
+        writer.write(1, 1);
+
+        if partial {
+            writer.write(frames as u32, 32);
+        }
+
+        for bytes in sample[0..frames * format.bytes_per_frame()].chunks_exact(format.bytes_per_sample()) {
+            writer.write((format.read_sample(bytes) >> (32 - self.sample_size)) as u32, self.sample_size);
+        }
+
+        writer.write(ID_END, 3);
+        writer.finish()
+    }
+
+    fn fallbacks(&self) -> Vec<Box<dyn AudioEncoder>> {
+        fallbacks(self, true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::UncompressedAlacCodec;
+    use crate::codec::AudioEncoder;
+    use crate::frames::Frames;
+    use crate::sample_rate::SampleRate;
+
+    #[test]
+    fn test_encode_uncompressed_alac() {
+        let mut codec = UncompressedAlacCodec::new(Frames::new(352), SampleRate::Hz44100, 24, 2);
+        let encoded = codec.encode_chunk(&[0x56, 0x34, 0x12, 0xFF, 0xFE, 0x80]);
+
+        let bits = encoded.iter().map(|byte| format!("{:08b}", byte)).collect::<String>();
+        let field = |start: usize, len: usize| u32::from_str_radix(&bits[start..start + len], 2).unwrap();
+
+        // a partial channel pair element of one frame, without compression
+        assert_eq!(field(0, 3), 1);
+        assert_eq!(field(19, 1), 1);
+        assert_eq!(field(22, 1), 1);
+        assert_eq!(field(23, 32), 1);
+        assert_eq!(field(55, 24), 0x12_3456);
+        assert_eq!(field(79, 24), 0x80_FEFF);
+        assert_eq!(field(103, 3), 7);
+        assert_eq!(encoded.len(), 14);
+    }
+}
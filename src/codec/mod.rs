@@ -0,0 +1,94 @@
+use crate::audio_format::{AudioFormat, Endianness, SampleType};
+use crate::frames::Frames;
+use crate::raop_error::RaopError;
+use crate::sample_rate::SampleRate;
+
+mod alac;
+mod pcm;
+
+pub use self::alac::{AlacCodec, UncompressedAlacCodec};
+pub use self::pcm::PcmCodec;
+
+pub trait AudioEncoder: Send + Sync {
+    // the coding, as logged
+    fn name(&self) -> &str;
+
+    fn chunk_length(&self) -> Frames;
+    fn sample_rate(&self) -> SampleRate;
+    fn sample_size(&self) -> u32;
+    fn channels(&self) -> u8;
+
+    // the format of the samples passed to encode_chunk
+    fn input_format(&self) -> AudioFormat {
+        let sample_type = if self.sample_size() > 16 { SampleType::S24 } else { SampleType::S16 };
+        AudioFormat::new(sample_type, Endianness::Little, u16::from(self.channels()))
+    }
+
+    // the media description announced to the receiver
+    fn sdp(&self) -> String;
+
+    fn encode_chunk(&mut self, sample: &[u8]) -> Vec<u8>;
+
+    // encoders to offer when a receiver rejects this one, in order of preference
+    fn fallbacks(&self) -> Vec<Box<dyn AudioEncoder>> {
+        Vec::new()
+    }
+}
+
+pub fn new_encoder(alac: bool, chunk_length: Frames, sample_rate: SampleRate, sample_size: u32, channels: u8) -> Result<Box<dyn AudioEncoder>, RaopError> {
+    if sample_size != 16 && sample_size != 24 {
+        return Err(RaopError::Codec(format!("unsupported sample size {}", sample_size)));
+    }
+
+    if alac && sample_size == 16 {
+        Ok(Box::new(AlacCodec::new(chunk_length, sample_rate, channels)))
+    } else if alac {
+        Ok(Box::new(UncompressedAlacCodec::new(chunk_length, sample_rate, sample_size, channels)))
+    } else {
+        Ok(Box::new(PcmCodec::new(chunk_length, sample_rate, sample_size, channels)))
+    }
+}
+
+// the same coding with 16-bit samples, then the other coding
+fn fallbacks(encoder: &dyn AudioEncoder, alac: bool) -> Vec<Box<dyn AudioEncoder>> {
+    let (chunk_length, sample_rate, sample_size, channels) = (encoder.chunk_length(), encoder.sample_rate(), encoder.sample_size(), encoder.channels());
+    let mut candidates = vec![(alac, 16), (!alac, sample_size), (!alac, 16)];
+
+    candidates.dedup();
+    candidates.into_iter()
+        .filter(|candidate| *candidate != (alac, sample_size))
+        .filter_map(|(alac, sample_size)| new_encoder(alac, chunk_length, sample_rate, sample_size, channels).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::AudioEncoder;
+    use crate::frames::Frames;
+    use crate::sample_rate::SampleRate;
+
+    #[test]
+    fn test_sdp() {
+        let codec = super::new_encoder(true, Frames::new(352), SampleRate::Hz44100, 24, 2).unwrap();
+        assert_eq!(codec.sdp(), "m=audio 0 RTP/AVP 96\r\na=rtpmap:96 AppleLossless\r\na=fmtp:96 352 0 24 40 10 14 2 255 0 0 44100\r\n");
+
+        let codec = super::new_encoder(false, Frames::new(352), SampleRate::Hz48000, 24, 1).unwrap();
+        assert_eq!(codec.sdp(), "m=audio 0 RTP/AVP 96\r\na=rtpmap:96 L24/48000/1\r\n");
+    }
+
+    #[test]
+    fn test_fallbacks() {
+        let describe = |codecs: Vec<Box<dyn AudioEncoder>>| codecs.iter().map(|codec| format!("{}/{}", codec.name(), codec.sample_size())).collect::<Vec<_>>();
+
+        let codec = super::new_encoder(true, Frames::new(352), SampleRate::Hz44100, 24, 2).unwrap();
+        assert_eq!(describe(codec.fallbacks()), vec!["ALAC/16", "PCM/24", "PCM/16"]);
+
+        let codec = super::new_encoder(false, Frames::new(352), SampleRate::Hz44100, 16, 2).unwrap();
+        assert_eq!(describe(codec.fallbacks()), vec!["ALAC/16"]);
+    }
+
+    #[test]
+    fn test_unsupported_sample_size() {
+        assert!(super::new_encoder(true, Frames::new(352), SampleRate::Hz44100, 20, 2).is_err());
+    }
+}
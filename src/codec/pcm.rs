@@ -0,0 +1,78 @@
+use super::{AudioEncoder, fallbacks};
+use crate::frames::Frames;
+use crate::sample_rate::SampleRate;
+
+pub struct PcmCodec {
+    chunk_length: Frames,
+    sample_rate: SampleRate,
+    sample_size: u32,
+    channels: u8,
+}
+
+impl PcmCodec {
+    pub fn new(chunk_length: Frames, sample_rate: SampleRate, sample_size: u32, channels: u8) -> PcmCodec {
+        PcmCodec { chunk_length, sample_rate, sample_size, channels }
+    }
+}
+
+impl AudioEncoder for PcmCodec {
+    fn name(&self) -> &str {
+        "PCM"
+    }
+
+    fn chunk_length(&self) -> Frames {
+        self.chunk_length
+    }
+
+    fn sample_rate(&self) -> SampleRate {
+        self.sample_rate
+    }
+
+    fn sample_size(&self) -> u32 {
+        self.sample_size
+    }
+
+    fn channels(&self) -> u8 {
+        self.channels
+    }
+
+    fn sdp(&self) -> String {
+        format!(
+            "m=audio 0 RTP/AVP 96\r\na=rtpmap:96 L{}/{}/{}\r\n",
+            self.sample_size,
+            self.sample_rate,
+            self.channels,
+        )
+    }
+
+    fn encode_chunk(&mut self, sample: &[u8]) -> Vec<u8> {
+        // RTP carries linear PCM in network byte order
+        let sample_size = self.input_format().bytes_per_sample();
+        let mut encoded = Vec::with_capacity(sample.len());
+
+        for bytes in sample.chunks_exact(sample_size) {
+            encoded.extend(bytes.iter().rev());
+        }
+
+        encoded
+    }
+
+    fn fallbacks(&self) -> Vec<Box<dyn AudioEncoder>> {
+        fallbacks(self, false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PcmCodec;
+    use crate::codec::AudioEncoder;
+    use crate::frames::Frames;
+    use crate::sample_rate::SampleRate;
+
+    #[test]
+    fn test_encode_l24() {
+        let mut codec = PcmCodec::new(Frames::new(352), SampleRate::Hz44100, 24, 2);
+        assert_eq!(codec.input_format().bytes_per_frame(), 6);
+        assert_eq!(codec.encode_chunk(&[0x56, 0x34, 0x12, 0xFF, 0xFE, 0x80]), vec![0x12, 0x34, 0x56, 0x80, 0xFE, 0xFF]);
+    }
+}
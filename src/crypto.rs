@@ -1,9 +1,109 @@
+use std::error::Error;
+use std::fmt::{self, Formatter, Display};
+use std::net::IpAddr;
+
 use base64;
 use openssl::bn::BigNum;
+use openssl::error::ErrorStack;
+use openssl::pkey::{HasPublic, Public};
 use openssl::rsa::{Rsa, Padding};
-use openssl::symm::{encrypt, Cipher};
+use openssl::symm::{Cipher, Crypter, Mode};
 use rand::random;
-use log::trace;
+use log::{debug, trace};
+
+const AES_BLOCK_SIZE: usize = 16;
+
+// the public key of AirPort Express receivers, big endian hex
+const APPLE_MODULUS: &str = "E7D744F2A2E2788B6C1F55A08EB70544A8FA7945AA8BE6C62CE5F51CBDD4DC6842FE3D1083DD2EDEC1BFD4252DC02E6F398BDF0E6148EA84855E2E442DA6D62664F674A1F304929ADE4F6893EF2DF6E711A8C77A0D91C9D980822E50D12922AFEA40EA9F0E14C0F76938C5F3882FC0323DD9FE55155F51BB5921C201629FD73352D5E2EFAABF9BA048D7B813A2B6767F6C3CCF1EB4CE673D037B0D2EA30C5FFFEB06F8D08ADDE409571A9C689FEF10728855DD8CFB9A8BEF5C8943EF3B5FAA15DDE698BEDDF3599603EB3E6F61372BB628F6559F599A78BF500687AA7F4976C0562D412956F8989E18A6355BD81597825E0FC875343EC782117625CDBF98447B";
+const APPLE_EXPONENT: &str = "010001";
+
+fn apple_public_key() -> Result<Rsa<Public>, ErrorStack> {
+    Rsa::from_public_components(BigNum::from_hex_str(APPLE_MODULUS)?, BigNum::from_hex_str(APPLE_EXPONENT)?)
+}
+
+#[derive(Debug)]
+pub enum AppleResponseError {
+    MissingResponse,
+    // without the hardware address of the receiver there is nothing to check its claim against
+    UnknownMacAddress,
+    InvalidEncoding(base64::DecodeError),
+    InvalidSignature(openssl::error::ErrorStack),
+    Mismatch,
+}
+
+impl Display for AppleResponseError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            AppleResponseError::MissingResponse => write!(f, "receiver did not answer the Apple-Challenge"),
+            AppleResponseError::UnknownMacAddress => write!(f, "the MAC address of the receiver is needed to verify its Apple-Response"),
+            AppleResponseError::InvalidEncoding(source) => write!(f, "Apple-Response is not valid base64: {}", source),
+            AppleResponseError::InvalidSignature(source) => write!(f, "Apple-Response is not signed by an Apple key: {}", source),
+            AppleResponseError::Mismatch => write!(f, "Apple-Response does not match the challenge, address and MAC address"),
+        }
+    }
+}
+
+impl Error for AppleResponseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AppleResponseError::InvalidEncoding(source) => Some(source),
+            AppleResponseError::InvalidSignature(source) => Some(source),
+            _ => None,
+        }
+    }
+}
+
+pub struct AppleChallenge([u8; 16]);
+
+impl AppleChallenge {
+    pub fn new() -> AppleChallenge {
+        AppleChallenge(random())
+    }
+
+    pub fn verify(&self, response: Option<&str>, ip: IpAddr, mac: Option<[u8; 6]>) -> Result<(), AppleResponseError> {
+        let key = apple_public_key().map_err(AppleResponseError::InvalidSignature)?;
+        self.verify_with_key(&key, response, ip, mac)
+    }
+
+    fn verify_with_key<T: HasPublic>(&self, key: &Rsa<T>, response: Option<&str>, ip: IpAddr, mac: Option<[u8; 6]>) -> Result<(), AppleResponseError> {
+        let mut response = response.ok_or(AppleResponseError::MissingResponse)?.trim().to_owned();
+        let mac = mac.ok_or(AppleResponseError::UnknownMacAddress)?;
+
+        // the response is usually sent without base64 padding
+        while response.len() % 4 != 0 { response.push('='); }
+
+        let signature = base64::decode(&response).map_err(AppleResponseError::InvalidEncoding)?;
+
+        let mut decrypted = vec![0u8; key.size() as usize];
+        let size = key.public_decrypt(&signature, &mut decrypted, Padding::PKCS1).map_err(AppleResponseError::InvalidSignature)?;
+        decrypted.truncate(size);
+
+        // the receiver signs the challenge, its own address and its hardware address
+        let mut expected = self.0.to_vec();
+
+        match ip {
+            IpAddr::V4(ip) => expected.extend_from_slice(&ip.octets()),
+            IpAddr::V6(ip) => expected.extend_from_slice(&ip.octets()),
+        }
+
+        expected.extend_from_slice(&mac);
+
+        if expected.len() < 32 { expected.resize(32, 0); }
+
+        if decrypted != expected {
+            debug!("Apple-Response mismatch, expected {} got {}", hex::encode(&expected), hex::encode(&decrypted));
+            return Err(AppleResponseError::Mismatch);
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for AppleChallenge {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", base64::encode_config(&self.0, base64::STANDARD_NO_PAD))
+    }
+}
 
 pub enum Crypto {
     Clear(),
@@ -26,32 +126,179 @@ impl Crypto {
         }
     }
 
-    pub fn sdp(&self) -> String {
+    pub fn sdp(&self) -> Result<String, ErrorStack> {
         match self {
-            Crypto::Clear() => String::from(""),
+            Crypto::Clear() => Ok(String::from("")),
             Crypto::AES { key, iv } => {
-                let modules = BigNum::from_slice(&base64::decode("59dE8qLieItsH1WgjrcFRKj6eUWqi+bGLOX1HL3U3GhC/j0Qg90u3sG/1CUtwC5vOYvfDmFI6oSFXi5ELabWJmT2dKHzBJKa3k9ok+8t9ucRqMd6DZHJ2YCCLlDRKSKv6kDqnw4UwPdpOMXziC/AMj3Z/lUVX1G7WSHCAWKf1zNS1eLvqr+boEjXuBOitnZ/bDzPHrTOZz0Dew0uowxf/+sG+NCK3eQJVxqcaJ/vEHKIVd2M+5qL71yJQ+87X6oV3eaYvt3zWZYD6z5vYTcrtij2VZ9Zmni/UAaHqn9JdsBWLUEpVviYnhimNVvYFZeCXg/IdTQ+x4IRdiXNv5hEew==").unwrap()).unwrap();
-                let exponent = BigNum::from_slice(&base64::decode("AQAB").unwrap()).unwrap();
-
-                let rsa = Rsa::from_public_components(modules, exponent).unwrap();
+                let rsa = apple_public_key()?;
                 let mut rsakey = [0u8; 512];
-                let rsakey_size = rsa.public_encrypt(key, &mut rsakey, Padding::PKCS1_OAEP).unwrap();
+                let rsakey_size = rsa.public_encrypt(key, &mut rsakey, Padding::PKCS1_OAEP)?;
 
                 let rsakey = base64::encode_config(&rsakey[0..rsakey_size], base64::STANDARD_NO_PAD);
                 let iv = base64::encode_config(iv, base64::STANDARD_NO_PAD);
 
-                format!("a=rsaaeskey:{}\r\na=aesiv:{}\r\n", rsakey, iv)
+                Ok(format!("a=rsaaeskey:{}\r\na=aesiv:{}\r\n", rsakey, iv))
             },
         }
     }
 
-    pub fn encrypt(&self, data: Vec<u8>) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    pub fn encrypt(&self, mut data: Vec<u8>) -> Result<Vec<u8>, ErrorStack> {
         match self {
             Crypto::Clear() => Ok(data),
             Crypto::AES { key, iv } => {
-                trace!("Encrypting {} bytes using AES 128-bit CBC", data.len());
-                Ok(encrypt(Cipher::aes_128_cbc(), key, Some(iv), &data)?)
+                // only whole blocks are encrypted, the trailing bytes are sent in clear without padding
+                let size = data.len() - data.len() % AES_BLOCK_SIZE;
+                trace!("Encrypting {} of {} bytes using AES 128-bit CBC", size, data.len());
+
+                // every packet starts a new chain from the announced IV
+                let mut crypter = Crypter::new(Cipher::aes_128_cbc(), Mode::Encrypt, key, Some(iv))?;
+                crypter.pad(false);
+
+                let mut encrypted = vec![0u8; size + AES_BLOCK_SIZE];
+                let count = crypter.update(&data[0..size], &mut encrypted)?;
+                crypter.finalize(&mut encrypted[count..])?;
+
+                data[0..size].copy_from_slice(&encrypted[0..size]);
+
+                Ok(data)
             },
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use openssl::rsa::{Padding, Rsa};
+    use openssl::symm::{Cipher, Crypter, Mode};
+
+    use crate::codec;
+    use crate::raop_client::MAX_SAMPLES_PER_CHUNK;
+    use crate::sample_rate::SampleRate;
+
+    fn decrypt(crypto: &super::Crypto, mut data: Vec<u8>) -> Vec<u8> {
+        let (key, iv) = match crypto {
+            super::Crypto::AES { key, iv } => (key, iv),
+            super::Crypto::Clear() => return data,
+        };
+
+        let size = data.len() - data.len() % super::AES_BLOCK_SIZE;
+        let mut crypter = Crypter::new(Cipher::aes_128_cbc(), Mode::Decrypt, key, Some(iv)).unwrap();
+        crypter.pad(false);
+
+        let mut decrypted = vec![0u8; size + super::AES_BLOCK_SIZE];
+        let count = crypter.update(&data[0..size], &mut decrypted).unwrap();
+        assert_eq!(crypter.finalize(&mut decrypted[count..]).unwrap(), 0);
+
+        data[0..size].copy_from_slice(&decrypted[0..size]);
+        data
+    }
+
+    fn samples() -> Vec<u8> {
+        (0..MAX_SAMPLES_PER_CHUNK.as_usize(4)).map(|i| ((i * 7919) % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_encrypt_keeps_trailing_bytes_in_clear() {
+        let crypto = super::Crypto::new(true);
+        let data = (0..37u8).collect::<Vec<u8>>();
+
+        let encrypted = crypto.encrypt(data.clone()).unwrap();
+
+        assert_eq!(encrypted.len(), data.len());
+        assert_ne!(&encrypted[0..32], &data[0..32]);
+        assert_eq!(&encrypted[32..], &data[32..]);
+        assert_eq!(decrypt(&crypto, encrypted), data);
+    }
+
+    #[test]
+    fn test_encrypt_restarts_chain_every_packet() {
+        let crypto = super::Crypto::new(true);
+        let data = vec![42u8; 64];
+
+        assert_eq!(crypto.encrypt(data.clone()).unwrap(), crypto.encrypt(data).unwrap());
+    }
+
+    #[test]
+    fn test_round_trip_pcm() {
+        let crypto = super::Crypto::new(true);
+        let mut codec = codec::new_encoder(false, MAX_SAMPLES_PER_CHUNK, SampleRate::Hz44100, 16, 2).unwrap();
+
+        let encoded = codec.encode_chunk(&samples());
+        let encrypted = crypto.encrypt(encoded.clone()).unwrap();
+
+        assert_eq!(encrypted.len(), encoded.len());
+        assert_eq!(decrypt(&crypto, encrypted), encoded);
+    }
+
+    #[test]
+    fn test_round_trip_alac() {
+        let crypto = super::Crypto::new(true);
+        let mut codec = codec::new_encoder(true, MAX_SAMPLES_PER_CHUNK, SampleRate::Hz44100, 16, 2).unwrap();
+
+        let encoded = codec.encode_chunk(&samples());
+        let encrypted = crypto.encrypt(encoded.clone()).unwrap();
+
+        assert_eq!(encrypted.len(), encoded.len());
+        assert_eq!(decrypt(&crypto, encrypted), encoded);
+    }
+
+    fn sign_challenge(key: &Rsa<openssl::pkey::Private>, message: &[u8]) -> String {
+        let mut signature = vec![0u8; key.size() as usize];
+        let size = key.private_encrypt(message, &mut signature, Padding::PKCS1).unwrap();
+        base64::encode_config(&signature[0..size], base64::STANDARD_NO_PAD)
+    }
+
+    #[test]
+    fn test_verify_apple_response() {
+        let key = Rsa::generate(1024).unwrap();
+        let challenge = super::AppleChallenge::new();
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 42));
+        let mac = [0x00, 0x50, 0xC2, 0x12, 0xA2, 0x3F];
+
+        let mut message = challenge.0.to_vec();
+        message.extend_from_slice(&[192, 168, 1, 42]);
+        message.extend_from_slice(&mac);
+        message.resize(32, 0);
+
+        let response = sign_challenge(&key, &message);
+
+        assert!(challenge.verify_with_key(&key, Some(&response), ip, Some(mac)).is_ok());
+
+        match challenge.verify_with_key(&key, Some(&response), ip, None) {
+            Err(super::AppleResponseError::UnknownMacAddress) => {},
+            result => panic!("expected the MAC address to be required, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_reject_apple_response() {
+        let key = Rsa::generate(1024).unwrap();
+        let challenge = super::AppleChallenge::new();
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 42));
+        let mac = [0x00, 0x50, 0xC2, 0x12, 0xA2, 0x3F];
+
+        let mut message = challenge.0.to_vec();
+        message.extend_from_slice(&[192, 168, 1, 43]);
+        message.extend_from_slice(&mac);
+        message.resize(32, 0);
+
+        let response = sign_challenge(&key, &message);
+
+        match challenge.verify_with_key(&key, Some(&response), ip, Some(mac)) {
+            Err(super::AppleResponseError::Mismatch) => {},
+            result => panic!("expected a mismatch, got {:?}", result),
+        }
+
+        match challenge.verify(Some(&response), ip, Some(mac)) {
+            Err(super::AppleResponseError::InvalidSignature(_)) => {},
+            result => panic!("expected an invalid signature, got {:?}", result),
+        }
+
+        match challenge.verify(None, ip, Some(mac)) {
+            Err(super::AppleResponseError::MissingResponse) => {},
+            result => panic!("expected a missing response, got {:?}", result),
+        }
+    }
+}
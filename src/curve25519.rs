@@ -1,3 +1,8 @@
+use std::error::Error;
+use std::fmt::{self, Formatter, Display};
+
+use openssl::error::ErrorStack;
+
 const EVP_PKEY_X25519: i32 = 1034;
 const EVP_PKEY_ED25519: i32 = 1087;
 
@@ -35,77 +40,121 @@ extern "C" {
     fn EVP_PKEY_new_raw_public_key(type_: ::std::os::raw::c_int, e: *mut ENGINE, pub_: *const ::std::os::raw::c_uchar, len: usize) -> *mut EVP_PKEY;
 }
 
-pub fn create_key_pair(secret: &[u8]) -> ([u8; PRIVATE_KEY_SIZE], [u8; PUBLIC_KEY_SIZE]) {
-    let key = unsafe { EVP_PKEY_new_raw_private_key(EVP_PKEY_ED25519, std::ptr::null_mut(), secret.as_ptr() as *mut u8, SECRET_KEY_SIZE) };
+#[derive(Debug)]
+pub enum Curve25519Error {
+    OpenSslError(ErrorStack),
+    // openssl handed back a key or signature of another size than the curve uses
+    UnexpectedSize { expected: usize, actual: usize },
+}
+
+impl Display for Curve25519Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Curve25519Error::OpenSslError(source) => write!(f, "curve25519 operation failed: {}", source),
+            Curve25519Error::UnexpectedSize { expected, actual } => write!(f, "curve25519 operation returned {} bytes, expected {}", actual, expected),
+        }
+    }
+}
+
+impl Error for Curve25519Error {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Curve25519Error::OpenSslError(source) => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<ErrorStack> for Curve25519Error {
+    fn from(error: ErrorStack) -> Self {
+        Curve25519Error::OpenSslError(error)
+    }
+}
+
+fn check(status: ::std::os::raw::c_int) -> Result<(), Curve25519Error> {
+    if status == 1 { Ok(()) } else { Err(ErrorStack::get().into()) }
+}
+
+fn check_size(actual: usize, expected: usize) -> Result<(), Curve25519Error> {
+    if actual == expected { Ok(()) } else { Err(Curve25519Error::UnexpectedSize { expected, actual }) }
+}
+
+fn check_key(key: *mut EVP_PKEY) -> Result<*mut EVP_PKEY, Curve25519Error> {
+    if key.is_null() { Err(ErrorStack::get().into()) } else { Ok(key) }
+}
+
+pub fn create_key_pair(secret: &[u8; SECRET_KEY_SIZE]) -> Result<([u8; PRIVATE_KEY_SIZE], [u8; PUBLIC_KEY_SIZE]), Curve25519Error> {
+    let key = check_key(unsafe { EVP_PKEY_new_raw_private_key(EVP_PKEY_ED25519, std::ptr::null_mut(), secret.as_ptr() as *mut u8, SECRET_KEY_SIZE) })?;
 
     let mut size = PRIVATE_KEY_SIZE;
     let mut private = [0u8; PRIVATE_KEY_SIZE];
 
-    unsafe { EVP_PKEY_get_raw_private_key(key, private.as_mut_ptr(), &mut size); }
-    assert_eq!(size, SECRET_KEY_SIZE);
+    check(unsafe { EVP_PKEY_get_raw_private_key(key, private.as_mut_ptr(), &mut size) })?;
+    check_size(size, SECRET_KEY_SIZE)?;
 
-    unsafe { EVP_PKEY_get_raw_public_key(key, private.as_mut_ptr().add(SECRET_KEY_SIZE), &mut size); }
-    assert_eq!(size, PUBLIC_KEY_SIZE);
+    check(unsafe { EVP_PKEY_get_raw_public_key(key, private.as_mut_ptr().add(SECRET_KEY_SIZE), &mut size) })?;
+    check_size(size, PUBLIC_KEY_SIZE)?;
 
     let mut size = PUBLIC_KEY_SIZE;
     let mut public = [0u8; PUBLIC_KEY_SIZE];
 
-    unsafe { EVP_PKEY_get_raw_public_key(key, public.as_mut_ptr(), &mut size); }
-    assert_eq!(size, PUBLIC_KEY_SIZE);
+    check(unsafe { EVP_PKEY_get_raw_public_key(key, public.as_mut_ptr(), &mut size) })?;
+    check_size(size, PUBLIC_KEY_SIZE)?;
 
-    (private, public)
+    Ok((private, public))
 }
 
-pub fn calculate_public_key(secret: &[u8]) -> [u8; PUBLIC_KEY_SIZE] {
-    let key = unsafe { EVP_PKEY_new_raw_private_key(EVP_PKEY_X25519, std::ptr::null_mut(), secret.as_ptr() as *mut u8, SECRET_KEY_SIZE) };
+pub fn calculate_public_key(secret: &[u8; SECRET_KEY_SIZE]) -> Result<[u8; PUBLIC_KEY_SIZE], Curve25519Error> {
+    let key = check_key(unsafe { EVP_PKEY_new_raw_private_key(EVP_PKEY_X25519, std::ptr::null_mut(), secret.as_ptr() as *mut u8, SECRET_KEY_SIZE) })?;
 
     let mut size = PUBLIC_KEY_SIZE;
     let mut public = [0u8; PUBLIC_KEY_SIZE];
 
-    let status = unsafe { EVP_PKEY_get_raw_public_key(key, public.as_mut_ptr(), &mut size) };
-    assert_eq!(status, 1);
-    assert_eq!(size, PUBLIC_KEY_SIZE);
+    check(unsafe { EVP_PKEY_get_raw_public_key(key, public.as_mut_ptr(), &mut size) })?;
+    check_size(size, PUBLIC_KEY_SIZE)?;
 
-    public
+    Ok(public)
 }
 
-pub fn create_shared_key(peer_public: &[u8], secret: &[u8]) -> [u8; SECRET_KEY_SIZE] {
-    let key = unsafe { EVP_PKEY_new_raw_private_key(EVP_PKEY_X25519, std::ptr::null_mut(), secret.as_ptr() as *mut u8, SECRET_KEY_SIZE) };
-    let peer_key = unsafe { EVP_PKEY_new_raw_public_key(EVP_PKEY_X25519, std::ptr::null_mut(), peer_public.as_ptr() as *mut u8, PUBLIC_KEY_SIZE) };
+// the peer key comes from the receiver, so it may well be invalid
+pub fn create_shared_key(peer_public: &[u8; PUBLIC_KEY_SIZE], secret: &[u8; SECRET_KEY_SIZE]) -> Result<[u8; SECRET_KEY_SIZE], Curve25519Error> {
+    let key = check_key(unsafe { EVP_PKEY_new_raw_private_key(EVP_PKEY_X25519, std::ptr::null_mut(), secret.as_ptr() as *mut u8, SECRET_KEY_SIZE) })?;
+    let peer_key = check_key(unsafe { EVP_PKEY_new_raw_public_key(EVP_PKEY_X25519, std::ptr::null_mut(), peer_public.as_ptr() as *mut u8, PUBLIC_KEY_SIZE) })?;
 
     let ctx = unsafe { EVP_PKEY_CTX_new(key, std::ptr::null_mut()) };
 
-    let status = unsafe { EVP_PKEY_derive_init(ctx) };
-    assert_eq!(status, 1);
+    if ctx.is_null() {
+        return Err(ErrorStack::get().into());
+    }
 
-    let status = unsafe { EVP_PKEY_derive_set_peer(ctx, peer_key) };
-    assert_eq!(status, 1);
+    check(unsafe { EVP_PKEY_derive_init(ctx) })?;
+    check(unsafe { EVP_PKEY_derive_set_peer(ctx, peer_key) })?;
 
     let mut size = SECRET_KEY_SIZE;
     let mut result = [0u8; SECRET_KEY_SIZE];
-    let status = unsafe { EVP_PKEY_derive(ctx, result.as_mut_ptr(), &mut size) };
-    println!("{}", size);
-    assert_eq!(status, 1);
-    assert_eq!(size, SECRET_KEY_SIZE);
+    check(unsafe { EVP_PKEY_derive(ctx, result.as_mut_ptr(), &mut size) })?;
+    check_size(size, SECRET_KEY_SIZE)?;
 
-    result
+    Ok(result)
 }
 
-pub fn sign_message(private_key: &[u8], message: &[u8]) -> [u8; SIGNATURE_SIZE] {
+pub fn sign_message(secret: &[u8; SECRET_KEY_SIZE], message: &[u8]) -> Result<[u8; SIGNATURE_SIZE], Curve25519Error> {
     let ctx = unsafe { EVP_MD_CTX_new() };
-    let key = unsafe { EVP_PKEY_new_raw_private_key(EVP_PKEY_ED25519, std::ptr::null_mut(), private_key.as_ptr() as *mut u8, SECRET_KEY_SIZE) };
+    let key = check_key(unsafe { EVP_PKEY_new_raw_private_key(EVP_PKEY_ED25519, std::ptr::null_mut(), secret.as_ptr() as *mut u8, SECRET_KEY_SIZE) })?;
+
+    if ctx.is_null() {
+        return Err(ErrorStack::get().into());
+    }
 
-    let status = unsafe { EVP_DigestSignInit(ctx, std::ptr::null_mut(), std::ptr::null(), std::ptr::null_mut(), key) };
-    assert_eq!(status, 1);
+    check(unsafe { EVP_DigestSignInit(ctx, std::ptr::null_mut(), std::ptr::null(), std::ptr::null_mut(), key) })?;
 
     let mut signature = [0u8; SIGNATURE_SIZE];
     let mut signature_length = SIGNATURE_SIZE;
 
-    let status = unsafe { EVP_DigestSign(ctx, signature.as_mut_ptr(), &mut signature_length, message.as_ptr(), message.len()) };
-    assert_eq!(status, 1);
-    assert_eq!(signature_length, SIGNATURE_SIZE);
+    check(unsafe { EVP_DigestSign(ctx, signature.as_mut_ptr(), &mut signature_length, message.as_ptr(), message.len()) })?;
+    check_size(signature_length, SIGNATURE_SIZE)?;
 
-    signature
+    Ok(signature)
 }
 
 #[cfg(test)]
@@ -119,7 +168,7 @@ mod test {
         let expected_public = <[u8; super::PUBLIC_KEY_SIZE]>::from_hex("03A107BFF3CE10BE1D70DD18E74BC09967E4D6309BA50D5F1DDC8664125531B8").unwrap();
         let expected_private = <[u8; super::PRIVATE_KEY_SIZE]>::from_hex("000102030405060708090A0B0C0D0E0F101112131415161718191A1B1C1D1E1F03A107BFF3CE10BE1D70DD18E74BC09967E4D6309BA50D5F1DDC8664125531B8").unwrap();
 
-        let (private, public) = super::create_key_pair(&secret);
+        let (private, public) = super::create_key_pair(&secret).unwrap();
 
         assert_eq!(&public[..], &expected_public[..]);
         assert_eq!(&private[..], &expected_private[..]);
@@ -130,7 +179,7 @@ mod test {
         let secret = <[u8; super::SECRET_KEY_SIZE]>::from_hex("000102030405060708090A0B0C0D0E0F101112131415161718191A1B1C1D1E1F").unwrap();
 
         let expected = <[u8; super::PUBLIC_KEY_SIZE]>::from_hex("8F40C5ADB68F25624AE5B214EA767A6EC94D829D3D7B5E1AD1BA6F3E2138285F").unwrap();
-        let actual = super::calculate_public_key(&secret);
+        let actual = super::calculate_public_key(&secret).unwrap();
 
         assert_eq!(&actual[..], &expected[..]);
     }
@@ -141,7 +190,7 @@ mod test {
         let peer = <[u8; super::PUBLIC_KEY_SIZE]>::from_hex("202122232425262728292A2B2C2D2E2F303132333435363738393A3B3C3D3E3F").unwrap();
 
         let expected = <[u8; super::SECRET_KEY_SIZE]>::from_hex("6C2384F2C0F13A8FF3CEEE55075540778CD9F94383178837AE24F9F419C12D7B").unwrap();
-        let actual = super::create_shared_key(&peer, &secret);
+        let actual = super::create_shared_key(&peer, &secret).unwrap();
 
         assert_eq!(&actual[..], &expected[..]);
     }
@@ -152,7 +201,7 @@ mod test {
         let message = <[u8; 32]>::from_hex("404142434445464748494A4B4C4D4E4F505152535455565758595A5B5C5D5E5F").unwrap();
 
         let expected = <[u8; super::SIGNATURE_SIZE]>::from_hex("D64F2DD10819B97847765606B736F4430738894241682CDD8834BCBF6C72505272A8C289075E32178FD3F86AED9755EDB2AF92C76DFC3C5DD2B9F256360D080C").unwrap();
-        let actual = super::sign_message(&secret, &message);
+        let actual = super::sign_message(&secret, &message).unwrap();
 
         assert_eq!(&actual[..], &expected[..]);
     }
@@ -0,0 +1,12 @@
+use std::future::Future;
+use std::time::Duration;
+
+use tokio::time::timeout;
+
+// resolves to None when the future is not done within the duration, without a duration it never times out
+pub async fn within<F: Future>(duration: Option<Duration>, future: F) -> Option<F::Output> {
+    match duration {
+        Some(duration) => timeout(duration, future).await.ok(),
+        None => Some(future.await),
+    }
+}
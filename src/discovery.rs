@@ -0,0 +1,369 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use byteorder::{BE, ByteOrder, WriteBytesExt};
+use hex::FromHex;
+use log::{debug, trace};
+use tokio::net::UdpSocket;
+use tokio::time::{timeout, Instant};
+
+pub const RAOP_SERVICE: &str = "_raop._tcp.local";
+
+const MDNS_ADDR: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+
+const CLASS_IN: u16 = 1;
+const CLASS_UNICAST_RESPONSE: u16 = 0x8000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RaopReceiver {
+    pub name: String,
+    pub addresses: Vec<IpAddr>,
+    pub port: u16,
+    pub txt: HashMap<String, String>,
+}
+
+impl RaopReceiver {
+    pub fn mac_address(&self) -> Option<[u8; 6]> {
+        // instance names look like "0050C212A23F@Living Room"
+        let hex = self.name.split('@').next().filter(|_| self.name.contains('@'))?;
+        <[u8; 6]>::from_hex(hex).ok()
+    }
+}
+
+#[derive(Debug)]
+enum Record {
+    Ptr { name: String, target: String },
+    Srv { name: String, port: u16, target: String },
+    Txt { name: String, entries: HashMap<String, String> },
+    Address { name: String, address: IpAddr },
+}
+
+#[derive(Default)]
+struct Answers {
+    instances: Vec<String>,
+    services: HashMap<String, (u16, String)>,
+    txt: HashMap<String, HashMap<String, String>>,
+    addresses: HashMap<String, Vec<IpAddr>>,
+}
+
+impl Answers {
+    fn add(&mut self, record: Record) {
+        match record {
+            Record::Ptr { name, target } => {
+                if name.eq_ignore_ascii_case(RAOP_SERVICE) && !self.instances.contains(&target.to_lowercase()) {
+                    self.instances.push(target.to_lowercase());
+                }
+            }
+            Record::Srv { name, port, target } => { self.services.insert(name.to_lowercase(), (port, target.to_lowercase())); }
+            Record::Txt { name, entries } => { self.txt.insert(name.to_lowercase(), entries); }
+            Record::Address { name, address } => {
+                let addresses = self.addresses.entry(name.to_lowercase()).or_default();
+                if !addresses.contains(&address) { addresses.push(address); }
+            }
+        }
+    }
+
+    fn into_receivers(mut self, names: HashMap<String, String>) -> Vec<RaopReceiver> {
+        let suffix = format!(".{}", RAOP_SERVICE);
+        let mut receivers = Vec::new();
+
+        for instance in self.instances {
+            let (port, target) = match self.services.get(&instance) {
+                Some(service) => service.clone(),
+                None => { debug!("no SRV record for {}, skipping", instance); continue; }
+            };
+
+            let name = names.get(&instance).map(String::as_str).unwrap_or(&instance);
+            let name = if name.to_lowercase().ends_with(&suffix) { &name[..name.len() - suffix.len()] } else { name };
+
+            receivers.push(RaopReceiver {
+                name: name.to_owned(),
+                addresses: self.addresses.get(&target).cloned().unwrap_or_default(),
+                port,
+                txt: self.txt.remove(&instance).unwrap_or_default(),
+            });
+        }
+
+        receivers
+    }
+}
+
+fn write_name(writer: &mut dyn Write, name: &str) -> io::Result<()> {
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        writer.write_u8(label.len() as u8)?;
+        writer.write_all(label.as_bytes())?;
+    }
+
+    writer.write_u8(0)
+}
+
+fn build_query(service: &str) -> Vec<u8> {
+    let mut query = Vec::with_capacity(12 + service.len() + 6);
+
+    // id, flags, one question, no answer, authority or additional records
+    query.extend_from_slice(&[0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+    write_name(&mut query, service).unwrap();
+    query.write_u16::<BE>(TYPE_PTR).unwrap();
+    query.write_u16::<BE>(CLASS_IN | CLASS_UNICAST_RESPONSE).unwrap();
+
+    query
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_name(packet: &[u8], mut offset: usize) -> io::Result<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    let mut jumps = 0;
+
+    loop {
+        let len = *packet.get(offset).ok_or_else(|| invalid_data("name out of bounds"))? as usize;
+
+        match len {
+            0 => {
+                offset += 1;
+                break;
+            }
+            0xC0..=0xFF => {
+                let pointer = packet.get(offset..offset + 2).ok_or_else(|| invalid_data("pointer out of bounds"))?;
+                if end.is_none() { end = Some(offset + 2); }
+
+                jumps += 1;
+                if jumps > 16 { return Err(invalid_data("too many name pointers")); }
+
+                offset = (BE::read_u16(pointer) & 0x3FFF) as usize;
+            }
+            0x40..=0xBF => return Err(invalid_data("unsupported label type")),
+            _ => {
+                let label = packet.get(offset + 1..offset + 1 + len).ok_or_else(|| invalid_data("label out of bounds"))?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                offset += 1 + len;
+            }
+        }
+    }
+
+    Ok((labels.join("."), end.unwrap_or(offset)))
+}
+
+fn parse_txt(data: &[u8]) -> HashMap<String, String> {
+    let mut entries = HashMap::new();
+    let mut offset = 0;
+
+    while offset < data.len() {
+        let len = data[offset] as usize;
+        let entry = &data[(offset + 1).min(data.len())..(offset + 1 + len).min(data.len())];
+        let entry = String::from_utf8_lossy(entry);
+
+        let mut parts = entry.splitn(2, '=');
+        if let Some(key) = parts.next().filter(|key| !key.is_empty()) {
+            entries.insert(key.to_lowercase(), parts.next().unwrap_or("").to_owned());
+        }
+
+        offset += 1 + len;
+    }
+
+    entries
+}
+
+// Parses all resource records of a response, keeping the case of the instance names around
+fn parse_response(packet: &[u8], names: &mut HashMap<String, String>) -> io::Result<Vec<Record>> {
+    let header = packet.get(0..12).ok_or_else(|| invalid_data("packet shorter than header"))?;
+
+    if BE::read_u16(&header[2..4]) & 0x8000 == 0 {
+        return Ok(Vec::new());
+    }
+
+    let questions = BE::read_u16(&header[4..6]);
+    let records = BE::read_u16(&header[6..8]) as usize + BE::read_u16(&header[8..10]) as usize + BE::read_u16(&header[10..12]) as usize;
+
+    let mut offset = 12;
+
+    for _ in 0..questions {
+        let (_, end) = read_name(packet, offset)?;
+        offset = end + 4;
+    }
+
+    let mut result = Vec::with_capacity(records);
+
+    for _ in 0..records {
+        let (name, end) = read_name(packet, offset)?;
+        let fixed = packet.get(end..end + 10).ok_or_else(|| invalid_data("record out of bounds"))?;
+
+        let type_ = BE::read_u16(&fixed[0..2]);
+        let rdlength = BE::read_u16(&fixed[8..10]) as usize;
+        let rdata_offset = end + 10;
+        let rdata = packet.get(rdata_offset..rdata_offset + rdlength).ok_or_else(|| invalid_data("record data out of bounds"))?;
+
+        offset = rdata_offset + rdlength;
+
+        let record = match type_ {
+            TYPE_PTR => {
+                let (target, _) = read_name(packet, rdata_offset)?;
+                names.insert(target.to_lowercase(), target.clone());
+                Record::Ptr { name, target }
+            }
+            TYPE_SRV if rdlength > 6 => {
+                let (target, _) = read_name(packet, rdata_offset + 6)?;
+                Record::Srv { name, port: BE::read_u16(&rdata[4..6]), target }
+            }
+            TYPE_TXT => Record::Txt { name, entries: parse_txt(rdata) },
+            TYPE_A if rdlength == 4 => {
+                Record::Address { name, address: IpAddr::V4(Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3])) }
+            }
+            TYPE_AAAA if rdlength == 16 => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(rdata);
+                Record::Address { name, address: IpAddr::V6(Ipv6Addr::from(octets)) }
+            }
+            _ => continue,
+        };
+
+        trace!("mDNS record {:?}", record);
+        result.push(record);
+    }
+
+    Ok(result)
+}
+
+pub async fn browse(wait: Duration) -> io::Result<Vec<RaopReceiver>> {
+    browse_at(SocketAddr::new(IpAddr::V4(MDNS_ADDR), MDNS_PORT), wait).await
+}
+
+pub async fn browse_at(responder: SocketAddr, wait: Duration) -> io::Result<Vec<RaopReceiver>> {
+    // sending from an ephemeral port makes responders answer with unicast (legacy unicast)
+    let local: SocketAddr = match responder {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+
+    let mut socket = UdpSocket::bind(local).await?;
+    socket.send_to(&build_query(RAOP_SERVICE), &responder).await?;
+
+    debug!("browsing for {} on {}", RAOP_SERVICE, responder);
+
+    let deadline = Instant::now() + wait;
+    let mut answers = Answers::default();
+    let mut names = HashMap::new();
+    let mut buffer = [0u8; 9000];
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+
+        let (n, from) = match timeout(remaining, socket.recv_from(&mut buffer)).await {
+            Ok(result) => result?,
+            Err(_) => break,
+        };
+
+        match parse_response(&buffer[0..n], &mut names) {
+            Ok(records) => for record in records { answers.add(record) },
+            Err(err) => debug!("ignoring malformed mDNS response from {}: {}", from, err),
+        }
+    }
+
+    Ok(answers.into_receivers(names))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::io::Write;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::Duration;
+
+    use byteorder::{BE, WriteBytesExt};
+    use tokio::net::UdpSocket;
+
+    fn write_record(packet: &mut Vec<u8>, name: &[u8], type_: u16, rdata: &[u8]) {
+        packet.write_all(name).unwrap();
+        packet.write_u16::<BE>(type_).unwrap();
+        packet.write_u16::<BE>(super::CLASS_IN).unwrap();
+        packet.write_u32::<BE>(120).unwrap();
+        packet.write_u16::<BE>(rdata.len() as u16).unwrap();
+        packet.write_all(rdata).unwrap();
+    }
+
+    fn build_response() -> Vec<u8> {
+        let mut packet = vec![0, 0, 0x84, 0, 0, 0, 0, 1, 0, 0, 0, 3];
+
+        // PTR answer, the service name is at offset 12 for the compression pointers below
+        let mut instance = Vec::new();
+        instance.write_u8(19).unwrap();
+        instance.write_all(b"0050C212A23F@Office").unwrap();
+        instance.write_all(&[0xC0, 12]).unwrap();
+
+        let mut service = Vec::new();
+        super::write_name(&mut service, super::RAOP_SERVICE).unwrap();
+        write_record(&mut packet, &service, super::TYPE_PTR, &instance);
+
+        // the instance name inside the PTR rdata starts at 12 + 18 + 10
+        let instance_pointer = [0xC0, 40];
+
+        let mut srv = vec![0, 0, 0, 0, 0x1B, 0x58];
+        super::write_name(&mut srv, "office.local").unwrap();
+        write_record(&mut packet, &instance_pointer, super::TYPE_SRV, &srv);
+
+        let mut txt = Vec::new();
+        for entry in &["txtvers=1", "cn=0,1", "et=0,1", "md=0,1,2", "am=AirPort4,107"] {
+            txt.write_u8(entry.len() as u8).unwrap();
+            txt.write_all(entry.as_bytes()).unwrap();
+        }
+        write_record(&mut packet, &instance_pointer, super::TYPE_TXT, &txt);
+
+        let mut host = Vec::new();
+        super::write_name(&mut host, "office.local").unwrap();
+        write_record(&mut packet, &host, super::TYPE_A, &[192, 168, 1, 42]);
+
+        packet
+    }
+
+    #[test]
+    fn test_read_compressed_name() {
+        let packet = build_response();
+        let (name, end) = super::read_name(&packet, 40).unwrap();
+
+        assert_eq!(name, "0050C212A23F@Office._raop._tcp.local");
+        assert_eq!(end, 40 + 1 + 19 + 2);
+    }
+
+    #[tokio::test]
+    async fn test_browse_local_responder() {
+        let mut responder = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let address = responder.local_addr().unwrap();
+
+        let serve = async move {
+            let mut buffer = [0u8; 512];
+            let (n, from) = responder.recv_from(&mut buffer).await.unwrap();
+            assert_eq!(&buffer[0..n], &super::build_query(super::RAOP_SERVICE)[..]);
+            responder.send_to(&build_response(), &from).await.unwrap();
+        };
+
+        let (receivers, _) = futures::join!(super::browse_at(address, Duration::from_millis(200)), serve);
+        let receivers = receivers.unwrap();
+
+        let mut txt = HashMap::new();
+        txt.insert(String::from("txtvers"), String::from("1"));
+        txt.insert(String::from("cn"), String::from("0,1"));
+        txt.insert(String::from("et"), String::from("0,1"));
+        txt.insert(String::from("md"), String::from("0,1,2"));
+        txt.insert(String::from("am"), String::from("AirPort4,107"));
+
+        assert_eq!(receivers[0].mac_address(), Some([0x00, 0x50, 0xC2, 0x12, 0xA2, 0x3F]));
+        assert_eq!(receivers, vec![super::RaopReceiver {
+            name: String::from("0050C212A23F@Office"),
+            addresses: vec![IpAddr::V4(Ipv4Addr::new(192, 168, 1, 42))],
+            port: 7000,
+            txt,
+        }]);
+    }
+}
@@ -6,14 +6,9 @@ use std::time::Duration;
 
 use crate::sample_rate::SampleRate;
 
-#[derive(Clone, Copy, PartialOrd, PartialEq, Ord, Eq)]
+#[derive(Debug, Clone, Copy, PartialOrd, PartialEq, Ord, Eq)]
 pub struct Frames(u64);
 
-fn scale(value: u64, max: u64) -> u32 {
-    assert!(max < (std::u32::MAX as u64));
-    (value * (std::u32::MAX as u64) / max) as u32
-}
-
 impl Frames {
     pub const fn new(value: u64) -> Frames {
         Frames(value)
@@ -26,6 +21,10 @@ impl Frames {
     pub const fn from_usize(size: usize, frame_size: usize) -> Frames {
         Frames((size / frame_size) as u64)
     }
+
+    pub fn from_duration(duration: Duration, sample_rate: SampleRate) -> Frames {
+        Frames((duration.as_nanos() * u128::from(u64::from(sample_rate)) / 1_000_000_000) as u64)
+    }
 }
 
 impl From<Frames> for u64 {
@@ -60,7 +59,7 @@ impl Div<SampleRate> for Frames {
     #[allow(clippy::suspicious_arithmetic_impl)]
     fn div(self, other: SampleRate) -> Duration {
         let sample_rate = u64::from(other);
-        Duration::new(self.0 / sample_rate, scale(self.0 % sample_rate, sample_rate - 1))
+        Duration::new(self.0 / sample_rate, ((self.0 % sample_rate) * 1_000_000_000 / sample_rate) as u32)
     }
 }
 
@@ -85,3 +84,18 @@ impl Display for Frames {
         write!(f, "{}", self.0)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::Frames;
+    use crate::sample_rate::SampleRate;
+
+    #[test]
+    fn test_div_sample_rate() {
+        assert_eq!(Frames::new(88200) / SampleRate::Hz44100, Duration::from_secs(2));
+        assert_eq!(Frames::new(66150) / SampleRate::Hz44100, Duration::from_millis(1500));
+        assert_eq!(Frames::new(352) / SampleRate::Hz44100, Duration::from_nanos(7_981_859));
+    }
+}
@@ -0,0 +1,275 @@
+use std::f64::consts::FRAC_1_SQRT_2;
+
+use crate::audio_format::AudioFormat;
+
+// speaker positions, as the bits of the WAVE dwChannelMask
+const FRONT_LEFT: u32 = 0x1;
+const FRONT_RIGHT: u32 = 0x2;
+const FRONT_CENTER: u32 = 0x4;
+const LOW_FREQUENCY: u32 = 0x8;
+const BACK_LEFT: u32 = 0x10;
+const BACK_RIGHT: u32 = 0x20;
+const FRONT_LEFT_OF_CENTER: u32 = 0x40;
+const FRONT_RIGHT_OF_CENTER: u32 = 0x80;
+const BACK_CENTER: u32 = 0x100;
+const SIDE_LEFT: u32 = 0x200;
+const SIDE_RIGHT: u32 = 0x400;
+const TOP_CENTER: u32 = 0x800;
+const TOP_FRONT_LEFT: u32 = 0x1000;
+const TOP_FRONT_CENTER: u32 = 0x2000;
+const TOP_FRONT_RIGHT: u32 = 0x4000;
+const TOP_BACK_LEFT: u32 = 0x8000;
+const TOP_BACK_CENTER: u32 = 0x10000;
+const TOP_BACK_RIGHT: u32 = 0x20000;
+
+// the FLAC channel assignments, which WAVE files without a channel mask are taken to follow as well
+fn default_channel_mask(channels: usize) -> u32 {
+    match channels {
+        1 => FRONT_CENTER,
+        2 => FRONT_LEFT | FRONT_RIGHT,
+        3 => FRONT_LEFT | FRONT_RIGHT | FRONT_CENTER,
+        4 => FRONT_LEFT | FRONT_RIGHT | BACK_LEFT | BACK_RIGHT,
+        5 => FRONT_LEFT | FRONT_RIGHT | FRONT_CENTER | BACK_LEFT | BACK_RIGHT,
+        6 => FRONT_LEFT | FRONT_RIGHT | FRONT_CENTER | LOW_FREQUENCY | BACK_LEFT | BACK_RIGHT,
+        7 => FRONT_LEFT | FRONT_RIGHT | FRONT_CENTER | LOW_FREQUENCY | BACK_CENTER | SIDE_LEFT | SIDE_RIGHT,
+        8 => FRONT_LEFT | FRONT_RIGHT | FRONT_CENTER | LOW_FREQUENCY | BACK_LEFT | BACK_RIGHT | SIDE_LEFT | SIDE_RIGHT,
+        _ => 0,
+    }
+}
+
+fn speaker_weights(speaker: u32) -> (f64, f64) {
+    match speaker {
+        FRONT_LEFT | FRONT_LEFT_OF_CENTER => (1.0, 0.0),
+        FRONT_RIGHT | FRONT_RIGHT_OF_CENTER => (0.0, 1.0),
+        FRONT_CENTER | TOP_FRONT_CENTER => (FRAC_1_SQRT_2, FRAC_1_SQRT_2),
+        BACK_LEFT | SIDE_LEFT | TOP_FRONT_LEFT | TOP_BACK_LEFT => (FRAC_1_SQRT_2, 0.0),
+        BACK_RIGHT | SIDE_RIGHT | TOP_FRONT_RIGHT | TOP_BACK_RIGHT => (0.0, FRAC_1_SQRT_2),
+        BACK_CENTER | TOP_CENTER | TOP_BACK_CENTER => (0.5, 0.5),
+        _ => (0.0, 0.0),
+    }
+}
+
+// weights of each input channel in the left and right output, the channels take the speakers of the mask in order
+fn downmix_weights(channels: usize, channel_mask: Option<u32>) -> Vec<(f64, f64)> {
+    let mask = channel_mask.unwrap_or_else(|| default_channel_mask(channels));
+    let mut speakers = (0..32).map(|bit| 1 << bit).filter(|speaker| mask & speaker != 0);
+
+    let mut weights = (0..channels).map(|channel| match speakers.next() {
+        Some(speaker) => speaker_weights(speaker),
+        // without a known position, channels go to the left and right in turn
+        None if channel % 2 == 0 => (FRAC_1_SQRT_2, 0.0),
+        None => (0.0, FRAC_1_SQRT_2),
+    }).collect::<Vec<_>>();
+
+    // all channels at full scale must not clip
+    let total = weights.iter().map(|(left, _)| left).sum::<f64>().max(weights.iter().map(|(_, right)| right).sum::<f64>());
+    weights.iter_mut().for_each(|(left, right)| { *left /= total; *right /= total; });
+
+    weights
+}
+
+// triangular dither of one output LSB, from a xorshift generator
+struct Dither {
+    state: u32,
+}
+
+impl Dither {
+    fn new() -> Dither {
+        Dither { state: 0x9E37_79B9 }
+    }
+
+    fn next(&mut self) -> i64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        i64::from(self.state)
+    }
+
+    fn sample(&mut self, lsb: u32) -> i64 {
+        (self.next() - self.next()) >> (32 - lsb)
+    }
+}
+
+// mixes interleaved samples to the output channels and quantizes them to the output sample format
+pub struct Converter {
+    input_channels: usize,
+    output: AudioFormat,
+    weights: Vec<(f64, f64)>,
+    // bits dropped from each sample, dithered when the input had more precision
+    lsb: u32,
+    dither: Option<Dither>,
+}
+
+impl Converter {
+    // without a channel mask, the channels are taken to be in the default order for their count
+    pub fn new(input_channels: u16, input_bits: u16, channel_mask: Option<u32>, output: AudioFormat) -> Converter {
+        let lsb = 32 - u32::from(output.bits_per_sample().min(32));
+        let dither = if !output.is_float() && input_bits > output.bits_per_sample() { Some(Dither::new()) } else { None };
+
+        Converter { input_channels: input_channels as usize, output, weights: downmix_weights(input_channels as usize, channel_mask), lsb, dither }
+    }
+
+    pub fn input_channels(&self) -> usize {
+        self.input_channels
+    }
+
+    fn downmix(&self, frame: &[i32]) -> (f64, f64) {
+        frame.iter().zip(&self.weights).fold((0.0, 0.0), |(left, right), (sample, weight)| {
+            (left + f64::from(*sample) * weight.0, right + f64::from(*sample) * weight.1)
+        })
+    }
+
+    fn quantize(&mut self, value: i64) -> i32 {
+        let value = match self.dither {
+            Some(ref mut dither) if self.lsb > 0 => {
+                let half = 1i64 << (self.lsb - 1);
+                (value + dither.sample(self.lsb) + half) & !((half << 1) - 1)
+            }
+            _ => value,
+        };
+
+        value.clamp(i64::from(i32::MIN), i64::from(i32::MAX) >> self.lsb << self.lsb) as i32
+    }
+
+    // converts whole frames, a trailing partial frame is ignored
+    pub fn convert(&mut self, samples: &[i32], output: &mut Vec<u8>) {
+        let output_channels = self.output.channels as usize;
+        let mut mixed = vec![0i64; output_channels];
+
+        for frame in samples.chunks_exact(self.input_channels) {
+            if self.input_channels == output_channels {
+                mixed.iter_mut().zip(frame).for_each(|(mixed, sample)| *mixed = i64::from(*sample));
+            } else if self.input_channels == 1 {
+                mixed.iter_mut().for_each(|mixed| *mixed = i64::from(frame[0]));
+            } else if output_channels == 1 {
+                let (left, right) = self.downmix(frame);
+                mixed[0] = ((left + right) / 2.0).round() as i64;
+            } else if output_channels == 2 {
+                let (left, right) = self.downmix(frame);
+                mixed[0] = left.round() as i64;
+                mixed[1] = right.round() as i64;
+            } else {
+                // without a known layout channels are mapped one to one
+                mixed.iter_mut().enumerate().for_each(|(channel, mixed)| *mixed = frame.get(channel).map_or(0, |sample| i64::from(*sample)));
+            }
+
+            for mixed in mixed.iter() {
+                let sample = self.quantize(*mixed);
+                self.output.write_sample(sample, output);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Converter;
+    use crate::audio_format::AudioFormat;
+
+    fn s16le(bytes: &[u8]) -> Vec<i16> {
+        bytes.chunks_exact(2).map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]])).collect()
+    }
+
+    #[test]
+    fn test_upmix_mono() {
+        let mut converter = Converter::new(1, 16, None, AudioFormat::from_name("s16le", 2).unwrap());
+        let mut output = Vec::new();
+        converter.convert(&[1 << 16, -2 << 16], &mut output);
+
+        assert_eq!(s16le(&output), vec![1, 1, -2, -2]);
+    }
+
+    #[test]
+    fn test_downmix_surround() {
+        let mut converter = Converter::new(6, 16, None, AudioFormat::from_name("s16le", 2).unwrap());
+        let mut output = Vec::new();
+
+        // full scale on every channel doesn't clip, a lone left channel stays left
+        converter.convert(&[i32::MAX; 6], &mut output);
+        converter.convert(&[1 << 30, 0, 0, 0, 0, 0], &mut output);
+        converter.convert(&[0, 0, 0, i32::MAX, 0, 0], &mut output);
+
+        let output = s16le(&output);
+        assert!(output[0] > 32000 && output[1] > 32000);
+        assert!(output[2] > 4000 && output[3] == 0);
+        assert_eq!(&output[4..6], &[0, 0]);
+    }
+
+    // the output for each channel alone, at half scale
+    fn downmix_each(channels: usize, channel_mask: Option<u32>, output_channels: u16) -> Vec<Vec<i16>> {
+        (0..channels).map(|channel| {
+            let mut converter = Converter::new(channels as u16, 16, channel_mask, AudioFormat::from_name("s16le", output_channels).unwrap());
+            let mut frame = vec![0; channels];
+            frame[channel] = 1 << 30;
+
+            let mut output = Vec::new();
+            converter.convert(&frame, &mut output);
+            s16le(&output)
+        }).collect()
+    }
+
+    #[test]
+    fn test_downmix_quad() {
+        // front left, front right, back left, back right
+        let output = downmix_each(4, None, 2);
+
+        assert!(output[0][0] > output[2][0] && output[2][0] > 0);
+        assert_eq!((output[0][1], output[2][1]), (0, 0));
+        assert!(output[1][1] > output[3][1] && output[3][1] > 0);
+        assert_eq!((output[1][0], output[3][0]), (0, 0));
+    }
+
+    #[test]
+    fn test_downmix_five_channels() {
+        // front left, front right, center, back left, back right
+        let output = downmix_each(5, None, 2);
+
+        assert!(output[2][0] > 0 && output[2][0] == output[2][1]);
+        assert!(output[3][0] > 0 && output[3][1] == 0);
+        assert!(output[4][1] > 0 && output[4][0] == 0);
+    }
+
+    #[test]
+    fn test_downmix_channel_mask() {
+        // front left, front right, front center, low frequency
+        let output = downmix_each(4, Some(0xF), 2);
+
+        assert!(output[2][0] > 0 && output[2][0] == output[2][1]);
+        assert_eq!(output[3], vec![0, 0]);
+    }
+
+    #[test]
+    fn test_downmix_mono() {
+        let output = downmix_each(6, None, 1);
+
+        // the low frequency channel is left out, the center is heard the most
+        assert_eq!(output[3], vec![0]);
+        assert!(output[2][0] > output[0][0] && output[0][0] > output[4][0]);
+        assert_eq!(output[0], output[1]);
+
+        // and all channels at full scale don't clip
+        let mut converter = Converter::new(6, 16, None, AudioFormat::from_name("s16le", 1).unwrap());
+        let mut output = Vec::new();
+        converter.convert(&[i32::MAX; 6], &mut output);
+        assert!(s16le(&output)[0] > 32000);
+    }
+
+    #[test]
+    fn test_dither() {
+        // a constant a quarter LSB above zero averages out at a quarter LSB
+        let mut converter = Converter::new(1, 24, None, AudioFormat::from_name("s16le", 1).unwrap());
+        let mut output = Vec::new();
+        converter.convert(&vec![1 << 14; 10000], &mut output);
+
+        let output = s16le(&output);
+        let mean = output.iter().map(|sample| f64::from(*sample)).sum::<f64>() / output.len() as f64;
+        assert!((mean - 0.25).abs() < 0.05);
+        assert!(output.iter().all(|sample| (-1..=2).contains(sample)));
+
+        // without extra precision there's nothing to dither
+        let mut converter = Converter::new(1, 16, None, AudioFormat::from_name("s16le", 1).unwrap());
+        let mut output = Vec::new();
+        converter.convert(&[3 << 16, -7 << 16], &mut output);
+        assert_eq!(s16le(&output), vec![3, -7]);
+    }
+}
@@ -0,0 +1,771 @@
+use std::io::{self, Read};
+use std::time::Duration;
+
+use log::debug;
+
+use super::{Input, InputFormat, FLAC_MAGIC};
+
+const BLOCK_STREAMINFO: u8 = 0;
+
+const FRAME_SYNC: u32 = 0x3FFE;
+
+// wider samples would overflow the side channel of a stereo frame
+const MAX_BITS_PER_SAMPLE: u32 = 24;
+
+const BUFFER_SIZE: usize = 4096;
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn crc8(mut crc: u8, byte: u8) -> u8 {
+    crc ^= byte;
+    for _ in 0..8 {
+        crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+    }
+    crc
+}
+
+fn crc16(mut crc: u16, byte: u8) -> u16 {
+    crc ^= u16::from(byte) << 8;
+    for _ in 0..8 {
+        crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x8005 } else { crc << 1 };
+    }
+    crc
+}
+
+struct BitReader<R> {
+    reader: R,
+    buf: Vec<u8>,
+    pos: usize,
+    len: usize,
+    cache: u64,
+    bits: u32,
+    // running checksums of all bytes taken since the last reset
+    crc8: u8,
+    crc16: u16,
+}
+
+impl<R: Read> BitReader<R> {
+    fn new(reader: R) -> BitReader<R> {
+        BitReader { reader, buf: vec![0u8; BUFFER_SIZE], pos: 0, len: 0, cache: 0, bits: 0, crc8: 0, crc16: 0 }
+    }
+
+    fn fill(&mut self) -> io::Result<bool> {
+        if self.pos == self.len {
+            self.pos = 0;
+            self.len = self.reader.read(&mut self.buf)?;
+        }
+
+        Ok(self.len > 0)
+    }
+
+    fn next_byte(&mut self) -> io::Result<u8> {
+        if !self.fill()? {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated FLAC stream"));
+        }
+
+        let byte = self.buf[self.pos];
+        self.pos += 1;
+        self.crc8 = crc8(self.crc8, byte);
+        self.crc16 = crc16(self.crc16, byte);
+        Ok(byte)
+    }
+
+    fn at_end(&mut self) -> io::Result<bool> {
+        Ok(self.bits == 0 && !self.fill()?)
+    }
+
+    fn read_bits(&mut self, n: u32) -> io::Result<u32> {
+        if n == 0 { return Ok(0); }
+
+        while self.bits < n {
+            self.cache = (self.cache << 8) | u64::from(self.next_byte()?);
+            self.bits += 8;
+        }
+
+        self.bits -= n;
+        Ok(((self.cache >> self.bits) & ((1 << n) - 1)) as u32)
+    }
+
+    fn read_bit(&mut self) -> io::Result<bool> {
+        Ok(self.read_bits(1)? == 1)
+    }
+
+    fn read_signed(&mut self, n: u32) -> io::Result<i32> {
+        if n == 0 { return Ok(0); }
+
+        let value = self.read_bits(n)?;
+        Ok(((value << (32 - n)) as i32) >> (32 - n))
+    }
+
+    // counts the zero bits before the next one bit
+    fn read_unary(&mut self) -> io::Result<u32> {
+        let mut count = 0;
+
+        loop {
+            if self.bits == 0 {
+                self.cache = u64::from(self.next_byte()?);
+                self.bits = 8;
+            }
+
+            let value = self.cache & ((1 << self.bits) - 1);
+
+            if value == 0 {
+                count += self.bits;
+                self.bits = 0;
+            } else {
+                let zeros = self.bits - (64 - value.leading_zeros());
+                self.bits -= zeros + 1;
+                return Ok(count + zeros);
+            }
+        }
+    }
+
+    fn read_rice(&mut self, param: u32) -> io::Result<i32> {
+        let quotient = self.read_unary()?;
+        let value = quotient.wrapping_shl(param) | self.read_bits(param)?;
+        Ok(((value >> 1) as i32) ^ -((value & 1) as i32))
+    }
+
+    fn align(&mut self) {
+        self.bits -= self.bits % 8;
+    }
+
+    fn reset_crc(&mut self) {
+        self.crc8 = 0;
+        self.crc16 = 0;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ChannelAssignment {
+    Independent(usize),
+    LeftSide,
+    SideRight,
+    MidSide,
+}
+
+impl ChannelAssignment {
+    fn from_code(code: u32) -> Option<ChannelAssignment> {
+        match code {
+            0..=7 => Some(ChannelAssignment::Independent(code as usize + 1)),
+            8 => Some(ChannelAssignment::LeftSide),
+            9 => Some(ChannelAssignment::SideRight),
+            10 => Some(ChannelAssignment::MidSide),
+            _ => None,
+        }
+    }
+
+    fn channels(self) -> usize {
+        match self {
+            ChannelAssignment::Independent(channels) => channels,
+            _ => 2,
+        }
+    }
+
+    // the side channel carries one extra bit
+    fn is_side(self, channel: usize) -> bool {
+        match self {
+            ChannelAssignment::Independent(_) => false,
+            ChannelAssignment::SideRight => channel == 0,
+            ChannelAssignment::LeftSide | ChannelAssignment::MidSide => channel == 1,
+        }
+    }
+}
+
+pub struct FlacInput<R> {
+    reader: BitReader<R>,
+    format: InputFormat,
+    duration: Option<Duration>,
+    channels: Vec<Vec<i32>>,
+    // interleaved samples of the last decoded frame
+    block: Vec<i32>,
+    position: usize,
+}
+
+impl<R: Read + Send> FlacInput<R> {
+    pub fn new(reader: R) -> io::Result<FlacInput<R>> {
+        let mut reader = BitReader::new(reader);
+
+        for &byte in FLAC_MAGIC {
+            if reader.read_bits(8)? != u32::from(byte) {
+                return Err(invalid_data(String::from("not a FLAC stream")));
+            }
+        }
+
+        let mut stream_info = None;
+
+        loop {
+            let last = reader.read_bit()?;
+            let kind = reader.read_bits(7)? as u8;
+            let len = reader.read_bits(24)?;
+
+            if kind == BLOCK_STREAMINFO && stream_info.is_none() {
+                if len != 34 {
+                    return Err(invalid_data(format!("STREAMINFO of {} bytes", len)));
+                }
+
+                let _min_block_size = reader.read_bits(16)?;
+                let _max_block_size = reader.read_bits(16)?;
+                let _min_frame_size = reader.read_bits(24)?;
+                let _max_frame_size = reader.read_bits(24)?;
+                let sample_rate = reader.read_bits(20)?;
+                let channels = reader.read_bits(3)? + 1;
+                let bits_per_sample = reader.read_bits(5)? + 1;
+                let total_samples = (u64::from(reader.read_bits(4)?) << 32) | u64::from(reader.read_bits(32)?);
+
+                // MD5 signature of the decoded audio
+                for _ in 0..4 { reader.read_bits(32)?; }
+
+                stream_info = Some((sample_rate, channels, bits_per_sample, total_samples));
+            } else {
+                for _ in 0..len { reader.read_bits(8)?; }
+            }
+
+            if last { break; }
+        }
+
+        let (sample_rate, channels, bits_per_sample, total_samples) = stream_info.ok_or_else(|| invalid_data(String::from("missing STREAMINFO")))?;
+
+        if sample_rate == 0 {
+            return Err(invalid_data(String::from("invalid sample rate in STREAMINFO")));
+        }
+
+        if !(4..=MAX_BITS_PER_SAMPLE).contains(&bits_per_sample) {
+            return Err(invalid_data(format!("unsupported FLAC bit depth {}", bits_per_sample)));
+        }
+
+        let format = InputFormat { sample_rate, channels: channels as u16, bits_per_sample: bits_per_sample as u16 };
+
+        // zero means the encoder didn't know the length
+        let duration = if total_samples == 0 { None } else { Some(Duration::from_millis(total_samples * 1000 / u64::from(sample_rate))) };
+
+        debug!("FLAC {} Hz, {} channels, {} bits, {:?}", sample_rate, channels, bits_per_sample, duration);
+
+        Ok(FlacInput { reader, format, duration, channels: vec![Vec::new(); channels as usize], block: Vec::new(), position: 0 })
+    }
+
+    fn read_frame_number(&mut self) -> io::Result<()> {
+        // UTF-8 like coding of the frame or sample number, which isn't needed for playback
+        let first = self.reader.read_bits(8)?;
+        let continuation = match (first as u8).leading_ones() {
+            0 => 0,
+            n @ 2..=7 => n - 1,
+            _ => return Err(invalid_data(String::from("invalid frame number"))),
+        };
+
+        for _ in 0..continuation {
+            if self.reader.read_bits(8)? & 0xC0 != 0x80 {
+                return Err(invalid_data(String::from("invalid frame number")));
+            }
+        }
+
+        Ok(())
+    }
+
+    // returns false at the end of the stream
+    fn decode_frame(&mut self) -> io::Result<bool> {
+        if self.reader.at_end()? { return Ok(false); }
+
+        self.reader.reset_crc();
+
+        if self.reader.read_bits(14)? != FRAME_SYNC || self.reader.read_bit()? {
+            return Err(invalid_data(String::from("lost FLAC frame sync")));
+        }
+
+        let _variable_block_size = self.reader.read_bit()?;
+        let block_size_code = self.reader.read_bits(4)?;
+        let sample_rate_code = self.reader.read_bits(4)?;
+        let assignment = ChannelAssignment::from_code(self.reader.read_bits(4)?).ok_or_else(|| invalid_data(String::from("reserved channel assignment")))?;
+        let sample_size_code = self.reader.read_bits(3)?;
+
+        if self.reader.read_bit()? {
+            return Err(invalid_data(String::from("reserved bit set in frame header")));
+        }
+
+        self.read_frame_number()?;
+
+        let block_size = match block_size_code {
+            1 => 192,
+            2..=5 => 576 << (block_size_code - 2),
+            6 => self.reader.read_bits(8)? + 1,
+            7 => self.reader.read_bits(16)? + 1,
+            8..=15 => 256 << (block_size_code - 8),
+            _ => return Err(invalid_data(String::from("reserved block size"))),
+        } as usize;
+
+        let sample_rate = match sample_rate_code {
+            0 => self.format.sample_rate,
+            1 => 88200,
+            2 => 176_400,
+            3 => 192_000,
+            4 => 8000,
+            5 => 16000,
+            6 => 22050,
+            7 => 24000,
+            8 => 32000,
+            9 => 44100,
+            10 => 48000,
+            11 => 96000,
+            12 => self.reader.read_bits(8)? * 1000,
+            13 => self.reader.read_bits(16)?,
+            14 => self.reader.read_bits(16)? * 10,
+            _ => return Err(invalid_data(String::from("invalid sample rate"))),
+        };
+
+        let bits_per_sample = match sample_size_code {
+            0 => u32::from(self.format.bits_per_sample),
+            1 => 8,
+            2 => 12,
+            4 => 16,
+            5 => 20,
+            6 => 24,
+            7 => 32,
+            _ => return Err(invalid_data(String::from("reserved sample size"))),
+        };
+
+        let crc = self.reader.crc8;
+        if self.reader.read_bits(8)? != u32::from(crc) {
+            return Err(invalid_data(String::from("FLAC frame header checksum mismatch")));
+        }
+
+        if sample_rate != self.format.sample_rate || bits_per_sample != u32::from(self.format.bits_per_sample) || assignment.channels() != self.channels.len() {
+            return Err(invalid_data(String::from("FLAC stream format changes between frames")));
+        }
+
+        for (channel, samples) in self.channels.iter_mut().enumerate() {
+            samples.resize(block_size, 0);
+            let bits = if assignment.is_side(channel) { bits_per_sample + 1 } else { bits_per_sample };
+            decode_subframe(&mut self.reader, bits, samples)?;
+        }
+
+        self.reader.align();
+
+        let crc = self.reader.crc16;
+        if self.reader.read_bits(16)? != u32::from(crc) {
+            return Err(invalid_data(String::from("FLAC frame checksum mismatch")));
+        }
+
+        decorrelate(assignment, &mut self.channels);
+
+        let shift = 32 - bits_per_sample;
+        self.block.clear();
+        for i in 0..block_size {
+            self.block.extend(self.channels.iter().map(|samples| samples[i] << shift));
+        }
+        self.position = 0;
+
+        Ok(true)
+    }
+}
+
+fn decode_subframe<R: Read>(reader: &mut BitReader<R>, bits_per_sample: u32, samples: &mut [i32]) -> io::Result<()> {
+    if reader.read_bit()? {
+        return Err(invalid_data(String::from("invalid subframe padding")));
+    }
+
+    let kind = reader.read_bits(6)?;
+
+    // low bits which are zero in every sample of the subframe
+    let wasted = if reader.read_bit()? { reader.read_unary()? + 1 } else { 0 };
+
+    if wasted >= bits_per_sample {
+        return Err(invalid_data(format!("{} wasted bits in {} bit samples", wasted, bits_per_sample)));
+    }
+
+    let bits = bits_per_sample - wasted;
+
+    match kind {
+        0 => {
+            let value = reader.read_signed(bits)?;
+            samples.iter_mut().for_each(|sample| *sample = value);
+        }
+        1 => {
+            for sample in samples.iter_mut() {
+                *sample = reader.read_signed(bits)?;
+            }
+        }
+        8..=12 => {
+            let order = (kind - 8) as usize;
+            decode_warmup(reader, bits, order, samples)?;
+            decode_residual(reader, order, samples)?;
+            predict_fixed(order, samples);
+        }
+        32..=63 => {
+            let order = (kind - 31) as usize;
+            decode_warmup(reader, bits, order, samples)?;
+
+            let precision = reader.read_bits(4)? + 1;
+            if precision == 16 {
+                return Err(invalid_data(String::from("invalid LPC precision")));
+            }
+
+            let shift = reader.read_signed(5)?;
+            if shift < 0 {
+                return Err(invalid_data(String::from("negative LPC shift")));
+            }
+
+            let mut coefficients = Vec::with_capacity(order);
+            for _ in 0..order {
+                coefficients.push(i64::from(reader.read_signed(precision)?));
+            }
+
+            decode_residual(reader, order, samples)?;
+            predict_lpc(&coefficients, shift as u32, samples);
+        }
+        _ => return Err(invalid_data(format!("reserved subframe type {}", kind))),
+    }
+
+    if wasted > 0 {
+        samples.iter_mut().for_each(|sample| *sample <<= wasted);
+    }
+
+    Ok(())
+}
+
+fn decode_warmup<R: Read>(reader: &mut BitReader<R>, bits: u32, order: usize, samples: &mut [i32]) -> io::Result<()> {
+    if order > samples.len() {
+        return Err(invalid_data(format!("predictor order {} exceeds block size {}", order, samples.len())));
+    }
+
+    for sample in samples[0..order].iter_mut() {
+        *sample = reader.read_signed(bits)?;
+    }
+
+    Ok(())
+}
+
+fn decode_residual<R: Read>(reader: &mut BitReader<R>, order: usize, samples: &mut [i32]) -> io::Result<()> {
+    let (param_bits, escape) = match reader.read_bits(2)? {
+        0 => (4, 0x0F),
+        1 => (5, 0x1F),
+        _ => return Err(invalid_data(String::from("reserved residual coding method"))),
+    };
+
+    let partition_order = reader.read_bits(4)?;
+    let partitions = 1 << partition_order;
+    let partition_size = samples.len() >> partition_order;
+
+    if partition_size * partitions != samples.len() || partition_size < order {
+        return Err(invalid_data(format!("invalid residual partition order {}", partition_order)));
+    }
+
+    let mut pos = order;
+
+    for partition in 0..partitions {
+        let end = (partition + 1) * partition_size;
+        let param = reader.read_bits(param_bits)?;
+
+        if param == escape {
+            let bits = reader.read_bits(5)?;
+            for sample in samples[pos..end].iter_mut() {
+                *sample = reader.read_signed(bits)?;
+            }
+        } else {
+            for sample in samples[pos..end].iter_mut() {
+                *sample = reader.read_rice(param)?;
+            }
+        }
+
+        pos = end;
+    }
+
+    Ok(())
+}
+
+// the samples hold the residual after the warm up samples
+fn predict_fixed(order: usize, samples: &mut [i32]) {
+    for i in order..samples.len() {
+        let s = |n: usize| i64::from(samples[i - n]);
+
+        let prediction = match order {
+            0 => 0,
+            1 => s(1),
+            2 => 2 * s(1) - s(2),
+            3 => 3 * s(1) - 3 * s(2) + s(3),
+            _ => 4 * s(1) - 6 * s(2) + 4 * s(3) - s(4),
+        };
+
+        samples[i] = samples[i].wrapping_add(prediction as i32);
+    }
+}
+
+fn predict_lpc(coefficients: &[i64], shift: u32, samples: &mut [i32]) {
+    for i in coefficients.len()..samples.len() {
+        let sum = coefficients.iter().enumerate().map(|(j, coefficient)| coefficient * i64::from(samples[i - 1 - j])).sum::<i64>();
+        samples[i] = samples[i].wrapping_add((sum >> shift) as i32);
+    }
+}
+
+fn decorrelate(assignment: ChannelAssignment, channels: &mut [Vec<i32>]) {
+    if let ChannelAssignment::Independent(_) = assignment { return; }
+
+    let (first, second) = channels.split_at_mut(1);
+
+    for (a, b) in first[0].iter_mut().zip(second[0].iter_mut()) {
+        match assignment {
+            ChannelAssignment::LeftSide => *b = *a - *b,
+            ChannelAssignment::SideRight => *a += *b,
+            ChannelAssignment::MidSide => {
+                let mid = (*a << 1) | (*b & 1);
+                let side = *b;
+                *a = (mid + side) >> 1;
+                *b = (mid - side) >> 1;
+            }
+            ChannelAssignment::Independent(_) => {},
+        }
+    }
+}
+
+impl<R: Read + Send> Input for FlacInput<R> {
+    fn format(&self) -> InputFormat {
+        self.format
+    }
+
+    fn duration(&self) -> Option<Duration> {
+        self.duration
+    }
+
+    fn read_samples(&mut self, buf: &mut [i32]) -> io::Result<usize> {
+        if self.position == self.block.len() && !self.decode_frame()? {
+            return Ok(0);
+        }
+
+        let n = buf.len().min(self.block.len() - self.position);
+        buf[0..n].copy_from_slice(&self.block[self.position..self.position + n]);
+        self.position += n;
+
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use std::time::Duration;
+
+    use crate::audio_format::AudioFormat;
+    use crate::input::Input;
+
+    fn s16le() -> AudioFormat {
+        AudioFormat::from_name("s16le", 2).unwrap()
+    }
+
+    struct BitWriter {
+        data: Vec<u8>,
+        cache: u64,
+        bits: u32,
+    }
+
+    impl BitWriter {
+        fn new() -> BitWriter {
+            BitWriter { data: Vec::new(), cache: 0, bits: 0 }
+        }
+
+        fn write(&mut self, value: i64, n: u32) {
+            for bit in (0..n).rev() {
+                self.cache = (self.cache << 1) | ((value >> bit) as u64 & 1);
+                self.bits += 1;
+
+                if self.bits == 8 {
+                    self.data.push(self.cache as u8);
+                    self.cache = 0;
+                    self.bits = 0;
+                }
+            }
+        }
+
+        fn write_rice(&mut self, value: i32, param: u32) {
+            let folded = ((value << 1) ^ (value >> 31)) as u32;
+            for _ in 0..folded >> param { self.write(0, 1); }
+            self.write(1, 1);
+            self.write(i64::from(folded), param);
+        }
+
+        fn align(&mut self) {
+            while self.bits != 0 { self.write(0, 1); }
+        }
+    }
+
+    enum Subframe {
+        Constant,
+        Verbatim,
+        Fixed(usize),
+        Lpc(Vec<i32>, u32),
+    }
+
+    fn write_subframe(writer: &mut BitWriter, subframe: &Subframe, bits: u32, wasted: u32, samples: &[i32]) {
+        let samples = samples.iter().map(|sample| sample >> wasted).collect::<Vec<_>>();
+        let bits = bits - wasted;
+
+        let kind = match subframe {
+            Subframe::Constant => 0,
+            Subframe::Verbatim => 1,
+            Subframe::Fixed(order) => 8 + *order as i64,
+            Subframe::Lpc(coefficients, _) => 31 + coefficients.len() as i64,
+        };
+
+        writer.write(0, 1);
+        writer.write(kind, 6);
+
+        if wasted > 0 {
+            writer.write(1, 1);
+            writer.write(1, wasted);
+        } else {
+            writer.write(0, 1);
+        }
+
+        let mut residual = samples.clone();
+
+        match subframe {
+            Subframe::Constant => return writer.write(i64::from(samples[0]), bits),
+            Subframe::Verbatim => return samples.iter().for_each(|sample| writer.write(i64::from(*sample), bits)),
+            Subframe::Fixed(order) => {
+                samples[0..*order].iter().for_each(|sample| writer.write(i64::from(*sample), bits));
+
+                // differentiating order times leaves the residual
+                for _ in 0..*order {
+                    for i in (1..residual.len()).rev() { residual[i] -= residual[i - 1]; }
+                }
+                residual.drain(0..*order);
+            }
+            Subframe::Lpc(coefficients, shift) => {
+                samples[0..coefficients.len()].iter().for_each(|sample| writer.write(i64::from(*sample), bits));
+                writer.write(15 - 1, 4);
+                writer.write(i64::from(*shift), 5);
+                coefficients.iter().for_each(|coefficient| writer.write(i64::from(*coefficient), 15));
+
+                residual = (coefficients.len()..samples.len()).map(|i| {
+                    let sum = coefficients.iter().enumerate().map(|(j, c)| i64::from(*c) * i64::from(samples[i - 1 - j])).sum::<i64>();
+                    samples[i] - (sum >> shift) as i32
+                }).collect();
+            }
+        }
+
+        // a single partition with 5-bit rice parameters
+        writer.write(1, 2);
+        writer.write(0, 4);
+        writer.write(3, 5);
+        residual.iter().for_each(|value| writer.write_rice(*value, 3));
+    }
+
+    fn stream_info(writer: &mut BitWriter, channels: u32, bits: u32, total_samples: u64) {
+        writer.data.extend_from_slice(crate::input::FLAC_MAGIC);
+
+        writer.write(0, 8);
+        writer.write(34, 24);
+        writer.write(16, 16);
+        writer.write(4096, 16);
+        writer.write(0, 24);
+        writer.write(0, 24);
+        writer.write(44100, 20);
+        writer.write(i64::from(channels - 1), 3);
+        writer.write(i64::from(bits - 1), 5);
+        writer.write(total_samples as i64, 36);
+        writer.write(0, 64);
+        writer.write(0, 64);
+
+        // padding is skipped
+        writer.write(0x81, 8);
+        writer.write(4, 24);
+        writer.write(0, 32);
+    }
+
+    fn frame(writer: &mut BitWriter, number: i64, assignment: i64, bits: u32, subframes: &[(Subframe, u32, Vec<i32>)]) {
+        let start = writer.data.len();
+        let size_code = if bits == 16 { 4 } else { 6 };
+
+        writer.write(0x3FFE, 14);
+        writer.write(0, 2);
+        writer.write(7, 4);
+        writer.write(9, 4);
+        writer.write(assignment, 4);
+        writer.write(size_code, 3);
+        writer.write(0, 1);
+        writer.write(number, 8);
+        writer.write(subframes[0].2.len() as i64 - 1, 16);
+        writer.write(i64::from(writer.data[start..].iter().fold(0, |crc, byte| super::crc8(crc, *byte))), 8);
+
+        for (channel, (subframe, wasted, samples)) in subframes.iter().enumerate() {
+            let side = match assignment { 8 | 10 => channel == 1, 9 => channel == 0, _ => false };
+            write_subframe(writer, subframe, if side { bits + 1 } else { bits }, *wasted, samples);
+        }
+
+        writer.align();
+        writer.write(i64::from(writer.data[start..].iter().fold(0, |crc, byte| super::crc16(crc, *byte))), 16);
+    }
+
+    fn decode(data: Vec<u8>) -> (Box<dyn Input>, Vec<i32>) {
+        let mut input = crate::input::from_reader(Box::new(Cursor::new(data)), s16le(), 44100, None).unwrap();
+        let mut samples = Vec::new();
+        let mut buf = [0i32; 7];
+
+        loop {
+            let n = input.read_samples(&mut buf).unwrap();
+            if n == 0 { break; }
+            samples.extend_from_slice(&buf[0..n]);
+        }
+
+        (input, samples)
+    }
+
+    fn interleave(left: &[i32], right: &[i32], bits: u32) -> Vec<i32> {
+        left.iter().zip(right).flat_map(|(l, r)| vec![l << (32 - bits), r << (32 - bits)]).collect()
+    }
+
+    #[test]
+    fn test_decode_16_bit() {
+        let left = (0..24).map(|i| ((i * 1500) % 32768) - 16384).collect::<Vec<i32>>();
+        let right = (0..24).map(|i| 1000 - i * i * 40).collect::<Vec<i32>>();
+        let mid = left.iter().zip(&right).map(|(l, r)| (l + r) >> 1).collect::<Vec<i32>>();
+        let side = left.iter().zip(&right).map(|(l, r)| l - r).collect::<Vec<i32>>();
+
+        let mut writer = BitWriter::new();
+        stream_info(&mut writer, 2, 16, 88200);
+        frame(&mut writer, 0, 1, 16, &[(Subframe::Verbatim, 0, left.clone()), (Subframe::Fixed(2), 0, right.clone())]);
+        frame(&mut writer, 1, 10, 16, &[(Subframe::Fixed(4), 0, mid), (Subframe::Lpc(vec![1 << 13], 13), 0, side)]);
+        frame(&mut writer, 2, 9, 16, &[(Subframe::Constant, 0, vec![-3; 24]), (Subframe::Constant, 0, vec![7; 24])]);
+
+        let (input, samples) = decode(writer.data);
+
+        assert_eq!(input.format(), crate::input::InputFormat { sample_rate: 44100, channels: 2, bits_per_sample: 16 });
+        assert_eq!(input.duration(), Some(Duration::from_secs(2)));
+
+        let mut expected = interleave(&left, &right, 16);
+        expected.extend(interleave(&left, &right, 16));
+        expected.extend(interleave(&[4; 24], &[7; 24], 16));
+        assert_eq!(samples, expected);
+    }
+
+    #[test]
+    fn test_decode_24_bit() {
+        let left = (0..32).map(|i| (i * 262_144) - 4_194_304).collect::<Vec<i32>>();
+        let right = (0..32).map(|i| ((i * 7919) % 65536 - 32768) * 128).collect::<Vec<i32>>();
+        let side = left.iter().zip(&right).map(|(l, r)| l - r).collect::<Vec<i32>>();
+
+        let mut writer = BitWriter::new();
+        stream_info(&mut writer, 2, 24, 0);
+        frame(&mut writer, 0, 8, 24, &[(Subframe::Lpc(vec![2 << 12, -1 << 12], 12), 18, left.clone()), (Subframe::Fixed(1), 7, side)]);
+
+        let (input, samples) = decode(writer.data);
+
+        assert_eq!(input.format().bits_per_sample, 24);
+        assert_eq!(input.duration(), None);
+        assert_eq!(samples, interleave(&left, &right, 24));
+    }
+
+    #[test]
+    fn test_checksum_mismatch() {
+        let mut writer = BitWriter::new();
+        stream_info(&mut writer, 1, 16, 16);
+        frame(&mut writer, 0, 0, 16, &[(Subframe::Verbatim, 0, (0..16).collect())]);
+
+        let len = writer.data.len();
+        writer.data[len - 5] ^= 0x10;
+
+        let mut input = crate::input::from_reader(Box::new(Cursor::new(writer.data)), s16le(), 44100, None).unwrap();
+        let err = input.read_samples(&mut [0i32; 16]).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
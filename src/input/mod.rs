@@ -0,0 +1,264 @@
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, Read};
+use std::thread;
+use std::time::Duration;
+
+use futures::executor::block_on;
+use log::{debug, warn};
+use tokio::sync::mpsc;
+
+use crate::audio_format::AudioFormat;
+use crate::frames::Frames;
+use crate::sample_rate::SampleRate;
+
+mod convert;
+#[cfg(feature = "flac")]
+mod flac;
+mod resample;
+mod wav;
+
+#[cfg(feature = "flac")]
+pub use self::flac::FlacInput;
+pub use self::convert::Converter;
+pub use self::resample::{Resampler, ResampleQuality};
+pub use self::wav::WavInput;
+
+const FLAC_MAGIC: &[u8] = b"fLaC";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub bits_per_sample: u16,
+}
+
+pub trait Input: Send {
+    fn format(&self) -> InputFormat;
+    fn duration(&self) -> Option<Duration>;
+
+    // the speakers of the channels as WAVE dwChannelMask bits, when the input names them
+    fn channel_mask(&self) -> Option<u32> {
+        None
+    }
+
+    // reads interleaved samples, left justified into 32 bits, returns 0 at the end of the stream
+    fn read_samples(&mut self, buf: &mut [i32]) -> io::Result<usize>;
+}
+
+// headerless input, in the format given on the command line
+pub struct RawInput<R> {
+    reader: R,
+    format: AudioFormat,
+    sample_rate: u32,
+    duration: Option<Duration>,
+    pending: Vec<u8>,
+}
+
+impl<R: Read + Send> RawInput<R> {
+    pub fn new(reader: R, format: AudioFormat, sample_rate: u32, size: Option<u64>) -> RawInput<R> {
+        let duration = size.map(|size| Duration::from_millis(size / format.bytes_per_frame() as u64 * 1000 / u64::from(sample_rate)));
+        RawInput { reader, format, sample_rate, duration, pending: Vec::new() }
+    }
+}
+
+impl<R: Read + Send> Input for RawInput<R> {
+    fn format(&self) -> InputFormat {
+        InputFormat { sample_rate: self.sample_rate, channels: self.format.channels, bits_per_sample: self.format.bits_per_sample() }
+    }
+
+    fn duration(&self) -> Option<Duration> {
+        self.duration
+    }
+
+    fn read_samples(&mut self, buf: &mut [i32]) -> io::Result<usize> {
+        let size = self.format.bytes_per_sample();
+        let mut data = vec![0u8; buf.len() * size];
+        data[0..self.pending.len()].copy_from_slice(&self.pending);
+        let mut len = self.pending.len();
+
+        // a partial read may split a sample
+        while len < size {
+            let n = self.reader.read(&mut data[len..])?;
+            if n == 0 { return Ok(0); }
+            len += n;
+        }
+
+        let samples = len / size;
+        self.pending = data[samples * size..len].to_vec();
+
+        for (sample, bytes) in buf.iter_mut().zip(data[0..samples * size].chunks_exact(size)) {
+            *sample = self.format.read_sample(bytes);
+        }
+
+        Ok(samples)
+    }
+}
+
+// the raw format and rate are only used for headerless input
+pub fn open(name: &str, raw_format: AudioFormat, raw_sample_rate: u32) -> io::Result<Box<dyn Input>> {
+    if name == "-" {
+        return from_reader(Box::new(io::stdin()), raw_format, raw_sample_rate, None);
+    }
+
+    let file = File::open(name)?;
+    let size = file.metadata()?.len();
+
+    from_reader(Box::new(BufReader::new(file)), raw_format, raw_sample_rate, Some(size))
+}
+
+pub fn from_reader(mut reader: Box<dyn Read + Send>, raw_format: AudioFormat, raw_sample_rate: u32, size: Option<u64>) -> io::Result<Box<dyn Input>> {
+    let mut magic = Vec::new();
+    (&mut reader).take(12).read_to_end(&mut magic)?;
+
+    // put the peeked bytes back in front, stdin can't seek
+    let reader = Cursor::new(magic.clone()).chain(reader);
+
+    if magic.len() == 12 && &magic[0..4] == b"RIFF" && &magic[8..12] == b"WAVE" {
+        debug!("reading WAVE input");
+        Ok(Box::new(WavInput::new(reader)?))
+    } else if magic.starts_with(FLAC_MAGIC) {
+        flac_input(reader)
+    } else {
+        debug!("reading raw {} input at {} Hz", raw_format, raw_sample_rate);
+        Ok(Box::new(RawInput::new(reader, raw_format, raw_sample_rate, size)))
+    }
+}
+
+#[cfg(feature = "flac")]
+fn flac_input(reader: impl Read + Send + 'static) -> io::Result<Box<dyn Input>> {
+    debug!("reading FLAC input");
+    Ok(Box::new(FlacInput::new(reader)?))
+}
+
+#[cfg(not(feature = "flac"))]
+fn flac_input(_reader: impl Read + Send + 'static) -> io::Result<Box<dyn Input>> {
+    Err(io::Error::new(io::ErrorKind::InvalidData, "FLAC input requires the flac feature"))
+}
+
+pub fn check_format(format: InputFormat) -> io::Result<()> {
+    if SampleRate::try_from(u64::from(format.sample_rate)).is_err() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported input sample rate {} Hz", format.sample_rate)));
+    }
+
+    if format.channels == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "input has no channels"));
+    }
+
+    Ok(())
+}
+
+// passes the input through when it already has the given rate
+pub fn resample(input: Box<dyn Input>, sample_rate: SampleRate, quality: ResampleQuality) -> Box<dyn Input> {
+    let sample_rate = u64::from(sample_rate) as u32;
+
+    if input.format().sample_rate == sample_rate {
+        input
+    } else {
+        Box::new(Resampler::new(input, sample_rate, quality))
+    }
+}
+
+// reads the input on its own thread, handing out chunks converted to the output format
+pub fn spawn(mut input: Box<dyn Input>, output: AudioFormat, chunk_length: Frames, capacity: usize) -> mpsc::Receiver<io::Result<Vec<u8>>> {
+    let (mut sender, receiver) = mpsc::channel(capacity);
+
+    thread::spawn(move || {
+        let format = input.format();
+        let mut converter = Converter::new(format.channels, format.bits_per_sample, input.channel_mask(), output);
+
+        let channels = converter.input_channels();
+        let chunk_size = chunk_length.as_usize(output.bytes_per_frame());
+
+        let mut samples = vec![0i32; chunk_length.as_usize(channels)];
+        let mut filled = 0;
+
+        loop {
+            let result = input.read_samples(&mut samples[filled..]);
+
+            let n = match result {
+                Ok(n) => n,
+                Err(err) => {
+                    let _ = block_on(sender.send(Err(err)));
+                    return;
+                }
+            };
+
+            filled += n;
+
+            // only whole chunks are sent, except at the end
+            if filled == samples.len() || (n == 0 && filled >= channels) {
+                let frames = filled - filled % channels;
+                let mut chunk = Vec::with_capacity(chunk_size);
+                converter.convert(&samples[0..frames], &mut chunk);
+
+                if block_on(sender.send(Ok(chunk))).is_err() {
+                    debug!("input receiver dropped, stopping");
+                    return;
+                }
+
+                filled = 0;
+            }
+
+            if n == 0 {
+                if filled > 0 { warn!("dropping {} trailing samples", filled); }
+                return;
+            }
+        }
+    });
+
+    receiver
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use crate::audio_format::AudioFormat;
+    use crate::frames::Frames;
+
+    #[test]
+    fn test_raw_input() {
+        let format = AudioFormat::from_name("s16le", 2).unwrap();
+        let data = (0..20u8).collect::<Vec<u8>>();
+        let input = super::from_reader(Box::new(Cursor::new(data.clone())), format, 44100, Some(176_400)).unwrap();
+
+        assert_eq!(input.format().sample_rate, 44100);
+        assert_eq!(input.duration(), Some(std::time::Duration::from_secs(1)));
+
+        let mut receiver = super::spawn(input, format, Frames::new(3), 4);
+        let mut output = Vec::new();
+
+        while let Some(chunk) = futures::executor::block_on(receiver.recv()) {
+            let chunk = chunk.unwrap();
+            assert!(chunk.len() <= 12);
+            output.extend_from_slice(&chunk);
+        }
+
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn test_raw_input_format() {
+        // mono 24-bit big endian at 48 kHz
+        let format = AudioFormat::from_name("s24be", 1).unwrap();
+        let data = vec![0x12, 0x34, 0x56, 0xFF, 0xFF, 0xFF];
+        let mut input = super::from_reader(Box::new(Cursor::new(data)), format, 48000, Some(144_000)).unwrap();
+
+        assert_eq!(input.format(), super::InputFormat { sample_rate: 48000, channels: 1, bits_per_sample: 24 });
+        assert_eq!(input.duration(), Some(std::time::Duration::from_secs(1)));
+
+        let mut samples = [0i32; 4];
+        assert_eq!(input.read_samples(&mut samples).unwrap(), 2);
+        assert_eq!(&samples[0..2], &[0x0012_3456 << 8, -1 << 8]);
+    }
+
+    #[test]
+    fn test_check_format() {
+        let format = super::InputFormat { sample_rate: 48000, channels: 2, bits_per_sample: 16 };
+
+        assert!(super::check_format(format).is_ok());
+        assert!(super::check_format(super::InputFormat { sample_rate: 44056, ..format }).is_err());
+        assert!(super::check_format(super::InputFormat { channels: 0, ..format }).is_err());
+    }
+}
@@ -0,0 +1,342 @@
+use std::f64::consts::PI;
+use std::fmt::{self, Formatter, Display};
+use std::io;
+use std::str::FromStr;
+use std::time::Duration;
+
+use log::debug;
+
+use super::{Input, InputFormat};
+
+const READ_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResampleQuality {
+    Low,
+    Medium,
+    High,
+}
+
+impl ResampleQuality {
+    // filter taps at unity ratio, kaiser window beta and passband width relative to nyquist
+    fn parameters(self) -> (usize, f64, f64) {
+        match self {
+            ResampleQuality::Low => (16, 5.0, 0.85),
+            ResampleQuality::Medium => (32, 8.0, 0.92),
+            ResampleQuality::High => (64, 10.0, 0.96),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidResampleQuality(String);
+
+impl Display for InvalidResampleQuality {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "unknown resampling quality {}, expected low, medium or high", self.0)
+    }
+}
+
+impl std::error::Error for InvalidResampleQuality {}
+
+impl FromStr for ResampleQuality {
+    type Err = InvalidResampleQuality;
+
+    fn from_str(s: &str) -> Result<ResampleQuality, Self::Err> {
+        match s {
+            "low" => Ok(ResampleQuality::Low),
+            "medium" => Ok(ResampleQuality::Medium),
+            "high" => Ok(ResampleQuality::High),
+            _ => Err(InvalidResampleQuality(s.to_owned())),
+        }
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+// modified bessel function of the first kind, order zero
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let mut k = 1.0;
+
+    while term > sum * 1e-12 {
+        term *= (x / (2.0 * k)) * (x / (2.0 * k));
+        sum += term;
+        k += 1.0;
+    }
+
+    sum
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 { 1.0 } else { (PI * x).sin() / (PI * x) }
+}
+
+// converts the sample rate with a kaiser windowed sinc filter, evaluated at one of `phases` fixed offsets
+pub struct Resampler {
+    input: Box<dyn Input>,
+    format: InputFormat,
+    channels: usize,
+    // the input advances `step` phases for every output frame
+    phases: u64,
+    step: u64,
+    half: usize,
+    filter: Vec<f64>,
+    // input frames from `dropped - half` on, interleaved
+    buffer: Vec<f64>,
+    dropped: u64,
+    position: u64,
+    // number of input frames, known once the input is exhausted
+    total: Option<u64>,
+    scratch: Vec<i32>,
+}
+
+impl Resampler {
+    pub fn new(input: Box<dyn Input>, sample_rate: u32, quality: ResampleQuality) -> Resampler {
+        let input_format = input.format();
+        let input_rate = u64::from(input_format.sample_rate);
+        let output_rate = u64::from(sample_rate);
+        let divisor = gcd(input_rate, output_rate);
+
+        let phases = output_rate / divisor;
+        let step = input_rate / divisor;
+
+        // when decimating the cutoff moves down, and the filter gets longer to keep its steepness
+        let (taps, beta, passband) = quality.parameters();
+        let ratio = (output_rate as f64 / input_rate as f64).min(1.0);
+        let cutoff = 0.5 * ratio * passband;
+        let half = ((taps as f64 / ratio).ceil() as usize).div_ceil(2);
+
+        let mut filter = Vec::with_capacity(phases as usize * half * 2);
+
+        for phase in 0..phases {
+            let offset = phase as f64 / phases as f64;
+            let start = filter.len();
+
+            for tap in 0..half * 2 {
+                let t = offset - (tap as f64 - half as f64 + 1.0);
+                let window = 1.0 - (t / half as f64) * (t / half as f64);
+                let window = if window > 0.0 { bessel_i0(beta * window.sqrt()) / bessel_i0(beta) } else { 0.0 };
+                filter.push(2.0 * cutoff * sinc(2.0 * cutoff * t) * window);
+            }
+
+            // unity gain at DC for every phase
+            let sum = filter[start..].iter().sum::<f64>();
+            filter[start..].iter_mut().for_each(|coefficient| *coefficient /= sum);
+        }
+
+        debug!("resampling from {} Hz to {} Hz, {} phases of {} taps", input_rate, output_rate, phases, half * 2);
+
+        let channels = input_format.channels as usize;
+
+        Resampler {
+            input,
+            format: InputFormat { sample_rate, ..input_format },
+            channels,
+            phases,
+            step,
+            half,
+            filter,
+            buffer: vec![0.0; half * channels],
+            dropped: 0,
+            position: 0,
+            total: None,
+            scratch: vec![0; READ_SIZE],
+        }
+    }
+
+    fn buffered_frames(&self) -> u64 {
+        (self.buffer.len() / self.channels) as u64
+    }
+
+    fn fill(&mut self) -> io::Result<()> {
+        let len = self.scratch.len() - self.scratch.len() % self.channels;
+        let n = self.input.read_samples(&mut self.scratch[0..len])?;
+
+        if n == 0 {
+            let pending = self.buffer.len() % self.channels;
+            if pending > 0 {
+                self.buffer.truncate(self.buffer.len() - pending);
+            }
+
+            self.total = Some(self.dropped + self.buffered_frames() - self.half as u64);
+
+            // the filter looks ahead past the last frame
+            self.buffer.resize(self.buffer.len() + self.half * self.channels, 0.0);
+        } else {
+            self.buffer.extend(self.scratch[0..n].iter().map(|sample| f64::from(*sample)));
+        }
+
+        Ok(())
+    }
+}
+
+impl Input for Resampler {
+    fn format(&self) -> InputFormat {
+        self.format
+    }
+
+    fn duration(&self) -> Option<Duration> {
+        self.input.duration()
+    }
+
+    fn channel_mask(&self) -> Option<u32> {
+        self.input.channel_mask()
+    }
+
+    fn read_samples(&mut self, buf: &mut [i32]) -> io::Result<usize> {
+        let mut written = 0;
+
+        while written + self.channels <= buf.len() {
+            let frame = self.position / self.phases;
+            let phase = (self.position % self.phases) as usize;
+
+            if let Some(total) = self.total {
+                if frame >= total { break; }
+            }
+
+            // the filter covers input frames frame - half + 1 ..= frame + half
+            let first = (frame + 1 - self.dropped) as usize;
+
+            // once the input is exhausted the buffer always covers the filter
+            if self.buffered_frames() < (first + self.half * 2) as u64 {
+                self.fill()?;
+                continue;
+            }
+
+            let filter = &self.filter[phase * self.half * 2..(phase + 1) * self.half * 2];
+
+            for channel in 0..self.channels {
+                let sum = filter.iter().enumerate().map(|(tap, coefficient)| coefficient * self.buffer[(first + tap) * self.channels + channel]).sum::<f64>();
+                buf[written + channel] = sum.round().clamp(f64::from(i32::MIN), f64::from(i32::MAX)) as i32;
+            }
+
+            written += self.channels;
+            self.position += self.step;
+        }
+
+        // forget the frames the next output no longer needs
+        let unused = (self.position / self.phases + 1).saturating_sub(self.dropped).min(self.buffered_frames());
+        if unused > READ_SIZE as u64 {
+            self.buffer.drain(0..unused as usize * self.channels);
+            self.dropped += unused;
+        }
+
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f64::consts::PI;
+    use std::io;
+    use std::time::Duration;
+
+    use super::{Resampler, ResampleQuality};
+    use crate::input::{Input, InputFormat};
+
+    struct Tone {
+        sample_rate: u32,
+        frequency: f64,
+        remaining: usize,
+        position: usize,
+    }
+
+    impl Input for Tone {
+        fn format(&self) -> InputFormat {
+            InputFormat { sample_rate: self.sample_rate, channels: 2, bits_per_sample: 16 }
+        }
+
+        fn duration(&self) -> Option<Duration> {
+            None
+        }
+
+        fn read_samples(&mut self, buf: &mut [i32]) -> io::Result<usize> {
+            let frames = (buf.len() / 2).min(self.remaining).min(1000);
+
+            for frame in buf.chunks_exact_mut(2).take(frames) {
+                let value = (2.0 * PI * self.frequency * self.position as f64 / f64::from(self.sample_rate)).sin();
+                frame[0] = (value * f64::from(1 << 30)) as i32;
+                frame[1] = -frame[0];
+                self.position += 1;
+            }
+
+            self.remaining -= frames;
+            Ok(frames * 2)
+        }
+    }
+
+    fn resample(input_rate: u32, output_rate: u32, frequency: f64, frames: usize, quality: ResampleQuality) -> Vec<f64> {
+        let tone = Tone { sample_rate: input_rate, frequency, remaining: frames, position: 0 };
+        let mut resampler = Resampler::new(Box::new(tone), output_rate, quality);
+        assert_eq!(resampler.format().sample_rate, output_rate);
+
+        let mut output = Vec::new();
+        let mut buf = [0i32; 333];
+
+        loop {
+            let n = resampler.read_samples(&mut buf).unwrap();
+            if n == 0 { break; }
+
+            for frame in buf[0..n].chunks_exact(2) {
+                assert_eq!(frame[0], -frame[1]);
+                output.push(f64::from(frame[0]) / f64::from(1 << 30));
+            }
+        }
+
+        output
+    }
+
+    // counts rising zero crossings, interpolating between samples, away from the edges
+    fn measure_frequency(samples: &[f64], sample_rate: u32) -> f64 {
+        let samples = &samples[samples.len() / 10..samples.len() * 9 / 10];
+        let crossings = (1..samples.len())
+            .filter(|i| samples[i - 1] < 0.0 && samples[*i] >= 0.0)
+            .map(|i| (i - 1) as f64 + samples[i - 1] / (samples[i - 1] - samples[i]))
+            .collect::<Vec<_>>();
+
+        let periods = (crossings.len() - 1) as f64;
+        periods * f64::from(sample_rate) / (crossings[crossings.len() - 1] - crossings[0])
+    }
+
+    fn rms(samples: &[f64]) -> f64 {
+        let samples = &samples[samples.len() / 10..samples.len() * 9 / 10];
+        (samples.iter().map(|sample| sample * sample).sum::<f64>() / samples.len() as f64).sqrt()
+    }
+
+    #[test]
+    fn test_downsample_tone() {
+        let output = resample(48000, 44100, 1000.0, 48000, ResampleQuality::Medium);
+
+        assert!((output.len() as i64 - 44100).abs() <= 1);
+        assert!((measure_frequency(&output, 44100) - 1000.0).abs() < 0.01);
+        assert!((rms(&output) - 0.5f64.sqrt()).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_upsample_tone() {
+        for &quality in &[ResampleQuality::Low, ResampleQuality::Medium, ResampleQuality::High] {
+            let output = resample(8000, 44100, 440.0, 8000, quality);
+
+            assert!((output.len() as i64 - 44100).abs() <= 1);
+            assert!((measure_frequency(&output, 44100) - 440.0).abs() < 0.01);
+            assert!((rms(&output) - 0.5f64.sqrt()).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn test_band_limited() {
+        // above the nyquist frequency of the output, so it must not alias into the audible range
+        let output = resample(96000, 44100, 30000.0, 9600, ResampleQuality::High);
+        assert!(rms(&output) < 0.001);
+    }
+
+    #[test]
+    fn test_parse_quality() {
+        assert_eq!("high".parse::<ResampleQuality>().unwrap(), ResampleQuality::High);
+        assert!("best".parse::<ResampleQuality>().is_err());
+    }
+}
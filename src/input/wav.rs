@@ -0,0 +1,295 @@
+use std::io::{self, Read};
+use std::time::Duration;
+
+use byteorder::{LE, ByteOrder};
+use log::debug;
+
+use super::{Input, InputFormat};
+use crate::audio_format::{AudioFormat, Endianness, SampleType};
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+// the sub format GUIDs only differ in their first two bytes, which hold the format tag
+const KSDATAFORMAT_SUBTYPE_TAIL: &[u8] = &[0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71];
+
+// streamed files don't know their size up front
+const UNKNOWN_SIZE: u32 = 0xFFFF_FFFF;
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub struct WavInput<R> {
+    reader: R,
+    format: InputFormat,
+    // the container format of the samples, which may be larger than the bits used
+    sample_format: AudioFormat,
+    channel_mask: Option<u32>,
+    remaining: Option<u64>,
+    duration: Option<Duration>,
+    pending: Vec<u8>,
+}
+
+fn skip(reader: &mut dyn Read, len: u64) -> io::Result<()> {
+    let skipped = io::copy(&mut reader.take(len), &mut io::sink())?;
+
+    if skipped != len {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated chunk"));
+    }
+
+    Ok(())
+}
+
+impl<R: Read + Send> WavInput<R> {
+    pub fn new(mut reader: R) -> io::Result<WavInput<R>> {
+        let mut header = [0u8; 12];
+        reader.read_exact(&mut header)?;
+
+        if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+            return Err(invalid_data(String::from("not a RIFF/WAVE file")));
+        }
+
+        let mut fmt: Option<(InputFormat, AudioFormat, Option<u32>)> = None;
+
+        loop {
+            let mut chunk_header = [0u8; 8];
+            reader.read_exact(&mut chunk_header)?;
+
+            let id = [chunk_header[0], chunk_header[1], chunk_header[2], chunk_header[3]];
+            let len = LE::read_u32(&chunk_header[4..8]);
+
+            if &id == b"data" {
+                let (format, sample_format, channel_mask) = fmt.ok_or_else(|| invalid_data(String::from("data chunk before fmt chunk")))?;
+                let block_align = sample_format.bytes_per_frame() as u64;
+
+                let remaining = if len == UNKNOWN_SIZE { None } else { Some(u64::from(len)) };
+                let duration = remaining.map(|len| Duration::from_millis(len / block_align * 1000 / u64::from(format.sample_rate)));
+
+                debug!("WAVE {} Hz, {} bits in {}, {:?}", format.sample_rate, format.bits_per_sample, sample_format, duration);
+
+                return Ok(WavInput { reader, format, sample_format, channel_mask, remaining, duration, pending: Vec::new() });
+            }
+
+            // chunks are padded to an even size
+            let padded = u64::from(len) + u64::from(len % 2);
+
+            if &id == b"fmt " {
+                let mut chunk = Vec::new();
+                (&mut reader).take(padded).read_to_end(&mut chunk)?;
+                fmt = Some(WavInput::<R>::parse_fmt(&chunk[0..(len as usize).min(chunk.len())])?);
+            } else {
+                debug!("skipping {} chunk of {} bytes", String::from_utf8_lossy(&id), len);
+                skip(&mut reader, padded)?;
+            }
+        }
+    }
+
+    fn parse_fmt(chunk: &[u8]) -> io::Result<(InputFormat, AudioFormat, Option<u32>)> {
+        if chunk.len() < 16 {
+            return Err(invalid_data(String::from("fmt chunk too short")));
+        }
+
+        let mut format_tag = LE::read_u16(&chunk[0..2]);
+        let channels = LE::read_u16(&chunk[2..4]);
+        let sample_rate = LE::read_u32(&chunk[4..8]);
+        let block_align = LE::read_u16(&chunk[12..14]);
+        let mut bits_per_sample = LE::read_u16(&chunk[14..16]);
+        let mut channel_mask = None;
+
+        if format_tag == WAVE_FORMAT_EXTENSIBLE {
+            if chunk.len() < 40 || &chunk[26..40] != KSDATAFORMAT_SUBTYPE_TAIL {
+                return Err(invalid_data(String::from("unsupported WAVE_FORMAT_EXTENSIBLE sub format")));
+            }
+
+            // the container size stays in bits_per_sample, the valid bits may be fewer
+            let valid_bits = LE::read_u16(&chunk[18..20]);
+            if valid_bits > 0 && valid_bits < bits_per_sample { bits_per_sample = valid_bits; }
+
+            // no speakers named means the default order
+            let mask = LE::read_u32(&chunk[20..24]);
+            if mask != 0 { channel_mask = Some(mask); }
+
+            format_tag = LE::read_u16(&chunk[24..26]);
+        }
+
+        if channels == 0 || sample_rate == 0 {
+            return Err(invalid_data(format!("invalid format, {} channels at {} Hz", channels, sample_rate)));
+        }
+
+        let container_size = (block_align / channels) as usize;
+
+        let sample_type = match (format_tag, container_size) {
+            // 8-bit samples are unsigned
+            (WAVE_FORMAT_PCM, 1) => SampleType::U8,
+            (WAVE_FORMAT_PCM, 2) => SampleType::S16,
+            (WAVE_FORMAT_PCM, 3) => SampleType::S24,
+            (WAVE_FORMAT_PCM, 4) => SampleType::S32,
+            (WAVE_FORMAT_IEEE_FLOAT, 4) => SampleType::F32,
+            (WAVE_FORMAT_IEEE_FLOAT, 8) => SampleType::F64,
+            _ => return Err(invalid_data(format!("unsupported WAVE format 0x{:04x} with {} byte samples", format_tag, container_size))),
+        };
+
+        if usize::from(block_align) != container_size * usize::from(channels) || usize::from(bits_per_sample) > container_size * 8 {
+            return Err(invalid_data(format!("inconsistent block align {} for {} channels of {} bits", block_align, channels, bits_per_sample)));
+        }
+
+        Ok((InputFormat { sample_rate, channels, bits_per_sample }, AudioFormat::new(sample_type, Endianness::Little, channels), channel_mask))
+    }
+}
+
+impl<R: Read + Send> Input for WavInput<R> {
+    fn format(&self) -> InputFormat {
+        self.format
+    }
+
+    fn duration(&self) -> Option<Duration> {
+        self.duration
+    }
+
+    fn channel_mask(&self) -> Option<u32> {
+        self.channel_mask
+    }
+
+    fn read_samples(&mut self, buf: &mut [i32]) -> io::Result<usize> {
+        let container_size = self.sample_format.bytes_per_sample();
+        let mut data = std::mem::take(&mut self.pending);
+
+        let mut want = (buf.len() * container_size - data.len()) as u64;
+        if let Some(remaining) = self.remaining { want = want.min(remaining); }
+
+        let mut read = 0;
+
+        // a partial read may split a sample
+        while data.len() < container_size {
+            let n = (&mut self.reader).take(want - read).read_to_end(&mut data)? as u64;
+            if n == 0 { return Ok(0); }
+            read += n;
+        }
+
+        if let Some(ref mut remaining) = self.remaining { *remaining -= read; }
+
+        let samples = data.len() / container_size;
+
+        for (sample, bytes) in buf.iter_mut().zip(data.chunks_exact(container_size)) {
+            *sample = self.sample_format.read_sample(bytes);
+        }
+
+        self.pending = data[samples * container_size..].to_vec();
+
+        Ok(samples)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use std::time::Duration;
+
+    use crate::input::{Input, InputFormat};
+
+    fn chunk(id: &[u8], content: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend_from_slice(&(content.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(content);
+        if content.len() % 2 == 1 { chunk.push(0); }
+        chunk
+    }
+
+    fn fmt(format_tag: u16, channels: u16, sample_rate: u32, bits: u16) -> Vec<u8> {
+        let block_align = channels * bits.div_ceil(8);
+        let mut fmt = Vec::new();
+        fmt.extend_from_slice(&format_tag.to_le_bytes());
+        fmt.extend_from_slice(&channels.to_le_bytes());
+        fmt.extend_from_slice(&sample_rate.to_le_bytes());
+        fmt.extend_from_slice(&(sample_rate * u32::from(block_align)).to_le_bytes());
+        fmt.extend_from_slice(&block_align.to_le_bytes());
+        fmt.extend_from_slice(&bits.to_le_bytes());
+        fmt
+    }
+
+    fn wave(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body = chunks.concat();
+        let mut data = b"RIFF".to_vec();
+        data.extend_from_slice(&(body.len() as u32 + 4).to_le_bytes());
+        data.extend_from_slice(b"WAVE");
+        data.extend_from_slice(&body);
+        data
+    }
+
+    fn read_all(input: &mut dyn Input) -> Vec<i32> {
+        let mut samples = Vec::new();
+        let mut buf = [0i32; 3];
+
+        loop {
+            let n = input.read_samples(&mut buf).unwrap();
+            if n == 0 { break; }
+            samples.extend_from_slice(&buf[0..n]);
+        }
+
+        samples
+    }
+
+    #[test]
+    fn test_read_pcm16_skipping_chunks() {
+        let data = wave(&[
+            chunk(b"fmt ", &fmt(1, 2, 44100, 16)),
+            chunk(b"LIST", b"INFOINAM\x03\x00\x00\x00abc"),
+            chunk(b"data", &[0x01, 0x00, 0xFF, 0xFF, 0x00, 0x80, 0xFF, 0x7F]),
+            chunk(b"id3 ", b"ID3 junk"),
+        ]);
+
+        let mut input = super::WavInput::new(Cursor::new(data)).unwrap();
+
+        assert_eq!(input.format(), InputFormat { sample_rate: 44100, channels: 2, bits_per_sample: 16 });
+        assert_eq!(input.channel_mask(), None);
+        assert_eq!(input.duration(), Some(Duration::new(0, 0)));
+        assert_eq!(read_all(&mut input), vec![1 << 16, -1 << 16, -32768 << 16, 32767 << 16]);
+    }
+
+    #[test]
+    fn test_read_extensible_24_bit() {
+        let mut fmt = fmt(0xFFFE, 2, 48000, 24);
+        fmt.extend_from_slice(&22u16.to_le_bytes());
+        fmt.extend_from_slice(&24u16.to_le_bytes());
+        fmt.extend_from_slice(&3u32.to_le_bytes());
+        fmt.extend_from_slice(&[0x01, 0x00]);
+        fmt.extend_from_slice(super::KSDATAFORMAT_SUBTYPE_TAIL);
+
+        let data = wave(&[
+            chunk(b"fmt ", &fmt),
+            chunk(b"data", &[0x56, 0x34, 0x12, 0xFF, 0xFF, 0xFF]),
+        ]);
+
+        let mut input = super::WavInput::new(Cursor::new(data)).unwrap();
+
+        assert_eq!(input.format(), InputFormat { sample_rate: 48000, channels: 2, bits_per_sample: 24 });
+        assert_eq!(input.channel_mask(), Some(3));
+        assert_eq!(read_all(&mut input), vec![0x1234_5600, -1 << 8]);
+    }
+
+    #[test]
+    fn test_read_8_bit_and_float() {
+        let data = wave(&[chunk(b"fmt ", &fmt(1, 1, 8000, 8)), chunk(b"data", &[0x80, 0xFF, 0x00])]);
+        let mut input = super::WavInput::new(Cursor::new(data)).unwrap();
+        assert_eq!(read_all(&mut input), vec![0, 127 << 24, -128 << 24]);
+
+        let mut samples = Vec::new();
+        for value in &[0.5f32, -1.0, 2.0] { samples.extend_from_slice(&value.to_le_bytes()); }
+
+        let data = wave(&[chunk(b"fmt ", &fmt(3, 1, 44100, 32)), chunk(b"data", &samples)]);
+        let mut input = super::WavInput::new(Cursor::new(data)).unwrap();
+        assert_eq!(read_all(&mut input), vec![1 << 30, i32::MIN, i32::MAX]);
+    }
+
+    #[test]
+    fn test_reject_unsupported() {
+        // IMA ADPCM
+        let data = wave(&[chunk(b"fmt ", &fmt(0x11, 2, 44100, 4)), chunk(b"data", &[0; 8])]);
+        assert!(super::WavInput::new(Cursor::new(data)).is_err());
+
+        let data = wave(&[chunk(b"data", &[0; 8])]);
+        assert!(super::WavInput::new(Cursor::new(data)).is_err());
+    }
+}
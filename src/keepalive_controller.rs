@@ -1,24 +1,31 @@
-use crate::rtsp_client::RTSPClient;
+use crate::rtsp_client::{RTSPClient, RtspError};
 
 use std::sync::{Arc};
 use std::time::Duration;
 
+use beefeater::Beefeater;
 use futures::future::{Abortable, AbortHandle};
 use futures::prelude::*;
 use tokio::sync::Mutex;
 use tokio::time::delay_for;
 
-use log::{info};
+use log::{error, info};
 
 pub struct KeepaliveController {
     abort_handle: Option<AbortHandle>,
 }
 
 impl KeepaliveController {
-    pub fn start(rtsp_client: Arc<Mutex<RTSPClient>>) -> KeepaliveController {
+    // lost is set when the receiver doesn't answer a keepalive
+    pub fn start(rtsp_client: Arc<Mutex<RTSPClient>>, lost: Arc<Beefeater<bool>>) -> KeepaliveController {
         let (abort_handle, abort_registration) = AbortHandle::new_pair();
 
-        let future = run(rtsp_client).map(|result| { result.unwrap(); });
+        let future = run(rtsp_client).map(move |result| {
+            if let Err(err) = result {
+                error!("keepalive failed, connection to receiver is lost: {}", err);
+                lost.store(true);
+            }
+        });
         let future = Abortable::new(future, abort_registration).map(|_| {});
 
         tokio::spawn(future);
@@ -33,7 +40,13 @@ impl KeepaliveController {
     }
 }
 
-async fn run(rtsp_client: Arc<Mutex<RTSPClient>>) -> Result<(), Box<dyn std::error::Error>> {
+impl Drop for KeepaliveController {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+async fn run(rtsp_client: Arc<Mutex<RTSPClient>>) -> Result<(), RtspError> {
     loop {
         delay_for(Duration::from_secs(5)).await;
 
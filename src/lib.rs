@@ -0,0 +1,50 @@
+// Streams audio to AirPlay (RAOP) receivers: connect with RaopClient, stream encoded chunks, and
+// send volume, metadata, artwork and progress. See the examples directory for complete players.
+
+mod artwork;
+mod audio_format;
+pub mod codec;
+mod crypto;
+mod curve25519;
+mod deadline;
+pub mod discovery;
+mod frames;
+pub mod input;
+mod keepalive_controller;
+mod meta_data;
+mod ntp;
+mod plist;
+mod raop_client;
+mod raop_group;
+mod raop_error;
+mod raop_params;
+mod rtp;
+mod rtsp_client;
+mod sample_rate;
+mod serialization;
+mod srp;
+mod tags;
+mod sync_controller;
+mod timing_controller;
+mod volume;
+
+// a receiver on the loopback interface for the tests, the integration tests get it through the test-receiver feature
+#[cfg(any(test, feature = "test-receiver"))]
+#[doc(hidden)]
+pub mod test_receiver;
+
+pub use crate::artwork::{Artwork, ArtworkError, ArtworkScaler, ArtworkType, MAX_ARTWORK_DIMENSION};
+pub use crate::audio_format::{AudioFormat, AudioFormatError, Endianness, SampleType};
+pub use crate::codec::AudioEncoder;
+pub use crate::crypto::Crypto;
+pub use crate::frames::Frames;
+pub use crate::meta_data::{MetaDataItem, MetaDataValue, TrackMetadata};
+pub use crate::ntp::NtpTime;
+pub use crate::raop_client::{RaopClient, RaopEvent, MAX_SAMPLES_PER_CHUNK};
+pub use crate::raop_error::RaopError;
+pub use crate::raop_group::RaopGroup;
+pub use crate::raop_params::{RaopParams, RaopParamsError};
+pub use crate::rtsp_client::RtspError;
+pub use crate::sample_rate::SampleRate;
+pub use crate::tags::Tags;
+pub use crate::volume::Volume;
@@ -1,6 +1,3 @@
-// FIXME: eventually remove these
-#![allow(dead_code)]
-
 // Docopt
 #[macro_use]
 extern crate serde_derive;
@@ -8,9 +5,8 @@ use docopt::Docopt;
 
 // Standard dependencies
 use std::net::{IpAddr, SocketAddr};
-use std::os::unix::io::FromRawFd;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 // General dependencies
 use beefeater::{AddAssign, Beefeater};
@@ -18,70 +14,82 @@ use ctrlc;
 use futures::future::{Abortable, AbortHandle};
 use log::{info, warn};
 use stderrlog;
-use tokio::fs::File;
 use futures::FutureExt;
-use tokio::prelude::*;
+use hex::FromHex;
 use tokio::time::delay_for;
 
-// Local dependencies
-mod codec;
-mod crypto;
-mod curve25519;
-mod frames;
-mod keepalive_controller;
-mod meta_data;
-mod ntp;
-mod raop_client;
-mod raop_params;
-mod rtp;
-mod rtsp_client;
-mod sample_rate;
-mod serialization;
-mod sync_controller;
-mod timing_controller;
-mod volume;
-
-use crate::codec::Codec;
-use crate::crypto::Crypto;
-use crate::frames::Frames;
-use crate::meta_data::MetaDataItem;
-use crate::ntp::NtpTime;
-use crate::raop_client::{RaopClient, MAX_SAMPLES_PER_CHUNK};
-use crate::raop_params::RaopParams;
-use crate::sample_rate::SampleRate;
-use crate::volume::Volume;
+use raop_play::{codec, discovery, input};
+use raop_play::{AudioFormat, Crypto, Frames, NtpTime, RaopClient, RaopParams, SampleRate, Tags, TrackMetadata, Volume, MAX_SAMPLES_PER_CHUNK};
+use raop_play::input::ResampleQuality;
 
 const USAGE: &str = "
 Usage:
     raop_play [options] <server-ip> <filename>
+    raop_play pair [options] <server-ip>
+    raop_play --list [options]
     raop_play (-h | --help)
 
 Options:
-    -a            Send ALAC compressed audio
-    -d LEVEL      Debug level (0 = silent, 5 = trace) [default: 2]
-    -e            Encrypt AirPlay stream using RSA
-    -h, --help    Print this help and exit
-    -l LATENCY    Latency in frames [default: 44100]
-    -p PORT       Specify remote port [default: 5000]
-    -v VOLUME     Specify volume between 0 and 100
+    -a                Send ALAC compressed audio
+    -A ARTWORK        Show the given JPEG or PNG image as cover art
+    --album ALBUM     Album to show instead of the one tagged in the file
+    --artist ARTIST   Artist to show instead of the one tagged in the file
+    -b BITS           Sample size to stream, 16 or 24 [default: 16]
+    --channels N      Channels of raw input [default: 2]
+    -d LEVEL          Debug level (0 = silent, 5 = trace) [default: 2]
+    -e                Encrypt AirPlay stream using RSA
+    --format FORMAT   Sample format of raw input, like u8, s16le, s24be or f32le [default: s16le]
+    -h, --help        Print this help and exit
+    -k KEYFILE        File holding the pairing secret of an AppleTV, written by pair
+    --list            List the AirPlay receivers on the local network and exit
+    -l LATENCY        Latency in frames [default: 44100]
+    --mac MAC         MAC address of the receiver, to check an encrypting receiver is genuine
+    -p PORT           Specify remote port [default: 5000]
+    -P PASSWORD       Password of a password protected receiver
+    -q QUALITY        Resampling quality for input at another rate (low, medium, high) [default: medium]
+    --rate RATE       Sample rate of raw input in Hz [default: 44100]
+    -t START          Start playback at the given UNIX time in milliseconds
+    --title TITLE     Title to show instead of the one tagged in the file
+    -v VOLUME         Specify volume between 0 and 100
 ";
 
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+// chunks read ahead of the player
+const INPUT_CAPACITY: usize = 16;
+
 #[derive(Deserialize)]
 struct Args {
-    arg_server_ip: IpAddr,
+    arg_server_ip: Option<IpAddr>,
     arg_filename: String,
+    cmd_pair: bool,
     flag_a: bool,
+    #[serde(rename = "flag_A")]
+    flag_artwork: Option<String>,
+    flag_album: Option<String>,
+    flag_artist: Option<String>,
+    flag_b: u32,
+    flag_channels: u16,
     flag_d: usize,
     flag_e: bool,
+    flag_format: String,
+    flag_k: Option<String>,
     flag_l: u64,
+    flag_list: bool,
+    flag_mac: Option<String>,
     flag_p: u16,
+    #[serde(rename = "flag_P")]
+    flag_password: Option<String>,
+    flag_q: String,
+    flag_rate: u32,
+    flag_t: Option<u64>,
+    flag_title: Option<String>,
     flag_v: Option<u8>,
 }
 
 #[derive(Clone, Copy, PartialEq)]
 enum Status {
     Stopped,
-    Paused,
     Playing,
 }
 
@@ -107,7 +115,7 @@ impl StatusLogger {
             let now = NtpTime::now();
             let frames = frames.load();
 
-            if frames > latency {
+            if frames > latency && now > start {
                 info!("at {} ({} ms after start), played {} ms", now, (now - start).as_millis(), ((frames - latency) / sample_rate).as_millis());
             }
 
@@ -116,14 +124,44 @@ impl StatusLogger {
     }
 }
 
-async fn open_file(name: String) -> io::Result<File> {
-    if name == "-" {
-        // FIXME: Using tokio::io::stdin results in glitched audio
-        // This is safe because this is the only thing accessing stdin
-        Ok(File::from_std(unsafe { std::fs::File::from_raw_fd(0) }))
-    } else {
-        File::open(name).await
+async fn list_receivers() -> Result<(), Box<dyn std::error::Error>> {
+    for receiver in discovery::browse(DISCOVERY_TIMEOUT).await? {
+        let addresses = receiver.addresses.iter().map(|address| SocketAddr::new(*address, receiver.port).to_string()).collect::<Vec<_>>();
+        println!("{}\t{}", receiver.name, addresses.join(", "));
+
+        let mut txt = receiver.txt.iter().map(|(key, value)| format!("{}={}", key, value)).collect::<Vec<_>>();
+        txt.sort();
+        println!("\t{}", txt.join(" "));
     }
+
+    Ok(())
+}
+
+// accepts 00:50:C2:12:A2:3F as well as 0050C212A23F
+fn parse_mac_address(mac: &str) -> Result<[u8; 6], hex::FromHexError> {
+    <[u8; 6]>::from_hex(mac.replace(|c| c == ':' || c == '-', ""))
+}
+
+fn read_pin() -> std::io::Result<String> {
+    eprint!("Enter the PIN shown on the AppleTV: ");
+
+    let mut pin = String::new();
+    std::io::stdin().read_line(&mut pin)?;
+    Ok(pin)
+}
+
+async fn pair(remote: SocketAddr, keyfile: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+    let secret = RaopClient::pair(remote, read_pin).await?;
+
+    match keyfile {
+        Some(keyfile) => {
+            std::fs::write(&keyfile, format!("{}\n", secret))?;
+            info!("pairing secret written to {}", keyfile);
+        }
+        None => println!("{}", secret),
+    }
+
+    Ok(())
 }
 
 #[tokio::main(basic_scheduler)]
@@ -134,16 +172,51 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     stderrlog::new().verbosity(args.flag_d).timestamp(stderrlog::Timestamp::Microsecond).color(stderrlog::ColorChoice::Never).init()?;
 
+    if args.flag_list {
+        return list_receivers().await;
+    }
+
+    // only listing goes without a receiver
+    let server_ip = args.arg_server_ip.ok_or("<server-ip> is required unless listing receivers, see --help")?;
+    let remote = SocketAddr::new(server_ip, args.flag_p);
+
+    if args.cmd_pair {
+        return pair(remote, args.flag_k).await;
+    }
+
     let mut params = RaopParams::new();
 
-    params.set_codec(Codec::new(args.flag_a, MAX_SAMPLES_PER_CHUNK, SampleRate::Hz44100, 16, 2));
+    params.set_codec(codec::new_encoder(args.flag_a, MAX_SAMPLES_PER_CHUNK, SampleRate::Hz44100, args.flag_b, 2)?);
     params.set_desired_latency(Frames::new(args.flag_l));
     params.set_crypto(Crypto::new(args.flag_e));
+    params.set_password(args.flag_password);
+
+    if let Some(mac) = args.flag_mac {
+        params.set_mac_address(Some(parse_mac_address(&mac)?));
+        params.set_auth(true);
+    }
 
-    let remote = SocketAddr::new(args.arg_server_ip, args.flag_p);
-    let mut infile = open_file(args.arg_filename).await?;
+    if let Some(keyfile) = args.flag_k {
+        params.set_secret(Some(std::fs::read_to_string(keyfile)?.trim().to_owned()));
+    }
+
+    let tags = if args.arg_filename == "-" {
+        Tags::default()
+    } else {
+        Tags::from_file(&args.arg_filename).unwrap_or_else(|err| {
+            warn!("Failed to read tags: {}", err);
+            Tags::default()
+        })
+    };
+
+    let raw_format = AudioFormat::from_name(&args.flag_format, args.flag_channels)?;
+    let input = input::open(&args.arg_filename, raw_format, args.flag_rate)?;
+    input::check_format(input.format())?;
+    let input = input::resample(input, params.codec().sample_rate(), args.flag_q.parse::<ResampleQuality>()?);
+    let input_duration = input.duration();
 
     let mut raopcl = RaopClient::connect(params, remote).await?;
+    let output_format = raopcl.codec().input_format();
 
     if let Some(volume) = args.flag_v {
         raopcl.set_volume(Volume::from_percent(volume)).await?;
@@ -151,20 +224,49 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let latency = raopcl.latency();
 
-    info!("connected to {} on port {}, player latency is {} ms", args.arg_server_ip, args.flag_p, (latency / raopcl.sample_rate()).as_millis());
+    info!("connected to {} on port {}, player latency is {} ms", server_ip, args.flag_p, (latency / raopcl.sample_rate()).as_millis());
 
-    let meta_data = MetaDataItem::listing_item(vec![
-        MetaDataItem::item_kind(2),
-    ]);
+    let meta_data = TrackMetadata {
+        title: args.flag_title.or(tags.metadata.title),
+        artist: args.flag_artist.or(tags.metadata.artist),
+        album: args.flag_album.or(tags.metadata.album),
+        ..tags.metadata
+    };
 
-    if let Err(err) = raopcl.set_meta_data(meta_data).await {
+    if let Err(err) = raopcl.set_meta_data(meta_data.to_listing_item()).await {
         warn!("Failed to set meta data: {}", err);
     }
 
-    let start = NtpTime::now();
+    let artwork = match args.flag_artwork {
+        Some(artwork) => Some(std::fs::read(artwork)?),
+        None => tags.artwork,
+    };
+
+    if let Some(artwork) = artwork {
+        if let Err(err) = raopcl.set_artwork(artwork, None).await {
+            warn!("Failed to set artwork: {}", err);
+        }
+    }
+
+    let length = meta_data.duration.or(input_duration);
+
+    if let Some(length) = length {
+        if let Err(err) = raopcl.set_progress(Duration::new(0, 0), Duration::new(0, 0), length).await {
+            warn!("Failed to set progress: {}", err);
+        }
+    }
+
+    let start = match args.flag_t {
+        Some(millis) => {
+            let start = NtpTime::from_system_time(SystemTime::UNIX_EPOCH + Duration::from_millis(millis));
+            raopcl.start_at(start).await?;
+            start
+        }
+        None => NtpTime::now(),
+    };
     let status = Arc::new(Beefeater::new(Status::Playing));
 
-    let mut buf = [0; MAX_SAMPLES_PER_CHUNK.as_usize(4)];
+    let mut chunks = input::spawn(input, output_format, MAX_SAMPLES_PER_CHUNK, INPUT_CAPACITY);
 
     let frames = Arc::new(Beefeater::new(Frames::new(0)));
     let mut playtime = Duration::new(0, 0);
@@ -179,24 +281,19 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let status_logger = StatusLogger::start(start, Arc::clone(&frames), raopcl.latency(), raopcl.sample_rate());
 
-    loop {
-        match status.load() {
-            Status::Playing => {
-                let n = infile.read(&mut buf).await?;
-                if n == 0 { break }
-                raopcl.accept_frames().await?;
-                raopcl.send_chunk(&buf[0..n], &mut playtime).await?;
-                frames.add_assign(Frames::from_usize(n, 4));
-            }
-            Status::Paused => {
-                unimplemented!();
-            }
-            Status::Stopped => {
-                break;
-            }
-        }
+    while status.load() == Status::Playing {
+        let chunk = match chunks.recv().await {
+            Some(chunk) => chunk?,
+            None => break,
+        };
+
+        raopcl.accept_frames().await?;
+        raopcl.send_chunk(&chunk, &mut playtime).await?;
+        frames.add_assign(Frames::from_usize(chunk.len(), output_format.bytes_per_frame()));
     }
 
     status_logger.stop();
-    raopcl.teardown().await
+    raopcl.teardown().await?;
+
+    Ok(())
 }
@@ -1,21 +1,39 @@
-use crate::serialization::Serializable;
+use crate::serialization::{read_exact_vec, Serializable};
 
-use byteorder::{BE, WriteBytesExt};
+use byteorder::{BE, ByteOrder, WriteBytesExt};
 
-use std::io::{self, Write};
+use std::io::{self, Read, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+const MAX_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum MetaDataValue {
     Byte(u8),
+    Short(u16),
+    Int(u32),
+    Long(u64),
+    // seconds since the UNIX epoch on the wire
+    Date(SystemTime),
+    Version(u16, u16),
     String(String),
     List(Vec<MetaDataItem>),
+    // tags not in the table are kept as they were received
+    Bytes(Vec<u8>),
 }
 
 impl Serializable for MetaDataValue {
     fn size(&self) -> usize {
         match self {
             MetaDataValue::Byte(_) => 4 + 1,
+            MetaDataValue::Short(_) => 4 + 2,
+            MetaDataValue::Int(_) => 4 + 4,
+            MetaDataValue::Long(_) => 4 + 8,
+            MetaDataValue::Date(_) => 4 + 4,
+            MetaDataValue::Version(_, _) => 4 + 4,
             MetaDataValue::String(value) => 4 + value.len(),
             MetaDataValue::List(items) => 4 + items.iter().fold(0, |sum, val| sum + val.size()),
+            MetaDataValue::Bytes(value) => 4 + value.len(),
         }
     }
 
@@ -24,20 +42,140 @@ impl Serializable for MetaDataValue {
 
         match self {
             MetaDataValue::Byte(value) => writer.write_u8(*value)?,
+            MetaDataValue::Short(value) => writer.write_u16::<BE>(*value)?,
+            MetaDataValue::Int(value) => writer.write_u32::<BE>(*value)?,
+            MetaDataValue::Long(value) => writer.write_u64::<BE>(*value)?,
+            MetaDataValue::Date(value) => writer.write_u32::<BE>(value.duration_since(UNIX_EPOCH).map(|since| since.as_secs() as u32).unwrap_or(0))?,
+            MetaDataValue::Version(major, minor) => {
+                writer.write_u16::<BE>(*major)?;
+                writer.write_u16::<BE>(*minor)?;
+            }
             MetaDataValue::String(value) => write!(writer, "{}", value)?,
             MetaDataValue::List(items) => for item in items.iter() { item.serialize(writer)? },
+            MetaDataValue::Bytes(value) => writer.write_all(value)?,
         }
 
         Ok(())
     }
 }
 
+#[derive(Clone, Copy)]
+enum TagType {
+    Byte,
+    Short,
+    Int,
+    Long,
+    Date,
+    Version,
+    String,
+    List,
+}
+
+fn tag_type(code: &[u8; 4]) -> Option<TagType> {
+    match code {
+        b"mlit" | b"mlcl" | b"mcon" | b"mdcl" | b"msrv" | b"mccr" | b"mlog" | b"mupd" | b"avdb" | b"adbs" | b"aply" | b"apso" | b"cmst" | b"casp" | b"cmgt" => Some(TagType::List),
+        b"mikd" | b"caps" | b"cash" | b"carp" | b"cavs" | b"cafs" | b"asrv" | b"asur" | b"asdk" | b"msau" | b"mslr" => Some(TagType::Byte),
+        b"astn" | b"astc" | b"asdn" | b"asdc" | b"asyr" | b"asbt" | b"asbr" | b"mshc" => Some(TagType::Short),
+        b"mstt" | b"miid" | b"mcti" | b"mimc" | b"mrco" | b"mtco" | b"mlid" | b"musr" | b"mstm" | b"astm" | b"assr" | b"assz" | b"asst" | b"assp" | b"cmsr" | b"cant" | b"cast" | b"cmvo" | b"caas" | b"caar" => Some(TagType::Int),
+        b"mper" | b"asai" | b"mpco" | b"cmpg" => Some(TagType::Long),
+        b"asda" | b"asdm" | b"mstc" => Some(TagType::Date),
+        b"mpro" | b"apro" => Some(TagType::Version),
+        b"minm" | b"asar" | b"asal" | b"asaa" | b"asgn" | b"ascp" | b"asfm" | b"ascm" | b"asdt" | b"asul" | b"mcna" | b"msts" | b"cann" | b"cana" | b"canl" | b"cang" | b"cmnm" => Some(TagType::String),
+        _ => None,
+    }
+}
+
+fn read_value(code: &[u8; 4], data: Vec<u8>, depth: usize) -> io::Result<MetaDataValue> {
+    let value = match (tag_type(code), data.len()) {
+        (Some(TagType::Byte), 1) => MetaDataValue::Byte(data[0]),
+        (Some(TagType::Short), 2) => MetaDataValue::Short(BE::read_u16(&data)),
+        (Some(TagType::Int), 4) => MetaDataValue::Int(BE::read_u32(&data)),
+        (Some(TagType::Long), 8) => MetaDataValue::Long(BE::read_u64(&data)),
+        (Some(TagType::Date), 4) => MetaDataValue::Date(UNIX_EPOCH + Duration::from_secs(u64::from(BE::read_u32(&data)))),
+        (Some(TagType::Version), 4) => MetaDataValue::Version(BE::read_u16(&data[0..2]), BE::read_u16(&data[2..4])),
+        (Some(TagType::String), _) => match String::from_utf8(data) {
+            Ok(value) => MetaDataValue::String(value),
+            Err(err) => MetaDataValue::Bytes(err.into_bytes()),
+        },
+        (Some(TagType::List), _) => {
+            let mut items = Vec::new();
+            let mut reader = &data[..];
+
+            while !reader.is_empty() {
+                items.push(MetaDataItem::read_item(&mut reader, depth + 1)?);
+            }
+
+            MetaDataValue::List(items)
+        }
+        // unknown tags, or known ones with an unexpected size
+        _ => MetaDataValue::Bytes(data),
+    };
+
+    Ok(value)
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct MetaDataItem {
     code: [u8; 4],
     value: MetaDataValue,
 }
 
 impl MetaDataItem {
+    pub fn new(code: [u8; 4], value: MetaDataValue) -> MetaDataItem {
+        MetaDataItem { code, value }
+    }
+
+    pub fn code(&self) -> &[u8; 4] {
+        &self.code
+    }
+
+    pub fn value(&self) -> &MetaDataValue {
+        &self.value
+    }
+
+    // finds the first item with the given code, searching lists depth first
+    pub fn find(&self, code: &[u8; 4]) -> Option<&MetaDataItem> {
+        if &self.code == code {
+            return Some(self);
+        }
+
+        match self.value {
+            MetaDataValue::List(ref items) => items.iter().find_map(|item| item.find(code)),
+            _ => None,
+        }
+    }
+
+    pub fn deserialize(reader: &mut dyn Read) -> io::Result<MetaDataItem> {
+        MetaDataItem::read_item(reader, 0)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> io::Result<MetaDataItem> {
+        let mut reader = data;
+        let item = MetaDataItem::deserialize(&mut reader)?;
+
+        if !reader.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "trailing data after item"));
+        }
+
+        Ok(item)
+    }
+
+    fn read_item(reader: &mut dyn Read, depth: usize) -> io::Result<MetaDataItem> {
+        if depth > MAX_DEPTH {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "nesting too deep"));
+        }
+
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header)?;
+
+        let code = [header[0], header[1], header[2], header[3]];
+        let len = BE::read_u32(&header[4..8]) as u64;
+
+        let data = read_exact_vec(reader, len, "item")?;
+
+        Ok(MetaDataItem { code, value: read_value(&code, data, depth)? })
+    }
+
     pub fn listing_item(content: Vec<MetaDataItem>) -> MetaDataItem {
         MetaDataItem { code: *b"mlit", value: MetaDataValue::List(content) }
     }
@@ -57,6 +195,50 @@ impl MetaDataItem {
     pub fn song_album(album: &str) -> MetaDataItem {
         MetaDataItem { code: *b"asal", value: MetaDataValue::String(album.to_owned()) }
     }
+
+    pub fn song_genre(genre: &str) -> MetaDataItem {
+        MetaDataItem { code: *b"asgn", value: MetaDataValue::String(genre.to_owned()) }
+    }
+
+    pub fn song_composer(composer: &str) -> MetaDataItem {
+        MetaDataItem { code: *b"ascp", value: MetaDataValue::String(composer.to_owned()) }
+    }
+
+    pub fn song_track_number(number: u16) -> MetaDataItem {
+        MetaDataItem { code: *b"astn", value: MetaDataValue::Short(number) }
+    }
+
+    pub fn song_track_count(count: u16) -> MetaDataItem {
+        MetaDataItem { code: *b"astc", value: MetaDataValue::Short(count) }
+    }
+
+    pub fn song_disc_number(number: u16) -> MetaDataItem {
+        MetaDataItem { code: *b"asdn", value: MetaDataValue::Short(number) }
+    }
+
+    pub fn song_disc_count(count: u16) -> MetaDataItem {
+        MetaDataItem { code: *b"asdc", value: MetaDataValue::Short(count) }
+    }
+
+    pub fn song_time(duration: Duration) -> MetaDataItem {
+        MetaDataItem { code: *b"astm", value: MetaDataValue::Int(duration.as_millis() as u32) }
+    }
+
+    pub fn song_year(year: u16) -> MetaDataItem {
+        MetaDataItem { code: *b"asyr", value: MetaDataValue::Short(year) }
+    }
+
+    pub fn song_date_added(date: SystemTime) -> MetaDataItem {
+        MetaDataItem { code: *b"asda", value: MetaDataValue::Date(date) }
+    }
+
+    pub fn persistent_id(id: u64) -> MetaDataItem {
+        MetaDataItem { code: *b"mper", value: MetaDataValue::Long(id) }
+    }
+
+    pub fn protocol_version(major: u16, minor: u16) -> MetaDataItem {
+        MetaDataItem { code: *b"mpro", value: MetaDataValue::Version(major, minor) }
+    }
 }
 
 impl Serializable for MetaDataItem {
@@ -67,3 +249,148 @@ impl Serializable for MetaDataItem {
         self.value.serialize(writer)
     }
 }
+
+#[derive(Debug, Clone, Default)]
+pub struct TrackMetadata {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub genre: Option<String>,
+    pub composer: Option<String>,
+    pub track_number: Option<u16>,
+    pub track_count: Option<u16>,
+    pub disc_number: Option<u16>,
+    pub disc_count: Option<u16>,
+    pub year: Option<u16>,
+    pub duration: Option<Duration>,
+    pub persistent_id: Option<u64>,
+}
+
+impl TrackMetadata {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn to_listing_item(&self) -> MetaDataItem {
+        // a listing item describes a song, mikd 2
+        let mut items = vec![MetaDataItem::item_kind(2)];
+
+        if let Some(ref title) = self.title { items.push(MetaDataItem::item_name(title)); }
+        if let Some(ref artist) = self.artist { items.push(MetaDataItem::song_artist(artist)); }
+        if let Some(ref album) = self.album { items.push(MetaDataItem::song_album(album)); }
+        if let Some(ref genre) = self.genre { items.push(MetaDataItem::song_genre(genre)); }
+        if let Some(ref composer) = self.composer { items.push(MetaDataItem::song_composer(composer)); }
+        if let Some(number) = self.track_number { items.push(MetaDataItem::song_track_number(number)); }
+        if let Some(count) = self.track_count { items.push(MetaDataItem::song_track_count(count)); }
+        if let Some(number) = self.disc_number { items.push(MetaDataItem::song_disc_number(number)); }
+        if let Some(count) = self.disc_count { items.push(MetaDataItem::song_disc_count(count)); }
+        if let Some(year) = self.year { items.push(MetaDataItem::song_year(year)); }
+        if let Some(duration) = self.duration { items.push(MetaDataItem::song_time(duration)); }
+        if let Some(id) = self.persistent_id { items.push(MetaDataItem::persistent_id(id)); }
+
+        MetaDataItem::listing_item(items)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{MetaDataItem, MetaDataValue};
+
+    use crate::serialization::Serializable;
+
+    #[test]
+    fn test_serialize_values() {
+        assert_eq!(super::MetaDataItem::song_track_number(7).as_bytes(), b"astn\x00\x00\x00\x02\x00\x07");
+        assert_eq!(super::MetaDataItem::song_time(Duration::from_millis(215_500)).as_bytes(), b"astm\x00\x00\x00\x04\x00\x03\x49\xCC");
+        assert_eq!(super::MetaDataItem::persistent_id(0x0102_0304_0506_0708).as_bytes(), b"mper\x00\x00\x00\x08\x01\x02\x03\x04\x05\x06\x07\x08");
+        assert_eq!(super::MetaDataItem::song_date_added(UNIX_EPOCH + Duration::from_secs(0x5E0B_E100)).as_bytes(), b"asda\x00\x00\x00\x04\x5E\x0B\xE1\x00");
+        assert_eq!(super::MetaDataItem::protocol_version(2, 0).as_bytes(), b"mpro\x00\x00\x00\x04\x00\x02\x00\x00");
+    }
+
+    #[test]
+    fn test_track_metadata() {
+        let track = super::TrackMetadata {
+            title: Some(String::from("Song")),
+            track_number: Some(3),
+            duration: Some(Duration::from_secs(1)),
+            ..super::TrackMetadata::new()
+        };
+
+        let expected = [
+            b"mlit\x00\x00\x00\x2B".as_ref(),
+            b"mikd\x00\x00\x00\x01\x02",
+            b"minm\x00\x00\x00\x04Song",
+            b"astn\x00\x00\x00\x02\x00\x03",
+            b"astm\x00\x00\x00\x04\x00\x00\x03\xE8",
+        ].concat();
+
+        assert_eq!(track.to_listing_item().as_bytes(), expected);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let track = super::TrackMetadata {
+            title: Some(String::from("Sång")),
+            artist: Some(String::from("Artist")),
+            album: Some(String::from("Album")),
+            genre: Some(String::from("Jazz")),
+            composer: Some(String::from("Composer")),
+            track_number: Some(3),
+            track_count: Some(12),
+            disc_number: Some(1),
+            disc_count: Some(2),
+            year: Some(1959),
+            duration: Some(Duration::from_millis(325_000)),
+            persistent_id: Some(0xDEAD_BEEF_0000_0001),
+        };
+
+        let item = track.to_listing_item();
+        let decoded = MetaDataItem::from_bytes(&item.as_bytes()).unwrap();
+
+        assert_eq!(decoded, item);
+        assert_eq!(decoded.find(b"asyr").map(MetaDataItem::value), Some(&MetaDataValue::Short(1959)));
+
+        let dated = MetaDataItem::listing_item(vec![
+            MetaDataItem::song_date_added(UNIX_EPOCH + Duration::from_secs(1_577_836_800)),
+            MetaDataItem::protocol_version(2, 0),
+        ]);
+        assert_eq!(MetaDataItem::from_bytes(&dated.as_bytes()).unwrap(), dated);
+    }
+
+    #[test]
+    fn test_unknown_tags_are_kept() {
+        let data = b"mlit\x00\x00\x00\x1Cmikd\x00\x00\x00\x01\x02xxxx\x00\x00\x00\x03\x01\x02\x03astn\x00\x00\x00\x00".to_vec();
+        let item = MetaDataItem::from_bytes(&data).unwrap();
+
+        assert_eq!(item.find(b"xxxx").map(MetaDataItem::value), Some(&MetaDataValue::Bytes(vec![1, 2, 3])));
+        // a known tag with an unexpected size is kept raw too
+        assert_eq!(item.find(b"astn").map(MetaDataItem::value), Some(&MetaDataValue::Bytes(vec![])));
+        assert_eq!(item.as_bytes(), data);
+    }
+
+    #[test]
+    fn test_decode_dacp_status() {
+        let data = [
+            b"cmst\x00\x00\x00\x22".as_ref(),
+            b"mstt\x00\x00\x00\x04\x00\x00\x00\xC8",
+            b"caps\x00\x00\x00\x01\x04",
+            b"cann\x00\x00\x00\x05Title",
+        ].concat();
+
+        let item = MetaDataItem::from_bytes(&data).unwrap();
+
+        assert_eq!(item.code(), b"cmst");
+        assert_eq!(item.find(b"mstt").map(MetaDataItem::value), Some(&MetaDataValue::Int(200)));
+        assert_eq!(item.find(b"caps").map(MetaDataItem::value), Some(&MetaDataValue::Byte(4)));
+        assert_eq!(item.find(b"cann").map(MetaDataItem::value), Some(&MetaDataValue::String(String::from("Title"))));
+    }
+
+    #[test]
+    fn test_reject_truncated() {
+        assert!(MetaDataItem::from_bytes(b"mlit\x00\x00\x00\x10mikd\x00\x00\x00\x01\x02").is_err());
+        assert!(MetaDataItem::from_bytes(b"mlit\x00\x00\x00\x05mikd\x00").is_err());
+        assert!(MetaDataItem::from_bytes(b"mikd\x00\x00\x00\x01\x02\x00").is_err());
+    }
+}
@@ -1,7 +1,7 @@
 use std::io::{self, Read, Write};
 use std::time::{Duration, SystemTime};
 use std::fmt::{self, Formatter, Display};
-use std::ops::Sub;
+use std::ops::{Add, Sub};
 
 use byteorder::{BE, ReadBytesExt, WriteBytesExt};
 
@@ -9,7 +9,7 @@ use crate::frames::Frames;
 use crate::sample_rate::SampleRate;
 use crate::serialization::{Deserializable, Serializable};
 
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct NtpTime {
     seconds: u32,
     fraction: u32,
@@ -19,7 +19,12 @@ impl NtpTime {
     pub const ZERO: NtpTime = NtpTime { seconds: 0, fraction: 0 };
 
     pub fn now() -> NtpTime {
-        let unix = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
+        NtpTime::from_system_time(SystemTime::now())
+    }
+
+    pub fn from_system_time(time: SystemTime) -> NtpTime {
+        // clocks before the unix epoch are clamped to it
+        let unix = time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
 
         NtpTime {
             seconds: (unix.as_secs() + 0x83AA_7E80) as u32,
@@ -47,16 +52,27 @@ impl NtpTime {
     }
 }
 
+impl Add<Duration> for NtpTime {
+    type Output = NtpTime;
+
+    fn add(self, other: Duration) -> NtpTime {
+        let ntp = ((self.seconds as u64) << 32) | (self.fraction as u64);
+        let ntp = ntp + (other.as_secs() << 32) + (((other.subsec_nanos() as u64) << 32) / 1_000_000_000);
+
+        NtpTime {
+            seconds: (ntp >> 32) as u32,
+            fraction: ntp as u32,
+        }
+    }
+}
+
 impl Sub for NtpTime {
     type Output = Duration;
 
     fn sub(self, other: NtpTime) -> Duration {
-        if self.seconds < other.seconds {
-            panic!("Cannot create negative durations");
-        }
-
-        if self.seconds == other.seconds && self.fraction < other.fraction {
-            panic!("Cannot create negative durations");
+        // durations can't be negative, so saturate at zero
+        if self < other {
+            return Duration::new(0, 0);
         }
 
         let (secs, fraction) = if self.fraction < other.fraction {
@@ -98,3 +114,27 @@ impl Display for NtpTime {
         write!(f, "{}.{}", self.seconds, self.fraction)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::NtpTime;
+
+    #[test]
+    fn test_add_duration() {
+        let time = NtpTime { seconds: 10, fraction: 0x8000_0000 };
+
+        assert_eq!(time + Duration::from_secs(2), NtpTime { seconds: 12, fraction: 0x8000_0000 });
+        assert_eq!(time + Duration::from_millis(250), NtpTime { seconds: 10, fraction: 0xC000_0000 });
+
+        // the fraction carries over into the seconds
+        assert_eq!(time + Duration::from_millis(500), NtpTime { seconds: 11, fraction: 0 });
+        assert_eq!(time + Duration::from_millis(750), NtpTime { seconds: 11, fraction: 0x4000_0000 });
+
+        let later = time + Duration::new(3, 123_456_789);
+        let elapsed = later - time;
+        assert_eq!(elapsed.as_secs(), 3);
+        assert!((elapsed.subsec_nanos() as i64 - 123_456_789).abs() <= 1);
+    }
+}
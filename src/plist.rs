@@ -0,0 +1,287 @@
+use std::io;
+
+use byteorder::{BE, ByteOrder};
+
+const MAGIC: &[u8] = b"bplist00";
+const TRAILER_SIZE: usize = 32;
+const MAX_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlistValue {
+    Integer(u64),
+    String(String),
+    Data(Vec<u8>),
+    Dictionary(Vec<(String, PlistValue)>),
+}
+
+impl PlistValue {
+    pub fn get(&self, key: &str) -> Option<&PlistValue> {
+        match self {
+            PlistValue::Dictionary(entries) => entries.iter().find(|entry| entry.0 == key).map(|entry| &entry.1),
+            _ => None,
+        }
+    }
+
+    pub fn as_data(&self) -> Option<&[u8]> {
+        match self {
+            PlistValue::Data(data) => Some(data),
+            _ => None,
+        }
+    }
+}
+
+fn byte_size(value: u64) -> usize {
+    match value {
+        0..=0xFF => 1,
+        0x100..=0xFFFF => 2,
+        0x1_0000..=0xFFFF_FFFF => 4,
+        _ => 8,
+    }
+}
+
+fn write_sized(buf: &mut Vec<u8>, value: u64, size: usize) {
+    buf.extend_from_slice(&value.to_be_bytes()[8 - size..]);
+}
+
+fn write_marker(buf: &mut Vec<u8>, marker: u8, len: usize) {
+    if len < 15 {
+        buf.push(marker | len as u8);
+    } else {
+        let size = byte_size(len as u64);
+        buf.push(marker | 0x0F);
+        buf.push(0x10 | size.trailing_zeros() as u8);
+        write_sized(buf, len as u64, size);
+    }
+}
+
+fn count_objects(value: &PlistValue) -> usize {
+    match value {
+        PlistValue::Dictionary(entries) => 1 + entries.iter().map(|entry| 1 + count_objects(&entry.1)).sum::<usize>(),
+        _ => 1,
+    }
+}
+
+struct Writer {
+    objects: Vec<Vec<u8>>,
+    ref_size: usize,
+}
+
+impl Writer {
+    fn add(&mut self, value: &PlistValue) -> usize {
+        let index = self.objects.len();
+        self.objects.push(Vec::new());
+
+        let mut buf = Vec::new();
+
+        match value {
+            PlistValue::Integer(value) => {
+                buf.push(0x13);
+                write_sized(&mut buf, *value, 8);
+            }
+            PlistValue::String(value) if value.is_ascii() => {
+                write_marker(&mut buf, 0x50, value.len());
+                buf.extend_from_slice(value.as_bytes());
+            }
+            PlistValue::String(value) => {
+                let units = value.encode_utf16().collect::<Vec<u16>>();
+                write_marker(&mut buf, 0x60, units.len());
+                for unit in units { buf.extend_from_slice(&unit.to_be_bytes()); }
+            }
+            PlistValue::Data(value) => {
+                write_marker(&mut buf, 0x40, value.len());
+                buf.extend_from_slice(value);
+            }
+            PlistValue::Dictionary(entries) => {
+                let keys = entries.iter().map(|entry| self.add(&PlistValue::String(entry.0.clone()))).collect::<Vec<_>>();
+                let values = entries.iter().map(|entry| self.add(&entry.1)).collect::<Vec<_>>();
+
+                write_marker(&mut buf, 0xD0, entries.len());
+                for reference in keys.into_iter().chain(values) {
+                    write_sized(&mut buf, reference as u64, self.ref_size);
+                }
+            }
+        }
+
+        self.objects[index] = buf;
+        index
+    }
+}
+
+pub fn to_bytes(value: &PlistValue) -> Vec<u8> {
+    let count = count_objects(value);
+    let mut writer = Writer { objects: Vec::with_capacity(count), ref_size: byte_size(count as u64) };
+    writer.add(value);
+
+    let mut buf = MAGIC.to_vec();
+    let mut offsets = Vec::with_capacity(count);
+
+    for object in &writer.objects {
+        offsets.push(buf.len() as u64);
+        buf.extend_from_slice(object);
+    }
+
+    let offset_table = buf.len() as u64;
+    let offset_size = byte_size(offset_table);
+
+    for offset in offsets {
+        write_sized(&mut buf, offset, offset_size);
+    }
+
+    buf.extend_from_slice(&[0; 6]);
+    buf.push(offset_size as u8);
+    buf.push(writer.ref_size as u8);
+    write_sized(&mut buf, count as u64, 8);
+    write_sized(&mut buf, 0, 8);
+    write_sized(&mut buf, offset_table, 8);
+
+    buf
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    offsets: Vec<usize>,
+    ref_size: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn slice(&self, start: usize, len: usize) -> io::Result<&'a [u8]> {
+        self.data.get(start..start.checked_add(len).ok_or_else(|| invalid_data("length overflow"))?).ok_or_else(|| invalid_data("object out of bounds"))
+    }
+
+    fn read_sized(&self, start: usize, size: usize) -> io::Result<u64> {
+        Ok(BE::read_uint(self.slice(start, size)?, size))
+    }
+
+    // returns the length of an object and where its content starts
+    fn read_length(&self, offset: usize) -> io::Result<(usize, usize)> {
+        let marker = self.slice(offset, 1)?[0];
+
+        if marker & 0x0F != 0x0F {
+            return Ok(((marker & 0x0F) as usize, offset + 1));
+        }
+
+        let int_marker = self.slice(offset + 1, 1)?[0];
+        if int_marker & 0xF0 != 0x10 { return Err(invalid_data("invalid length marker")); }
+
+        let size = 1 << (int_marker & 0x0F);
+        if size > 8 { return Err(invalid_data("invalid length size")); }
+
+        Ok((self.read_sized(offset + 2, size)? as usize, offset + 2 + size))
+    }
+
+    fn read_object(&self, index: usize, depth: usize) -> io::Result<PlistValue> {
+        if depth > MAX_DEPTH { return Err(invalid_data("nesting too deep")); }
+
+        let offset = *self.offsets.get(index).ok_or_else(|| invalid_data("object reference out of bounds"))?;
+        let marker = self.slice(offset, 1)?[0];
+
+        match marker & 0xF0 {
+            0x10 => {
+                let size = 1 << (marker & 0x0F);
+                if size > 8 { return Err(invalid_data("integer too large")); }
+                Ok(PlistValue::Integer(self.read_sized(offset + 1, size)?))
+            }
+            0x40 => {
+                let (len, start) = self.read_length(offset)?;
+                Ok(PlistValue::Data(self.slice(start, len)?.to_vec()))
+            }
+            0x50 => {
+                let (len, start) = self.read_length(offset)?;
+                Ok(PlistValue::String(String::from_utf8_lossy(self.slice(start, len)?).into_owned()))
+            }
+            0x60 => {
+                let (len, start) = self.read_length(offset)?;
+                let units = self.slice(start, len * 2)?.chunks(2).map(BE::read_u16).collect::<Vec<u16>>();
+                Ok(PlistValue::String(String::from_utf16_lossy(&units)))
+            }
+            0xD0 => {
+                let (len, start) = self.read_length(offset)?;
+                let mut entries = Vec::with_capacity(len);
+
+                for i in 0..len {
+                    let key = self.read_sized(start + i * self.ref_size, self.ref_size)? as usize;
+                    let value = self.read_sized(start + (len + i) * self.ref_size, self.ref_size)? as usize;
+
+                    let key = match self.read_object(key, depth + 1)? {
+                        PlistValue::String(key) => key,
+                        _ => return Err(invalid_data("dictionary key is not a string")),
+                    };
+
+                    entries.push((key, self.read_object(value, depth + 1)?));
+                }
+
+                Ok(PlistValue::Dictionary(entries))
+            }
+            _ => Err(invalid_data("unsupported object type")),
+        }
+    }
+}
+
+pub fn from_bytes(data: &[u8]) -> io::Result<PlistValue> {
+    if data.len() < MAGIC.len() + TRAILER_SIZE || &data[0..MAGIC.len()] != MAGIC {
+        return Err(invalid_data("not a binary plist"));
+    }
+
+    let trailer = &data[data.len() - TRAILER_SIZE..];
+    let offset_size = trailer[6] as usize;
+    let ref_size = trailer[7] as usize;
+    let count = BE::read_u64(&trailer[8..16]) as usize;
+    let top = BE::read_u64(&trailer[16..24]) as usize;
+    let offset_table = BE::read_u64(&trailer[24..32]) as usize;
+
+    if offset_size == 0 || offset_size > 8 || ref_size == 0 || ref_size > 8 {
+        return Err(invalid_data("invalid trailer"));
+    }
+
+    let mut reader = Reader { data, offsets: Vec::new(), ref_size };
+
+    for i in 0..count {
+        reader.offsets.push(reader.read_sized(offset_table + i * offset_size, offset_size)? as usize);
+    }
+
+    reader.read_object(top, 0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::PlistValue;
+
+    #[test]
+    fn test_round_trip() {
+        let value = PlistValue::Dictionary(vec![
+            (String::from("method"), PlistValue::String(String::from("pin"))),
+            (String::from("user"), PlistValue::String(String::from("366B4165DD64AD3A"))),
+            (String::from("pk"), PlistValue::Data((0..=255).collect())),
+            (String::from("count"), PlistValue::Integer(42)),
+            (String::from("name"), PlistValue::String(String::from("Vardagsrum ☀"))),
+        ]);
+
+        let bytes = super::to_bytes(&value);
+
+        assert_eq!(&bytes[0..8], b"bplist00");
+        assert_eq!(super::from_bytes(&bytes).unwrap(), value);
+    }
+
+    #[test]
+    fn test_encode_small_dictionary() {
+        let value = PlistValue::Dictionary(vec![(String::from("a"), PlistValue::Data(vec![1, 2]))]);
+
+        let expected = [
+            b"bplist00".as_ref(),
+            &[0xD1, 0x01, 0x02],
+            &[0x51, b'a'],
+            &[0x42, 0x01, 0x02],
+            &[0x08, 0x0B, 0x0D],
+            &[0, 0, 0, 0, 0, 0, 1, 1],
+            &[0, 0, 0, 0, 0, 0, 0, 3],
+            &[0, 0, 0, 0, 0, 0, 0, 0],
+            &[0, 0, 0, 0, 0, 0, 0, 0x10],
+        ].concat();
+
+        assert_eq!(super::to_bytes(&value), expected);
+    }
+}
@@ -1,28 +1,31 @@
-use crate::codec::Codec;
-use crate::crypto::Crypto;
+use crate::artwork::{Artwork, MAX_ARTWORK_DIMENSION};
+use crate::codec::AudioEncoder;
+use crate::crypto::AppleChallenge;
+use crate::deadline::within;
 use crate::frames::Frames;
 use crate::keepalive_controller::KeepaliveController;
 use crate::meta_data::MetaDataItem;
 use crate::ntp::NtpTime;
+use crate::raop_error::RaopError;
 use crate::raop_params::RaopParams;
 use crate::rtp::{RtpHeader, RtpAudioPacket};
-use crate::rtsp_client::RTSPClient;
+use crate::rtsp_client::{RTSPClient, RtspError};
 use crate::sample_rate::SampleRate;
 use crate::serialization::{Serializable};
 use crate::sync_controller::SyncController;
 use crate::timing_controller::TimingController;
 use crate::volume::Volume;
 
+use std::io;
 use std::net::{IpAddr, SocketAddr};
-use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 
 use beefeater::Beefeater;
 use rand::random;
-use log::{error, info, debug, trace};
+use log::{error, warn, info, debug, trace};
 use tokio::net::UdpSocket;
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, Mutex, MutexGuard};
 use tokio::time::delay_for;
 
 const LATENCY_MIN: Frames = Frames::new(11025);
@@ -30,31 +33,37 @@ const LATENCY_MIN: Frames = Frames::new(11025);
 pub const MAX_BACKLOG: u16 = 512;
 pub const MAX_SAMPLES_PER_CHUNK: Frames = Frames::new(352);
 
-pub fn analyse_setup(setup_headers: Vec<(String, String)>) -> Result<(u16, u16, u16), Box<dyn std::error::Error>> {
+// between failed reconnect attempts, grows with every one as a rebooting receiver takes a while to come back
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+pub fn analyse_setup(setup_headers: Vec<(String, String)>) -> Result<(u16, u16, u16), RtspError> {
     // get transport (port ...) info
-    let transport_header = setup_headers.iter().find(|header| header.0.to_lowercase() == "transport").map(|header| header.1.as_str());
+    let transport_header = match setup_headers.iter().find(|header| header.0.to_lowercase() == "transport") {
+        Some(header) => header.1.as_str(),
+        None => {
+            error!("no transport in response");
+            return Err(RtspError::MissingHeader("Transport"));
+        }
+    };
 
-    if transport_header.is_none() {
-        error!("no transport in response");
-        panic!("no transport in response");
-    }
+    let invalid = || RtspError::InvalidHeader("Transport", transport_header.to_owned());
 
     let mut audio_port: u16 = 0;
     let mut ctrl_port: u16 = 0;
     let mut time_port: u16 = 0;
 
-    for token in transport_header.unwrap().split(';') {
+    for token in transport_header.split(';') {
         match token.split('=').collect::<Vec<&str>>().as_slice() {
-            ["server_port", port] => audio_port = port.parse()?,
-            ["control_port", port] => ctrl_port = port.parse()?,
-            ["timing_port", port] => time_port = port.parse()?,
+            ["server_port", port] => audio_port = port.parse().map_err(|_| invalid())?,
+            ["control_port", port] => ctrl_port = port.parse().map_err(|_| invalid())?,
+            ["timing_port", port] => time_port = port.parse().map_err(|_| invalid())?,
             _ => {},
         }
     }
 
     if audio_port == 0 || ctrl_port == 0 {
         error!("missing a RTP port in response");
-        panic!("missing a RTP port in response");
+        return Err(invalid());
     }
 
     if time_port == 0 {
@@ -68,6 +77,7 @@ pub fn analyse_setup(setup_headers: Vec<(String, String)>) -> Result<(u16, u16,
 enum RaopState {
     Flushing,
     Streaming,
+    Paused,
 }
 
 #[derive(Clone, Copy, PartialEq)]
@@ -77,15 +87,35 @@ struct MetaDataCapabilities {
     progress: bool,
 }
 
-impl FromStr for MetaDataCapabilities {
-    type Err = ();
+impl MetaDataCapabilities {
+    fn from_txt(md: &str) -> MetaDataCapabilities {
+        MetaDataCapabilities {
+            text: md.contains('0'),
+            artwork: md.contains('1'),
+            progress: md.contains('2'),
+        }
+    }
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(MetaDataCapabilities {
-            text: s.contains('0'),
-            artwork: s.contains('1'),
-            progress: s.contains('2'),
-        })
+// the position in the track of the frame sent with the anchor timestamp
+#[derive(Clone, Copy)]
+struct Progress {
+    start: Duration,
+    end: Duration,
+    position: Duration,
+    anchor: Option<Frames>,
+}
+
+impl Progress {
+    fn parameter(&self, latency: Frames, sample_rate: SampleRate) -> Option<String> {
+        let anchor = self.anchor?;
+
+        let start = anchor - Frames::from_duration(self.position.checked_sub(self.start).unwrap_or_default(), sample_rate);
+        let end = start + Frames::from_duration(self.end.checked_sub(self.start).unwrap_or_default(), sample_rate);
+        let curr = anchor - latency;
+
+        // RTP timestamps are 32 bits on the wire
+        Some(format!("progress: {}/{}/{}\r\n", u64::from(start) as u32, u64::from(curr) as u32, u64::from(end) as u32))
     }
 }
 
@@ -103,9 +133,67 @@ pub struct Status {
     start_ts: Frames,
     first_ts: Frames,
     first_pkt: bool,
+    progress: Option<Progress>,
     pub backlog: [Option<BacklogEntry>; 512usize],
 }
 
+impl Status {
+    fn new() -> Status {
+        Status {
+            state: RaopState::Flushing,
+            seq_number: random(),
+            head_ts: Frames::new(0),
+            pause_ts: Frames::new(0),
+            start_ts: Frames::new(0),
+            first_ts: Frames::new(0),
+            first_pkt: true,
+            progress: None,
+            // FIXME: https://github.com/rust-lang/rust/issues/49147
+            backlog: [
+                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
+                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
+                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
+                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
+                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
+                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
+                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
+                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
+                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
+                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
+                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
+                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
+                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
+                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
+                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
+                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
+            ],
+        }
+    }
+
+    // restarts the stream at the position it was in, for a session without anything buffered
+    // a paused stream stays paused, and a scheduled start is kept
+    fn restart(&mut self, sample_rate: SampleRate) {
+        let head_ts = self.head_ts;
+
+        if let Some(ref mut progress) = self.progress {
+            if let Some(anchor) = progress.anchor.take() {
+                progress.position += (head_ts - anchor) / sample_rate;
+            }
+        }
+
+        if self.state == RaopState::Streaming {
+            self.state = RaopState::Flushing;
+        }
+
+        self.pause_ts = Frames::new(0);
+        self.first_pkt = true;
+
+        for entry in self.backlog.iter_mut() {
+            *entry = None;
+        }
+    }
+}
+
 pub struct SaneAudio {
     pub avail: u64,
     pub select: u64,
@@ -114,7 +202,6 @@ pub struct SaneAudio {
 
 pub struct Sane {
     pub ctrl: u64,
-    pub time: u64,
     pub audio: SaneAudio,
 }
 
@@ -122,12 +209,16 @@ impl Sane {
     fn new() -> Sane {
         Sane {
             ctrl: 0,
-            time: 0,
             audio: SaneAudio { avail: 0, select: 0, send: 0 },
         }
     }
 }
 
+// the connection is gone after a failed read or write, and after a timeout closed it
+fn is_connection_lost(err: &RaopError) -> bool {
+    matches!(err, RaopError::Connection(_) | RaopError::Timeout(_))
+}
+
 fn format_ip_for_sdp(ip: IpAddr) -> String {
     match ip {
         IpAddr::V4(ip) => format!("IN IP4 {}", ip.to_string()),
@@ -135,65 +226,225 @@ fn format_ip_for_sdp(ip: IpAddr) -> String {
     }
 }
 
-pub struct RaopClient {
-    // Immutable properties
-    auth: bool,
-
-    codec: Codec,
-    crypto: Crypto,
-    meta_data_capabilities: MetaDataCapabilities,
-
-    secret: Option<String>,
-    et: Option<String>,
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RaopEvent {
+    // the receiver stopped answering, or closed the connection
+    ConnectionLost,
+    // setting up a new session, attempts count from 1
+    Reconnecting(u32),
+    // streaming continues in the new session
+    Reconnected,
+    // no new session could be set up, sending fails from now on
+    ReconnectFailed,
+}
 
+// everything tied to the connection to the receiver, replaced when the connection is lost
+struct Session {
     latency: Frames,
-    ssrc: u32,
 
-    // Mutable properties
     keepalive_controller: KeepaliveController,
     rtp_audio: Arc<Mutex<UdpSocket>>,
     sync_controller: SyncController,
     timing_controller: TimingController,
 
+    rtsp_client: Arc<Mutex<RTSPClient>>,
+
+    // set by the keepalive and the control channel when the receiver is gone
+    lost: Arc<Beefeater<bool>>,
+}
+
+impl Session {
+    fn stop(&mut self) {
+        self.keepalive_controller.stop();
+        self.sync_controller.stop();
+        self.timing_controller.stop();
+    }
+}
+
+pub struct RaopClient {
+    // Immutable properties
+    params: RaopParams,
+    remote: SocketAddr,
+
+    meta_data_capabilities: MetaDataCapabilities,
+    ssrc: u32,
+
+    // Mutable properties
+    session: Mutex<Session>,
+
+    // that of the current session, readable without locking it
+    latency: Beefeater<Frames>,
+
     sane: Arc<Mutex<Sane>>,
     retransmit: Arc<Beefeater<u32>>,
 
     status: Arc<Mutex<Status>>,
 
+    // replayed when the connection is recovered
     volume: Arc<Beefeater<Option<Volume>>>,
+    meta_data: Mutex<Option<MetaDataItem>>,
+    artwork: Mutex<Option<Artwork>>,
 
-    rtsp_client: Arc<Mutex<RTSPClient>>,
+    events: mpsc::UnboundedSender<RaopEvent>,
+    event_receiver: Option<mpsc::UnboundedReceiver<RaopEvent>>,
 }
 
 impl RaopClient {
-    pub async fn connect(params: RaopParams, remote: SocketAddr) -> Result<RaopClient, Box<dyn std::error::Error>> {
-        if params.codec.chunk_length() > MAX_SAMPLES_PER_CHUNK {
-            panic!("Chunk length must below {}", MAX_SAMPLES_PER_CHUNK);
+    // the receiver only shows its PIN once pairing has started, so ask for it in between
+    pub async fn pair<F>(remote: SocketAddr, read_pin: F) -> Result<String, RaopError>
+        where F: FnOnce() -> io::Result<String>
+    {
+        let sid = format!("{:010}", random::<u32>());
+        let sci = format!("{:016x}", random::<u64>());
+
+        let mut rtsp_client = RTSPClient::connect(remote, &sid, "iTunes/7.6.2 (Windows; N;)", &[("Client-Instance", &sci)], None).await?;
+
+        rtsp_client.pair_pin_start().await?;
+        let pin = read_pin().map_err(|err| RaopError::Authentication(Box::new(err)))?;
+
+        let secret = rtsp_client.pair_setup(pin.trim()).await?;
+
+        Ok(secret)
+    }
+
+    // returns the fallback codec the receiver accepted, if it rejected the preferred one
+    async fn negotiate_codec(rtsp_client: &mut RTSPClient, params: &RaopParams, session: &str, remote: SocketAddr) -> Result<Option<Box<dyn AudioEncoder>>, RaopError> {
+        let mut fallbacks = params.codec.fallbacks().into_iter();
+        let mut accepted = None;
+
+        loop {
+            let codec = accepted.as_deref().unwrap_or_else(|| params.codec.as_ref());
+            let sdp = format!("{}{}{}", session, codec.sdp(), params.crypto.sdp()?);
+
+            let err = match RaopClient::announce(rtsp_client, params, &sdp, remote).await {
+                Ok(()) => return Ok(accepted),
+                Err(err) => err,
+            };
+
+            // an unauthorized announce was already retried with the password, and is an authentication error
+            let rejected = matches!(err, RaopError::Protocol(RtspError::ClientError { .. }));
+
+            match fallbacks.next() {
+                Some(fallback) if rejected => {
+                    warn!("receiver rejected {} coding with {}-bit samples, trying {} with {}-bit samples", codec.name(), codec.sample_size(), fallback.name(), fallback.sample_size());
+                    accepted = Some(fallback);
+                }
+                _ => return Err(err),
+            }
         }
+    }
 
-        let mut latency = std::cmp::max(params.desired_latency, LATENCY_MIN);
+    async fn announce(rtsp_client: &mut RTSPClient, params: &RaopParams, sdp: &str, remote: SocketAddr) -> Result<(), RaopError> {
+        if params.auth && !params.crypto.is_clear() {
+            let challenge = AppleChallenge::new();
 
-        // strcpy(raopcld->DACP_id, DACP_id ? DACP_id : "");
-        // strcpy(raopcld->active_remote, active_remote ? active_remote : "");
+            rtsp_client.add_exthds("Apple-Challenge", &challenge.to_string());
+            let announce_headers = rtsp_client.announce_sdp(sdp).await;
+            rtsp_client.mark_del_exthds("Apple-Challenge");
+
+            let announce_headers = announce_headers?;
+            let response = announce_headers.iter().find(|header| header.0.to_lowercase() == "apple-response").map(|header| header.1.as_str());
+
+            if let Err(err) = challenge.verify(response, remote.ip(), params.mac_address) {
+                error!("receiver failed the Apple-Challenge");
+                return Err(err.into());
+            }
+
+            info!("receiver answered the Apple-Challenge");
+        } else {
+            rtsp_client.announce_sdp(sdp).await?;
+        }
+
+        Ok(())
+    }
 
-        let meta_data_capabilities = params.md.as_ref().map_or("", String::as_str).parse::<MetaDataCapabilities>().unwrap();
+    // dropping the returned future cancels connecting, and closes everything opened so far
+    pub async fn connect(mut params: RaopParams, remote: SocketAddr) -> Result<RaopClient, RaopError> {
+        if params.codec.chunk_length() > MAX_SAMPLES_PER_CHUNK {
+            return Err(RaopError::Codec(format!("chunk length {} is above the maximum of {}", params.codec.chunk_length(), MAX_SAMPLES_PER_CHUNK)));
+        }
 
-        info!("using {} coding", params.codec);
+        info!("using {} coding", params.codec.name());
 
+        let status = Arc::new(Mutex::new(Status::new()));
+        let sane = Arc::new(Mutex::new(Sane::new()));
         let retransmit = Arc::new(Beefeater::new(0));
-        let sane_mutex = Arc::new(Mutex::new(Sane::new()));
 
+        let (session, accepted) = RaopClient::open_session(&params, remote, &status, &sane, &retransmit).await?;
+
+        if let Some(codec) = accepted {
+            params.codec = codec;
+        }
+
+        let meta_data_capabilities = MetaDataCapabilities::from_txt(params.md.as_ref().map_or("", String::as_str));
+        let (events, event_receiver) = mpsc::unbounded_channel();
+
+        Ok(RaopClient {
+            // Immutable properties
+            params,
+            remote,
+
+            meta_data_capabilities,
+            ssrc: random(),
+
+            // Mutable properties
+            latency: Beefeater::new(session.latency),
+            session: Mutex::new(session),
+
+            sane,
+            retransmit,
+
+            status,
+
+            volume: Arc::new(Beefeater::new(None)),
+            meta_data: Mutex::new(None),
+            artwork: Mutex::new(None),
+
+            events,
+            event_receiver: Some(event_receiver),
+        })
+    }
+
+    async fn open_session(params: &RaopParams, remote: SocketAddr, status: &Arc<Mutex<Status>>, sane: &Arc<Mutex<Sane>>, retransmit: &Arc<Beefeater<u32>>) -> Result<(Session, Option<Box<dyn AudioEncoder>>), RaopError> {
         let sid = format!("{:010}", random::<u32>());
         let sci = format!("{:016x}", random::<u64>());
 
+        let rtsp_client = RTSPClient::connect(remote, &sid, "iTunes/7.6.2 (Windows; N;)", &[("Client-Instance", &sci)], params.connect_timeout).await?;
+
+        RaopClient::start_session(params, rtsp_client, &sid, remote, status, sane, retransmit).await
+    }
+
+    async fn start_session(params: &RaopParams, mut rtsp_client: RTSPClient, sid: &str, remote: SocketAddr, status: &Arc<Mutex<Status>>, sane: &Arc<Mutex<Sane>>, retransmit: &Arc<Beefeater<u32>>) -> Result<(Session, Option<Box<dyn AudioEncoder>>), RaopError> {
+        rtsp_client.set_request_timeout(params.request_timeout);
+
+        match within(params.handshake_timeout, RaopClient::handshake(params, rtsp_client, sid, remote, status, sane, retransmit)).await {
+            Some(result) => result,
+            None => {
+                error!("receiver did not complete the handshake in time");
+                Err(RaopError::Timeout("handshake"))
+            }
+        }
+    }
+
+    async fn handshake(params: &RaopParams, mut rtsp_client: RTSPClient, sid: &str, remote: SocketAddr, status_mutex: &Arc<Mutex<Status>>, sane_mutex: &Arc<Mutex<Sane>>, retransmit: &Arc<Beefeater<u32>>) -> Result<(Session, Option<Box<dyn AudioEncoder>>), RaopError> {
+        let mut latency = std::cmp::max(params.desired_latency, LATENCY_MIN);
+
+        // strcpy(raopcld->DACP_id, DACP_id ? DACP_id : "");
+        // strcpy(raopcld->active_remote, active_remote ? active_remote : "");
+
         // RTSP misc setup
-        let mut rtsp_client = RTSPClient::connect(remote, &sid, "iTunes/7.6.2 (Windows; N;)", &[("Client-Instance", &sci)]).await?;
         // FIXME:
         // if self.DACP_id[0] != 0 { rtspcl_add_eself.((*s_elient..cnew("DACP-ID").unwrap().into_raw(), self.DACP_id); }
         // if self.active_remote[0] != 0 { rtspclself.esel.f_ient((.s_elient.new("Active-Remote").unwrap().into_raw(), self.active_remote)?;
 
         info!("local interface {}", rtsp_client.local_ip()?);
 
+        if params.password_required && params.password.is_none() {
+            warn!("receiver is password protected but no password was given");
+        }
+
+        rtsp_client.set_password(params.password.clone());
+
         // RTSP pairing verify for AppleTV
         if let Some(ref secret) = params.secret {
             rtsp_client.pair_verify(secret).await?;
@@ -206,33 +457,23 @@ impl RaopClient {
 
         let local_ip = rtsp_client.local_ip()?;
 
-        let mut sdp = format!(
+        let session = format!(
             "v=0\r\no=iTunes {} 0 {}\r\ns=iTunes\r\nc={}\r\nt=0 0\r\n",
             sid,
             format_ip_for_sdp(local_ip),
             format_ip_for_sdp(remote.ip()),
         );
 
-        sdp.push_str(params.codec.sdp().as_str());
-        sdp.push_str(params.crypto.sdp().as_str());
-
         // AppleTV expects now the timing port ot be opened BEFORE the setup message
         let rtp_time = UdpSocket::bind((local_ip, 0)).await?;
         let local_time_port = rtp_time.local_addr()?.port();
         let timing_controller = TimingController::start(rtp_time);
 
-        // RTSP ANNOUNCE
-        if params.auth && !params.crypto.is_clear() {
-            panic!("Not implemented");
-            // let seed_sac: [u8; 16] = random();
-            // base64_encode(&seed.sac, 16, &sac);
-            // remove_char_from_string(sac, '=');
-            // rtsp_client.add_exthds("Apple-Challenge", &sac)?;
-            // rtsp_client.announce_sdp(&sdp)?;
-            // rtsp_client.mark_del_exthds("Apple-Challenge")?;
-        } else {
-            rtsp_client.announce_sdp(&sdp).await?;
-        }
+        let accepted = RaopClient::negotiate_codec(&mut rtsp_client, params, &session, remote).await?;
+        let codec = accepted.as_deref().unwrap_or_else(|| params.codec.as_ref());
+        let sample_rate = codec.sample_rate();
+
+        info!("receiver accepted {} coding with {}-bit samples", codec.name(), codec.sample_size());
 
         // open RTP sockets, need local ports here before sending SETUP
         let rtp_ctrl = UdpSocket::bind((local_ip, 0)).await?;
@@ -254,103 +495,166 @@ impl RaopClient {
 
         let rtp_audio_mutex = Arc::new(Mutex::new(rtp_audio));
 
-        let status = Status {
-            state: RaopState::Flushing,
-            seq_number: random(),
-            head_ts: Frames::new(0),
-            pause_ts: Frames::new(0),
-            start_ts: Frames::new(0),
-            first_ts: Frames::new(0),
-            first_pkt: true,
-            // FIXME: https://github.com/rust-lang/rust/issues/49147
-            backlog: [
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-            ],
-        };
-
-        let record_headers = rtsp_client.record(status.seq_number + 1, NtpTime::now().into_timestamp(params.codec.sample_rate())).await?;
+        let seq_number = status_mutex.lock().await.seq_number;
+        let record_headers = rtsp_client.record(seq_number.wrapping_add(1), NtpTime::now().into_timestamp(sample_rate)).await?;
         let returned_latency = record_headers.iter().find(|header| header.0.to_lowercase() == "audio-latency").map(|header| header.1.as_str());
 
         if let Some(returned_latency) = returned_latency {
-            latency = std::cmp::max(latency, returned_latency.parse()?);
+            let returned_latency = returned_latency.parse().map_err(|_| RtspError::InvalidHeader("Audio-Latency", returned_latency.to_owned()))?;
+            latency = std::cmp::max(latency, returned_latency);
         }
 
-        let status_mutex = Arc::new(Mutex::new(status));
+        let lost = Arc::new(Beefeater::new(false));
 
         let sync_controller = {
-            let status_ref = Arc::clone(&status_mutex);
-            let sane_ref = Arc::clone(&sane_mutex);
-            let retransmit = Arc::clone(&retransmit);
-            let sample_rate = params.codec.sample_rate();
+            let status_ref = Arc::clone(status_mutex);
+            let sane_ref = Arc::clone(sane_mutex);
+            let retransmit = Arc::clone(retransmit);
 
-            SyncController::start(rtp_ctrl, status_ref, sane_ref, retransmit, latency, sample_rate)
+            SyncController::start(rtp_ctrl, status_ref, sane_ref, retransmit, latency, sample_rate, Arc::clone(&lost))
         };
 
         let rtsp_client_mutex = Arc::new(Mutex::new(rtsp_client));
-        let keepalive_controller = KeepaliveController::start(Arc::clone(&rtsp_client_mutex));
+        let keepalive_controller = KeepaliveController::start(Arc::clone(&rtsp_client_mutex), Arc::clone(&lost));
 
-        Ok(RaopClient {
-            // Immutable properties
-            auth: params.auth,
-            codec: params.codec,
-            crypto: params.crypto,
-            meta_data_capabilities,
-            secret: params.secret,
-            et: params.et,
+        let session = Session {
+            latency,
 
-            // Mutable properties
             keepalive_controller,
             rtp_audio: rtp_audio_mutex,
             sync_controller,
             timing_controller,
 
-            sane: sane_mutex,
-
-            retransmit,
-            ssrc: random(),
-
-            status: status_mutex,
+            rtsp_client: rtsp_client_mutex,
 
-            latency,
-            volume: Arc::new(Beefeater::new(None)),
+            lost,
+        };
 
-            rtsp_client: rtsp_client_mutex,
-        })
+        Ok((session, accepted))
     }
 
     pub fn latency(&self) -> Frames {
         // Why do AirPlay devices use required latency + 11025?
-        self.latency + LATENCY_MIN
+        self.latency.load() + LATENCY_MIN
     }
 
     pub fn sample_rate(&self) -> SampleRate {
-        self.codec.sample_rate()
+        self.params.codec.sample_rate()
     }
 
-    async fn flush(&self, mut status: &mut Status) -> Result<(), Box<dyn std::error::Error>> {
-        let now = NtpTime::now();
-        let now_ts = now.into_timestamp(self.codec.sample_rate());
+    pub fn codec(&self) -> &dyn AudioEncoder {
+        self.params.codec.as_ref()
+    }
+
+    // the receiver of connection events, it can be taken only once
+    pub fn events(&mut self) -> Option<mpsc::UnboundedReceiver<RaopEvent>> {
+        self.event_receiver.take()
+    }
+
+    fn emit(&self, event: RaopEvent) {
+        // nobody listening is fine
+        let _ = self.events.send(event);
+    }
+
+    // the session is always locked before the status, a lost one is recovered first
+    async fn session(&self) -> Result<MutexGuard<'_, Session>, RaopError> {
+        let mut session = self.session.lock().await;
 
-        // We are either paused or we shouldn't start until later
-        if status.pause_ts != Frames::new(0) || status.start_ts > now_ts + self.latency() {
-            unimplemented!();
+        if session.lost.load() && self.params.reconnect_attempts > 0 {
+            self.recover(&mut session).await?;
         }
 
+        Ok(session)
+    }
+
+    // a request that failed because the connection is gone recovers the session, which replays what it was to set
+    async fn recover_on_loss<E: Into<RaopError>>(&self, session: &mut Session, result: Result<(), E>) -> Result<(), RaopError> {
+        match result.map_err(Into::into) {
+            Err(ref err) if is_connection_lost(err) && self.params.reconnect_attempts > 0 => {
+                warn!("request failed, connection to receiver is lost: {}", err);
+                session.lost.store(true);
+                self.recover(session).await
+            }
+            result => result,
+        }
+    }
+
+    async fn recover(&self, session: &mut Session) -> Result<(), RaopError> {
+        warn!("lost connection to {}, reconnecting", self.remote);
+        self.emit(RaopEvent::ConnectionLost);
+        session.stop();
+
+        let mut attempt = 0;
+
+        loop {
+            attempt += 1;
+            self.emit(RaopEvent::Reconnecting(attempt));
+
+            match self.reconnect(session).await {
+                Ok(()) => {
+                    info!("reconnected to {}", self.remote);
+                    self.emit(RaopEvent::Reconnected);
+                    return Ok(());
+                }
+                Err(err) if attempt < self.params.reconnect_attempts => {
+                    warn!("reconnect attempt {} to {} failed: {}", attempt, self.remote, err);
+                    delay_for(RECONNECT_DELAY * attempt).await;
+                }
+                Err(err) => {
+                    error!("giving up on {} after {} reconnect attempts: {}", self.remote, attempt, err);
+                    self.emit(RaopEvent::ReconnectFailed);
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    // sets up a new session with the same parameters, and continues the stream at the position it was in
+    async fn reconnect(&self, session: &mut Session) -> Result<(), RaopError> {
+        let (new_session, accepted) = RaopClient::open_session(&self.params, self.remote, &self.status, &self.sane, &self.retransmit).await?;
+
+        // the chunks sent are already in the format of the current codec
+        if let Some(codec) = accepted {
+            return Err(RaopError::Codec(format!("receiver no longer accepts {} coding, only {}", self.params.codec.name(), codec.name())));
+        }
+
+        *session = new_session;
+        self.latency.store(session.latency);
+
+        if let Some(vol) = self.volume.load() {
+            let parameter = format!("volume: {}\r\n", vol.into_f32());
+            session.rtsp_client.lock().await.set_parameter(&parameter).await?;
+        }
+
+        let mut status = self.status.lock().await;
+        let streaming = status.state == RaopState::Streaming;
+        status.restart(self.params.codec.sample_rate());
+
+        // otherwise resume or accept_frames starts the stream again
+        if streaming {
+            self.flush(session, &mut status).await?;
+        }
+
+        let ts = status.head_ts;
+        drop(status);
+
+        let mut rtsp_client = session.rtsp_client.lock().await;
+
+        if let Some(ref meta_data) = *self.meta_data.lock().await {
+            rtsp_client.set_meta_data(ts, meta_data.clone()).await?;
+        }
+
+        if let Some(ref artwork) = *self.artwork.lock().await {
+            rtsp_client.set_artwork(ts, artwork.content_type(), artwork.data()).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn flush(&self, session: &Session, mut status: &mut Status) -> Result<(), RaopError> {
+        let now = NtpTime::now();
+        let now_ts = now.into_timestamp(self.params.codec.sample_rate());
+
         info!("begining to stream hts:{} n:{}", status.head_ts, now);
         status.state = RaopState::Streaming;
 
@@ -359,21 +663,26 @@ impl RaopClient {
             status.head_ts = if status.start_ts > Frames::new(0) { status.start_ts } else { now_ts };
             status.first_ts = status.head_ts;
 
-            self.sync_controller.send_sync(&mut status, self.codec.sample_rate(), self.latency, true).await?;
+            session.sync_controller.send_sync(&mut status, self.params.codec.sample_rate(), session.latency, true).await?;
+
+            let head_ts = status.head_ts;
+            if let Some(ref mut progress) = status.progress {
+                progress.anchor = Some(head_ts);
+            }
 
             info!("restarting w/o pause n:{}, hts:{}", now, status.head_ts);
         } else {
             let mut n: u16;
             let mut i: u16;
-            let chunks = (u64::from(self.latency()) / u64::from(self.codec.chunk_length())) as u16;
+            let chunks = (u64::from(self.latency()) / u64::from(self.params.codec.chunk_length())) as u16;
 
             // if un-pausing w/o start_time, can anticipate as we have buffer
             status.first_ts = if status.start_ts > Frames::new(0) { status.start_ts } else { now_ts - self.latency() };
 
             // last head_ts shall be first + raopcl_latency - chunk_length
-            status.head_ts = status.first_ts - self.codec.chunk_length();
+            status.head_ts = status.first_ts - self.params.codec.chunk_length();
 
-            self.sync_controller.send_sync(&mut status, self.codec.sample_rate(), self.latency, true).await?;
+            session.sync_controller.send_sync(&mut status, self.params.codec.sample_rate(), session.latency, true).await?;
 
             info!("restarting w/ pause n:{}, hts:{} (re-send: {})", now, status.head_ts, chunks);
 
@@ -382,26 +691,26 @@ impl RaopClient {
             i = 0;
             while i < MAX_BACKLOG && status.backlog[(n % MAX_BACKLOG) as usize].as_ref().map(|e| e.timestamp).unwrap_or_else(|| Frames::new(0)) > status.pause_ts {
                 i += 1;
-                n -= 1;
+                n = n.wrapping_sub(1);
             }
 
             // the resend shall go up to (including) pause_ts
-            n = (n - chunks + 1) % MAX_BACKLOG;
+            n = n.wrapping_sub(chunks).wrapping_add(1);
 
             // re-send old packets
             i = 0;
             while i < chunks {
-                let index = ((n + i) % MAX_BACKLOG) as usize;
+                let index = (n.wrapping_add(i) % MAX_BACKLOG) as usize;
 
                 if let Some(mut entry) = status.backlog[index].take() {
-                    status.seq_number += 1;
+                    status.seq_number = status.seq_number.wrapping_add(1);
 
                     entry.packet.header.type_ = if status.first_pkt { 0xE0 } else { 0x60 };
                     entry.packet.header.seq = status.seq_number;
                     entry.packet.timestamp = status.head_ts;
                     status.first_pkt = false;
 
-                    self._send_audio(&mut status, &entry.packet).await?;
+                    self._send_audio(session, &mut status, &entry.packet).await?;
 
                     // then replace packets in backlog in case
                     let reindex = (status.seq_number % MAX_BACKLOG) as usize;
@@ -412,13 +721,23 @@ impl RaopClient {
                         packet: entry.packet,
                     });
 
-                    status.head_ts += self.codec.chunk_length();
-
-                    i += 1;
+                    status.head_ts += self.params.codec.chunk_length();
                 }
+
+                i += 1;
             }
 
             debug!("finished resend {}", i);
+
+            // the re-sent audio moved from before pause_ts to before head_ts
+            let (head_ts, pause_ts) = (status.head_ts, status.pause_ts);
+            if let Some(ref mut progress) = status.progress {
+                progress.anchor = progress.anchor.map(|anchor| anchor + head_ts - pause_ts);
+            }
+        }
+
+        if let Some(parameter) = status.progress.and_then(|progress| progress.parameter(self.latency(), self.params.codec.sample_rate())) {
+            session.rtsp_client.lock().await.set_parameter(&parameter).await?;
         }
 
         status.pause_ts = Frames::new(0);
@@ -427,49 +746,148 @@ impl RaopClient {
         Ok(())
     }
 
-    pub async fn accept_frames(&self) -> Result<(), Box<dyn std::error::Error>> {
-        trace!("[accept_frames] - aquiring status");
+    pub async fn start_at(&self, start: NtpTime) -> Result<(), RaopError> {
+        // a lost session is recovered before, the start is kept by it
+        let _session = self.session().await?;
+        let mut status = self.status.lock().await;
+
+        if status.state == RaopState::Streaming {
+            return Err(RaopError::InvalidState("cannot schedule the start of a stream that is already playing"));
+        }
+
+        // the first frame sent is heard one latency after its timestamp
+        let start_ts = start.into_timestamp(self.params.codec.sample_rate());
+        let now_ts = NtpTime::now().into_timestamp(self.params.codec.sample_rate());
+
+        if start_ts < now_ts + self.latency() {
+            warn!("start at {} is less than the latency away, beginning will be cut", start);
+        }
+
+        status.start_ts = start_ts - self.latency();
+
+        info!("scheduled start n:{} sts:{}", start, status.start_ts);
+
+        Ok(())
+    }
+
+    pub async fn pause(&self) -> Result<(), RaopError> {
+        let mut session = self.session().await?;
+        let mut status = self.status.lock().await;
+
+        if status.state != RaopState::Streaming {
+            return Ok(());
+        }
+
+        // keep the backlog around so that resume can re-send what the player drops
+        status.state = RaopState::Paused;
+        status.pause_ts = status.head_ts;
+        status.first_pkt = true;
+
+        info!("pausing hts:{} sn:{}", status.head_ts, status.seq_number);
+
+        let (seq_number, head_ts) = (status.seq_number, status.head_ts);
+        drop(status);
+
+        // the new session stays paused
+        let result = session.rtsp_client.lock().await.flush(seq_number.wrapping_add(1), head_ts + Frames::new(1)).await;
+        self.recover_on_loss(&mut session, result).await
+    }
+
+    pub async fn resume(&self) -> Result<(), RaopError> {
+        let mut session = self.session().await?;
+        let mut status = self.status.lock().await;
+
+        if status.state != RaopState::Paused {
+            return Ok(());
+        }
+
+        status.state = RaopState::Flushing;
+        let result = self.flush(&session, &mut status).await;
+        drop(status);
+
+        // the new session continues the stream from the pause
+        self.recover_on_loss(&mut session, result).await
+    }
+
+    pub async fn accept_frames(&self) -> Result<(), RaopError> {
+        if let Some(delay) = self.start_delay().await {
+            delay_for(delay).await;
+        }
+
+        if let Some(delay) = self.frames_delay().await? {
+            delay_for(delay).await;
+        }
+
+        Ok(())
+    }
+
+    // how long until a pending flush is due, we shouldn't start until the start is within the latency
+    pub(crate) async fn start_delay(&self) -> Option<Duration> {
+        trace!("[start_delay] - aquiring status");
+        let status = self.status.lock().await;
+        trace!("[start_delay] - got status");
+
+        if status.state != RaopState::Flushing {
+            return None;
+        }
+
+        let now_ts = NtpTime::now().into_timestamp(self.params.codec.sample_rate());
+
+        if status.start_ts > now_ts + self.latency() {
+            Some((status.start_ts - (now_ts + self.latency())) / self.params.codec.sample_rate())
+        } else {
+            None
+        }
+    }
+
+    // flushes when pending, and tells how long until the next chunk is due
+    pub(crate) async fn frames_delay(&self) -> Result<Option<Duration>, RaopError> {
+        // the session is locked before the status
+        let session = self.session().await?;
+
+        trace!("[frames_delay] - aquiring status");
         let mut status = self.status.lock().await;
-        trace!("[accept_frames] - got status");
+        trace!("[frames_delay] - got status");
 
-        // a flushing is pending
         if status.state == RaopState::Flushing {
-            self.flush(&mut status).await?;
+            self.flush(&session, &mut status).await?;
         }
 
         // when paused, fix "now" at the time when it was paused.
         let now_ts = if status.pause_ts > Frames::new(0) {
             status.pause_ts
         } else {
-            NtpTime::now().into_timestamp(self.codec.sample_rate())
+            NtpTime::now().into_timestamp(self.params.codec.sample_rate())
         };
 
-        let chunk_length = self.codec.chunk_length();
-        let head_ts = status.head_ts;
+        let chunk_length = self.params.codec.chunk_length();
 
-        trace!("[accept_frames] - dropping status");
-        drop(status);
+        if now_ts < status.head_ts + chunk_length {
+            let sleep_frames = (status.head_ts + chunk_length) - now_ts;
+            Ok(Some(sleep_frames / self.params.codec.sample_rate()))
+        } else {
+            Ok(None)
+        }
+    }
 
-        if now_ts < head_ts + chunk_length {
-            let sleep_frames = (head_ts + chunk_length) - now_ts;
-            let sleep_duration = sleep_frames / self.codec.sample_rate();
-            delay_for(sleep_duration).await;
+    pub async fn send_chunk(&mut self, sample: &[u8], playtime: &mut Duration) -> Result<(), RaopError> {
+        // the chunk would only play after resume re-sent what was buffered at the pause
+        if self.status.lock().await.state == RaopState::Paused {
+            return Err(RaopError::InvalidState("cannot send audio while paused"));
         }
 
-        Ok(())
-    }
+        let encoded = self.params.codec.encode_chunk(&sample);
+        let encrypted = self.params.crypto.encrypt(encoded)?;
+
+        let session = self.session().await?;
 
-    pub async fn send_chunk(&mut self, sample: &[u8], playtime: &mut Duration) -> Result<(), Box<dyn std::error::Error>> {
         let now = NtpTime::now();
 
         trace!("[send_chunk] - aquiring status");
         let mut status = self.status.lock().await;
         trace!("[send_chunk] - got status");
 
-        let encoded = self.codec.encode_chunk(&sample);
-        let encrypted = self.crypto.encrypt(encoded)?;
-
-        *playtime = (status.head_ts + self.latency()) / self.codec.sample_rate();
+        *playtime = (status.head_ts + self.latency()) / self.params.codec.sample_rate();
 
         trace!("sending audio ts:{} (pt:{} now:{}) ", status.head_ts, playtime.as_secs_f32(), NtpTime::now());
 
@@ -485,9 +903,13 @@ impl RaopClient {
             ssrc: self.ssrc,
             data: encrypted,
         };
-        status.first_pkt = false;
 
-        self._send_audio(&mut status, &packet).await?;
+        // only sent while streaming, the first packet after a pause or flush has to stay marked
+        if status.state == RaopState::Streaming {
+            status.first_pkt = false;
+        }
+
+        self._send_audio(&session, &mut status, &packet).await?;
 
         let n = (status.seq_number % MAX_BACKLOG) as usize;
 
@@ -497,7 +919,7 @@ impl RaopClient {
             packet,
         });
 
-        status.head_ts += self.codec.chunk_length();
+        status.head_ts += self.params.codec.chunk_length();
 
         // Print extra info every ten seconds
         if playtime.as_secs() % 10 == 0 && playtime.subsec_millis() < 8 {
@@ -513,36 +935,128 @@ impl RaopClient {
         Ok(())
     }
 
-    pub async fn set_volume(&self, vol: Volume) -> Result<(), Box<dyn std::error::Error>> {
+    pub async fn set_volume(&self, vol: Volume) -> Result<(), RaopError> {
         self.volume.store(Some(vol));
+        let mut session = self.session().await?;
 
         let parameter = format!("volume: {}\r\n", vol.into_f32());
-        self.rtsp_client.lock().await.set_parameter(&parameter).await?;
+        let result = session.rtsp_client.lock().await.set_parameter(&parameter).await;
+
+        self.recover_on_loss(&mut session, result).await
+    }
+
+    // start, current and end are positions in the track, current being that of the next chunk sent
+    pub async fn set_progress(&self, start: Duration, current: Duration, end: Duration) -> Result<(), RaopError> {
+        if !self.meta_data_capabilities.progress {
+            debug!("receiver does not display progress, not sending it");
+            return Ok(());
+        }
+
+        let mut session = self.session().await?;
+
+        let parameter = {
+            let mut status = self.status.lock().await;
+
+            // while flushing the timestamps are not known yet, the progress is sent once streaming restarts
+            let anchor = if status.state == RaopState::Flushing { None } else { Some(status.head_ts) };
+            let progress = Progress { start, end, position: current, anchor };
+            status.progress = Some(progress);
+
+            progress.parameter(self.latency(), self.params.codec.sample_rate())
+        };
+
+        if let Some(parameter) = parameter {
+            let result = session.rtsp_client.lock().await.set_parameter(&parameter).await;
+            self.recover_on_loss(&mut session, result).await?;
+        }
 
         Ok(())
     }
 
-    pub async fn set_meta_data(&self, meta_data: MetaDataItem) -> Result<(), Box<dyn std::error::Error>> {
+    // drops everything the receiver has buffered, the next chunk sent is the one at position in the track
+    pub async fn seek(&self, position: Duration) -> Result<(), RaopError> {
+        let mut session = self.session().await?;
+        let mut status = self.status.lock().await;
+
+        let result = if status.state == RaopState::Streaming {
+            session.rtsp_client.lock().await.flush(status.seq_number.wrapping_add(1), status.head_ts + Frames::new(1)).await
+        } else {
+            Ok(())
+        };
+
+        info!("seeking to {} ms hts:{} sn:{}", position.as_millis(), status.head_ts, status.seq_number);
+
+        status.state = RaopState::Flushing;
+        status.pause_ts = Frames::new(0);
+        status.first_pkt = true;
+
+        for entry in status.backlog.iter_mut() {
+            *entry = None;
+        }
+
+        if let Some(ref mut progress) = status.progress {
+            progress.position = position;
+            progress.anchor = None;
+        }
+
+        drop(status);
+
+        // the new session waits for the next chunk sent, as after any seek
+        self.recover_on_loss(&mut session, result).await
+    }
+
+    pub async fn set_meta_data(&self, meta_data: MetaDataItem) -> Result<(), RaopError> {
+        *self.meta_data.lock().await = Some(meta_data.clone());
+        let mut session = self.session().await?;
+
         let ts = (*self.status.lock().await).head_ts;
-        (*self.rtsp_client.lock().await).set_meta_data(ts, meta_data).await?;
-        Ok(())
+        let result = (*session.rtsp_client.lock().await).set_meta_data(ts, meta_data).await;
+
+        self.recover_on_loss(&mut session, result).await
+    }
+
+    pub async fn set_artwork(&self, image: Vec<u8>, content_type: Option<&str>) -> Result<(), RaopError> {
+        if !self.meta_data_capabilities.artwork {
+            debug!("receiver does not display artwork, not sending it");
+            return Ok(());
+        }
+
+        let mut artwork = Artwork::new(image, content_type)?;
+
+        if artwork.is_oversized(MAX_ARTWORK_DIMENSION) {
+            match self.params.artwork_scaler {
+                Some(ref scaler) => {
+                    let (width, height) = artwork.dimensions();
+                    artwork = scaler(&artwork, MAX_ARTWORK_DIMENSION).map_err(RaopError::Artwork)?;
+                    debug!("downscaled artwork from {}x{} to {}x{}", width, height, artwork.dimensions().0, artwork.dimensions().1);
+                }
+                None => warn!("artwork is larger than {}x{}, receiver might not display it", MAX_ARTWORK_DIMENSION, MAX_ARTWORK_DIMENSION),
+            }
+        }
+
+        *self.artwork.lock().await = Some(artwork.clone());
+        let mut session = self.session().await?;
+
+        let ts = self.status.lock().await.head_ts;
+        let result = session.rtsp_client.lock().await.set_artwork(ts, artwork.content_type(), artwork.data()).await;
+
+        self.recover_on_loss(&mut session, result).await
     }
 
-    pub async fn teardown(mut self) -> Result<(), Box<dyn std::error::Error>> {
+    pub async fn teardown(self) -> Result<(), RaopError> {
+        let mut session = self.session.lock().await;
         let status = self.status.lock().await;
 
-        self.keepalive_controller.stop();
-        self.sync_controller.stop();
-        self.timing_controller.stop();
+        session.stop();
 
-        let mut rtsp_client = self.rtsp_client.lock().await;
-        rtsp_client.flush(status.seq_number + 1, status.head_ts + Frames::new(1)).await?;
+        let mut rtsp_client = session.rtsp_client.lock().await;
+        rtsp_client.flush(status.seq_number.wrapping_add(1), status.head_ts + Frames::new(1)).await?;
         rtsp_client.teardown().await?;
 
         Ok(())
     }
 
-    async fn _send_audio(&self, status: &mut Status, packet: &RtpAudioPacket) -> Result<bool, Box<dyn std::error::Error>> {
+    async fn _send_audio(&self, session: &Session, status: &mut Status, packet: &RtpAudioPacket) -> Result<bool, RaopError> {
         /*
         Do not send if audio port closed or we are not yet in streaming state. We
         might be just waiting for flush to happen in the case of a device taking a
@@ -554,8 +1068,17 @@ impl RaopClient {
         // FIXME: if self.rtp_ports.audio.fd == -1  { return Ok(false); }
         if status.state != RaopState::Streaming { return Ok(false); }
 
-        let mut socket = self.rtp_audio.lock().await;
-        let n = socket.send(&packet.as_bytes()).await.unwrap();
+        let mut socket = session.rtp_audio.lock().await;
+        let n = match socket.send(&packet.as_bytes()).await {
+            Ok(n) => n,
+            // the packet stays in the backlog, the next chunk sent reconnects
+            Err(err) if self.params.reconnect_attempts > 0 => {
+                warn!("failed to send audio packet, connection to receiver is lost: {}", err);
+                session.lost.store(true);
+                return Ok(false);
+            }
+            Err(err) => return Err(err.into()),
+        };
         drop(socket);
 
         let mut ret = true;
@@ -575,3 +1098,505 @@ impl RaopClient {
         Ok(ret)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant, SystemTime};
+
+    use beefeater::Beefeater;
+    use futures::future::{Abortable, AbortHandle};
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::Mutex;
+    use tokio::task::JoinHandle;
+    use tokio::time::{delay_for, timeout};
+
+    use crate::codec::{self, AudioEncoder};
+    use crate::frames::Frames;
+    use crate::meta_data::TrackMetadata;
+    use crate::ntp::NtpTime;
+    use crate::raop_client::{RaopClient, RaopEvent, Sane, Status};
+    use crate::raop_error::RaopError;
+    use crate::raop_params::RaopParams;
+    use crate::rtsp_client::{RTSPClient, RtspError};
+    use crate::sample_rate::SampleRate;
+    use crate::serialization::Serializable;
+    use crate::test_receiver::{self, Receiver};
+    use crate::volume::Volume;
+
+    // answers every ANNOUNCE with the status for the offered rtpmap, returns the offered rtpmaps
+    async fn simulate_receiver(mut listener: TcpListener, status: fn(&str) -> &'static str) -> Vec<String> {
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut socket = BufReader::new(socket);
+        let mut offered = Vec::new();
+
+        loop {
+            let mut request_line = String::new();
+            if socket.read_line(&mut request_line).await.unwrap() == 0 { return offered; }
+
+            let mut cseq = String::new();
+            let mut content_length = 0;
+
+            loop {
+                let mut line = String::new();
+                socket.read_line(&mut line).await.unwrap();
+                if line.trim() == "" { break; }
+
+                let (key, value) = line.split_at(line.find(':').unwrap());
+                match key.to_lowercase().as_str() {
+                    "cseq" => cseq = value[1..].trim().to_owned(),
+                    "content-length" => content_length = value[1..].trim().parse().unwrap(),
+                    _ => {},
+                }
+            }
+
+            let mut body = vec![0u8; content_length];
+            socket.read_exact(&mut body).await.unwrap();
+
+            let sdp = String::from_utf8(body).unwrap();
+            let rtpmap = sdp.lines().find(|line| line.starts_with("a=rtpmap:")).unwrap().to_owned();
+            let head = format!("RTSP/1.0 {}\r\nCSeq: {}\r\n\r\n", status(&rtpmap), cseq);
+            socket.get_mut().write_all(head.as_bytes()).await.unwrap();
+
+            offered.push(rtpmap);
+        }
+    }
+
+    async fn negotiate(codec: Box<dyn AudioEncoder>, status: fn(&str) -> &'static str) -> (Result<Option<Box<dyn AudioEncoder>>, RaopError>, Vec<String>) {
+        let listener = TcpListener::from_std(std::net::TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, 0)).unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let receiver = tokio::spawn(simulate_receiver(listener, status));
+
+        let mut params = RaopParams::new();
+        params.set_codec(codec);
+
+        // tokio's own connect trips over the socket address layout of newer compilers
+        let socket = TcpStream::from_std(std::net::TcpStream::connect(addr).unwrap()).unwrap();
+        let mut rtsp_client = RTSPClient::from_stream(socket, "1234567890", "iTunes/7.6.2 (Windows; N;)", &[]).unwrap();
+
+        let result = super::RaopClient::negotiate_codec(&mut rtsp_client, &params, "v=0\r\n", addr).await;
+        drop(rtsp_client);
+
+        (result, receiver.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_negotiate_codec_fallback() {
+        let codec = codec::new_encoder(true, Frames::new(352), SampleRate::Hz44100, 24, 2).unwrap();
+        let (result, offered) = negotiate(codec, |rtpmap| if rtpmap.contains("L16") { "200 OK" } else { "415 Unsupported Media Type" }).await;
+
+        let codec = result.unwrap().unwrap();
+        assert_eq!(codec.name(), "PCM");
+        assert_eq!(codec.sample_size(), 16);
+        assert_eq!(offered, vec!["a=rtpmap:96 AppleLossless", "a=rtpmap:96 AppleLossless", "a=rtpmap:96 L24/44100/2", "a=rtpmap:96 L16/44100/2"]);
+    }
+
+    #[tokio::test]
+    async fn test_negotiate_codec_preferred() {
+        let codec = codec::new_encoder(true, Frames::new(352), SampleRate::Hz44100, 16, 2).unwrap();
+        let (result, offered) = negotiate(codec, |_| "200 OK").await;
+
+        assert!(result.unwrap().is_none());
+        assert_eq!(offered.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_negotiate_codec_exhausted() {
+        let codec = codec::new_encoder(false, Frames::new(352), SampleRate::Hz44100, 16, 2).unwrap();
+        let (result, offered) = negotiate(codec, |_| "400 Bad Request").await;
+
+        match result {
+            Err(RaopError::Protocol(RtspError::ClientError { status: 400, .. })) => {},
+            Err(err) => panic!("expected the last rejection, got {:?}", err),
+            Ok(_) => panic!("expected the last rejection, got a codec"),
+        }
+        assert_eq!(offered, vec!["a=rtpmap:96 L16/44100/2", "a=rtpmap:96 AppleLossless"]);
+    }
+
+    struct SilentEncoder;
+
+    impl AudioEncoder for SilentEncoder {
+        fn name(&self) -> &str { "silence" }
+        fn chunk_length(&self) -> Frames { Frames::new(352) }
+        fn sample_rate(&self) -> SampleRate { SampleRate::Hz44100 }
+        fn sample_size(&self) -> u32 { 8 }
+        fn channels(&self) -> u8 { 1 }
+        fn sdp(&self) -> String { "m=audio 0 RTP/AVP 96\r\na=rtpmap:96 L8/44100/1\r\n".to_owned() }
+        fn encode_chunk(&mut self, _sample: &[u8]) -> Vec<u8> { vec![0; 352] }
+    }
+
+    #[tokio::test]
+    async fn test_negotiate_custom_encoder() {
+        let (result, offered) = negotiate(Box::new(SilentEncoder), |_| "415 Unsupported Media Type").await;
+
+        // without fallbacks only the custom encoder is offered
+        assert!(result.is_err());
+        assert_eq!(offered, vec!["a=rtpmap:96 L8/44100/1"]);
+    }
+
+    #[test]
+    fn test_progress_parameter() {
+        let progress = super::Progress {
+            start: Duration::new(0, 0),
+            end: Duration::from_secs(180),
+            position: Duration::from_secs(10),
+            anchor: Some(Frames::new(1_000_000)),
+        };
+
+        let parameter = progress.parameter(Frames::new(88200), SampleRate::Hz44100).unwrap();
+        assert_eq!(parameter, "progress: 559000/911800/8497000\r\n");
+    }
+
+    #[test]
+    fn test_progress_parameter_wraps() {
+        let progress = super::Progress {
+            start: Duration::new(0, 0),
+            end: Duration::from_secs(1),
+            position: Duration::new(0, 0),
+            anchor: Some(Frames::new(0xFFFF_FFFF)),
+        };
+
+        let parameter = progress.parameter(Frames::new(0), SampleRate::Hz44100).unwrap();
+        assert_eq!(parameter, "progress: 4294967295/4294967295/44099\r\n");
+
+        let progress = super::Progress { anchor: None, ..progress };
+        assert_eq!(progress.parameter(Frames::new(0), SampleRate::Hz44100), None);
+    }
+
+    #[test]
+    fn test_analyse_setup() {
+        let header = |value: &str| vec![("Transport".to_owned(), value.to_owned())];
+
+        let ports = super::analyse_setup(header("RTP/AVP/UDP;unicast;mode=record;server_port=6000;control_port=6001;timing_port=6002")).unwrap();
+        assert_eq!(ports, (6000, 6001, 6002));
+
+        assert!(matches!(super::analyse_setup(vec![]), Err(RtspError::MissingHeader("Transport"))));
+        assert!(matches!(super::analyse_setup(header("RTP/AVP/UDP;server_port=6000")), Err(RtspError::InvalidHeader("Transport", _))));
+        assert!(matches!(super::analyse_setup(header("RTP/AVP/UDP;server_port=none;control_port=6001")), Err(RtspError::InvalidHeader("Transport", _))));
+    }
+
+    async fn stalling_receiver() -> (RTSPClient, SocketAddr, JoinHandle<String>) {
+        let (addr, receiver) = test_receiver::stalling();
+        let rtsp_client = RTSPClient::connect(addr, "1234567890", "iTunes/7.6.2 (Windows; N;)", &[], None).await.unwrap();
+
+        (rtsp_client, addr, receiver)
+    }
+
+    async fn start_session(params: RaopParams, rtsp_client: RTSPClient, remote: SocketAddr) -> Result<(), RaopError> {
+        let status = Arc::new(Mutex::new(Status::new()));
+        let sane = Arc::new(Mutex::new(Sane::new()));
+        let retransmit = Arc::new(Beefeater::new(0));
+
+        RaopClient::start_session(&params, rtsp_client, "1234567890", remote, &status, &sane, &retransmit).await.map(|_| ())
+    }
+
+    #[tokio::test]
+    async fn test_handshake_request_timeout() {
+        let (rtsp_client, addr, receiver) = stalling_receiver().await;
+
+        let mut params = RaopParams::new();
+        params.set_request_timeout(Some(Duration::from_millis(100)));
+        params.set_handshake_timeout(None);
+
+        match start_session(params, rtsp_client, addr).await {
+            Err(RaopError::Timeout("ANNOUNCE")) => {},
+            result => panic!("expected the ANNOUNCE to time out, got {:?}", result),
+        }
+
+        assert!(receiver.await.unwrap().starts_with("ANNOUNCE "));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_timeout() {
+        let (rtsp_client, addr, receiver) = stalling_receiver().await;
+
+        let mut params = RaopParams::new();
+        params.set_request_timeout(None);
+        params.set_handshake_timeout(Some(Duration::from_millis(100)));
+
+        match start_session(params, rtsp_client, addr).await {
+            Err(RaopError::Timeout("handshake")) => {},
+            result => panic!("expected the handshake to time out, got {:?}", result),
+        }
+
+        assert!(receiver.await.unwrap().starts_with("ANNOUNCE "));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_cancelled() {
+        let (rtsp_client, addr, receiver) = stalling_receiver().await;
+
+        let mut params = RaopParams::new();
+        params.set_request_timeout(None);
+        params.set_handshake_timeout(None);
+
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+        let session = Abortable::new(start_session(params, rtsp_client, addr), abort_registration);
+
+        tokio::spawn(async move {
+            delay_for(Duration::from_millis(100)).await;
+            abort_handle.abort();
+        });
+
+        assert!(session.await.is_err());
+
+        // cancelling must hang up on the receiver
+        let requests = timeout(Duration::from_secs(5), receiver).await.unwrap().unwrap();
+        assert!(requests.starts_with("ANNOUNCE "));
+    }
+
+    #[test]
+    fn test_status_restart() {
+        let mut status = Status::new();
+        status.state = super::RaopState::Streaming;
+        status.head_ts = Frames::new(1_044_100);
+        status.pause_ts = Frames::new(1_000_000);
+        status.progress = Some(super::Progress {
+            start: Duration::new(0, 0),
+            end: Duration::from_secs(180),
+            position: Duration::from_secs(10),
+            anchor: Some(Frames::new(1_000_000)),
+        });
+        status.backlog[0] = Some(super::BacklogEntry { seq_number: 0, timestamp: Frames::new(0), packet: super::RtpAudioPacket {
+            header: super::RtpHeader { proto: 0x80, type_: 0x60, seq: 0 },
+            timestamp: Frames::new(0),
+            ssrc: 0,
+            data: vec![],
+        } });
+
+        status.restart(SampleRate::Hz44100);
+
+        // the stream continues one second further in the track, at a timestamp the next flush picks
+        let progress = status.progress.unwrap();
+        assert_eq!(progress.position, Duration::from_secs(11));
+        assert_eq!(progress.anchor, None);
+        assert!(status.state == super::RaopState::Flushing);
+        assert_eq!(status.pause_ts, Frames::new(0));
+        assert!(status.first_pkt);
+        assert!(status.backlog.iter().all(Option::is_none));
+
+        // a paused stream stays paused, and waits for its start
+        status.state = super::RaopState::Paused;
+        status.start_ts = Frames::new(2_000_000);
+        status.restart(SampleRate::Hz44100);
+
+        assert!(status.state == super::RaopState::Paused);
+        assert_eq!(status.start_ts, Frames::new(2_000_000));
+    }
+
+    // a client of the stub receiver, with a latency of 22050 frames
+    async fn connect(receiver: &Receiver) -> RaopClient {
+        let mut params = RaopParams::new();
+        params.set_desired_latency(Frames::new(11025));
+
+        RaopClient::connect(params, receiver.addr).await.unwrap()
+    }
+
+    // every sample of the chunk is the given byte, which the PCM coding keeps
+    fn chunk(marker: u8) -> Vec<u8> {
+        vec![marker; 352 * 4]
+    }
+
+    #[tokio::test]
+    async fn test_pause_resume() {
+        let mut receiver = Receiver::start().await;
+        let mut client = connect(&receiver).await;
+        let mut playtime = Duration::new(0, 0);
+
+        // the sequence numbers wrap around while streaming
+        client.status.lock().await.seq_number = 0xFFD0;
+
+        for marker in 0..100 {
+            client.accept_frames().await.unwrap();
+            client.send_chunk(&chunk(marker), &mut playtime).await.unwrap();
+        }
+
+        let sent = receiver.audio_packets().await;
+        assert_eq!(sent.len(), 100);
+        let last = sent.last().unwrap().clone();
+        assert_eq!(last.seq, 0x0034);
+
+        client.pause().await.unwrap();
+        let flush = receiver.request("FLUSH").await;
+        assert!(flush.header("RTP-Info").unwrap().starts_with(&format!("seq={};", last.seq.wrapping_add(1))));
+
+        // nothing is taken while paused
+        match client.send_chunk(&chunk(100), &mut playtime).await {
+            Err(RaopError::InvalidState(_)) => {},
+            result => panic!("expected a paused stream to refuse audio, got {:?}", result),
+        }
+        assert!(receiver.audio_packets().await.is_empty());
+
+        client.resume().await.unwrap();
+
+        // the latency-worth of chunks up to the pause is sent again, as a new stream
+        let resent = receiver.audio_packets().await;
+        assert_eq!(resent.len(), 22050 / 352);
+        assert!(resent[0].first);
+
+        for (index, packet) in resent.iter().enumerate() {
+            assert_eq!(packet.seq, last.seq.wrapping_add(1 + index as u16));
+            assert_eq!(packet.payload[0], 100 - resent.len() as u8 + index as u8);
+        }
+
+        for (previous, packet) in resent.iter().zip(&resent[1..]) {
+            assert!(!packet.first);
+            assert_eq!(packet.timestamp, previous.timestamp.wrapping_add(352));
+        }
+
+        // and streaming goes on right after it
+        client.send_chunk(&chunk(100), &mut playtime).await.unwrap();
+        let next = receiver.audio().await;
+        let replayed = resent.last().unwrap();
+        assert_eq!(next.seq, replayed.seq.wrapping_add(1));
+        assert_eq!(next.timestamp, replayed.timestamp.wrapping_add(352));
+        assert_eq!(next.payload[0], 100);
+    }
+
+    #[tokio::test]
+    async fn test_start_at() {
+        let mut receiver = Receiver::start().await;
+        let mut client = connect(&receiver).await;
+        let mut playtime = Duration::new(0, 0);
+
+        let start = NtpTime::now() + Duration::from_secs(1);
+        client.start_at(start).await.unwrap();
+
+        // the first frame is heard one latency after its timestamp
+        let start_ts = start.into_timestamp(SampleRate::Hz44100) - Frames::new(22050);
+        assert_eq!(client.status.lock().await.start_ts, start_ts);
+
+        // and it is sent no earlier than that
+        let begin = Instant::now();
+        client.accept_frames().await.unwrap();
+        assert!(begin.elapsed() >= Duration::from_millis(400));
+
+        client.send_chunk(&chunk(0), &mut playtime).await.unwrap();
+        let first = receiver.audio().await;
+        assert!(first.first);
+        assert_eq!(first.timestamp, u64::from(start_ts) as u32);
+
+        match client.start_at(start).await {
+            Err(RaopError::InvalidState(_)) => {},
+            result => panic!("expected a playing stream to refuse a start, got {:?}", result),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_start_at_past() {
+        let mut receiver = Receiver::start().await;
+        let mut client = connect(&receiver).await;
+        let mut playtime = Duration::new(0, 0);
+
+        let start = NtpTime::from_system_time(SystemTime::now() - Duration::from_secs(1));
+        client.start_at(start).await.unwrap();
+
+        // already too late, the stream begins right away and the receiver cuts its beginning
+        let begin = Instant::now();
+        client.accept_frames().await.unwrap();
+        assert!(begin.elapsed() < Duration::from_millis(100));
+
+        client.send_chunk(&chunk(0), &mut playtime).await.unwrap();
+        let start_ts = start.into_timestamp(SampleRate::Hz44100) - Frames::new(22050);
+        assert_eq!(receiver.audio().await.timestamp, u64::from(start_ts) as u32);
+    }
+
+    #[tokio::test]
+    async fn test_reconnect() {
+        let mut receiver = Receiver::start().await;
+        let mut client = connect(&receiver).await;
+        let mut events = client.events().unwrap();
+        let mut playtime = Duration::new(0, 0);
+
+        let meta_data = TrackMetadata { title: Some("Song".to_owned()), ..TrackMetadata::new() }.to_listing_item();
+        client.set_meta_data(meta_data.clone()).await.unwrap();
+
+        client.accept_frames().await.unwrap();
+        client.send_chunk(&chunk(0), &mut playtime).await.unwrap();
+        receiver.audio().await;
+        receiver.requests().await;
+
+        // the receiver reboots, the next request finds the connection closed
+        receiver.hang_up();
+        delay_for(Duration::from_millis(50)).await;
+
+        // the first attempt does not wait
+        let begin = Instant::now();
+        client.set_volume(Volume::from_percent(30)).await.unwrap();
+        assert!(begin.elapsed() < Duration::from_secs(1));
+
+        assert_eq!(events.recv().await, Some(RaopEvent::ConnectionLost));
+        assert_eq!(events.recv().await, Some(RaopEvent::Reconnecting(1)));
+        assert_eq!(events.recv().await, Some(RaopEvent::Reconnected));
+
+        // the new session gets the volume and the meta data again
+        receiver.request("RECORD").await;
+        assert_eq!(receiver.request("SET_PARAMETER").await.text(), format!("volume: {}\r\n", Volume::from_percent(30).into_f32()));
+
+        let replayed = receiver.request("SET_PARAMETER").await;
+        assert_eq!(replayed.header("Content-Type"), Some("application/x-dmap-tagged"));
+        assert_eq!(replayed.body, meta_data.as_bytes());
+
+        // and the stream goes on as a new one
+        client.accept_frames().await.unwrap();
+        client.send_chunk(&chunk(1), &mut playtime).await.unwrap();
+
+        let packet = receiver.audio().await;
+        assert!(packet.first);
+        assert_eq!(packet.payload[0], 1);
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_paused() {
+        let mut receiver = Receiver::start().await;
+        let mut client = connect(&receiver).await;
+        let mut events = client.events().unwrap();
+        let mut playtime = Duration::new(0, 0);
+
+        for marker in 0..10 {
+            client.accept_frames().await.unwrap();
+            client.send_chunk(&chunk(marker), &mut playtime).await.unwrap();
+        }
+        receiver.audio_packets().await;
+
+        // pausing finds the connection closed, the new session stays paused
+        receiver.hang_up();
+        delay_for(Duration::from_millis(50)).await;
+
+        client.pause().await.unwrap();
+
+        assert_eq!(events.recv().await, Some(RaopEvent::ConnectionLost));
+        assert_eq!(events.recv().await, Some(RaopEvent::Reconnecting(1)));
+        assert_eq!(events.recv().await, Some(RaopEvent::Reconnected));
+
+        receiver.request("RECORD").await;
+        assert!(receiver.audio_packets().await.is_empty());
+
+        client.resume().await.unwrap();
+        client.accept_frames().await.unwrap();
+        client.send_chunk(&chunk(10), &mut playtime).await.unwrap();
+
+        let packet = receiver.audio().await;
+        assert!(packet.first);
+        assert_eq!(packet.payload[0], 10);
+
+        // and so does seeking
+        receiver.hang_up();
+        delay_for(Duration::from_millis(50)).await;
+
+        client.seek(Duration::from_secs(5)).await.unwrap();
+
+        assert_eq!(events.recv().await, Some(RaopEvent::ConnectionLost));
+        assert_eq!(events.recv().await, Some(RaopEvent::Reconnecting(1)));
+        assert_eq!(events.recv().await, Some(RaopEvent::Reconnected));
+
+        client.accept_frames().await.unwrap();
+        client.send_chunk(&chunk(11), &mut playtime).await.unwrap();
+
+        let packet = receiver.audio().await;
+        assert!(packet.first);
+        assert_eq!(packet.payload[0], 11);
+    }
+}
@@ -0,0 +1,109 @@
+use std::error::Error;
+use std::fmt::{self, Formatter, Display};
+use std::io;
+
+use openssl::error::ErrorStack;
+
+use crate::artwork::ArtworkError;
+use crate::crypto::AppleResponseError;
+use crate::rtsp_client::RtspError;
+
+#[derive(Debug)]
+pub enum RaopError {
+    // the receiver can't be reached, or the connection to it broke
+    Connection(io::Error),
+    // the receiver answered with an error status, or with something that isn't understood
+    Protocol(RtspError),
+    // the password, pairing secret or Apple-Challenge wasn't accepted
+    Authentication(Box<dyn Error + Send + Sync>),
+    Codec(String),
+    Crypto(ErrorStack),
+    Artwork(Box<dyn Error + Send + Sync>),
+    // names the step that took too long
+    Timeout(&'static str),
+    // the request doesn't fit the state of the stream
+    InvalidState(&'static str),
+}
+
+impl Display for RaopError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            RaopError::Connection(source) => write!(f, "connection to receiver failed: {}", source),
+            RaopError::Protocol(source) => write!(f, "receiver did not accept the request: {}", source),
+            RaopError::Authentication(source) => write!(f, "authentication with receiver failed: {}", source),
+            RaopError::Codec(message) => write!(f, "codec error: {}", message),
+            RaopError::Crypto(source) => write!(f, "encryption failed: {}", source),
+            RaopError::Artwork(source) => write!(f, "invalid artwork: {}", source),
+            RaopError::Timeout(step) => write!(f, "{} timed out", step),
+            RaopError::InvalidState(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for RaopError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RaopError::Connection(source) => Some(source),
+            RaopError::Protocol(source) => Some(source),
+            RaopError::Authentication(source) => Some(source.as_ref()),
+            RaopError::Crypto(source) => Some(source),
+            RaopError::Artwork(source) => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RaopError {
+    fn from(error: io::Error) -> Self {
+        RaopError::Connection(error)
+    }
+}
+
+impl From<RtspError> for RaopError {
+    fn from(error: RtspError) -> Self {
+        match error {
+            RtspError::IoError(source) => RaopError::Connection(source),
+            RtspError::Timeout(step) => RaopError::Timeout(step),
+            RtspError::OpenSslError(source) => RaopError::Crypto(source),
+            // curve25519 keys are only used for pairing and MFi authentication
+            RtspError::ClientError { status: 401, .. } | RtspError::ClientError { status: 403, .. } | RtspError::PairingError(_) | RtspError::SrpError(_) | RtspError::Curve25519Error(_) => RaopError::Authentication(Box::new(error)),
+            _ => RaopError::Protocol(error),
+        }
+    }
+}
+
+impl From<AppleResponseError> for RaopError {
+    fn from(error: AppleResponseError) -> Self {
+        RaopError::Authentication(Box::new(error))
+    }
+}
+
+impl From<ErrorStack> for RaopError {
+    fn from(error: ErrorStack) -> Self {
+        RaopError::Crypto(error)
+    }
+}
+
+impl From<ArtworkError> for RaopError {
+    fn from(error: ArtworkError) -> Self {
+        RaopError::Artwork(Box::new(error))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+
+    use super::RaopError;
+    use crate::rtsp_client::RtspError;
+
+    #[test]
+    fn test_from_rtsp_error() {
+        let status = |status: u16| RtspError::ClientError { status, headers: vec![], body: String::new() };
+
+        assert!(matches!(RaopError::from(status(401)), RaopError::Authentication(_)));
+        assert!(matches!(RaopError::from(status(415)), RaopError::Protocol(RtspError::ClientError { status: 415, .. })));
+        assert!(matches!(RaopError::from(RtspError::MissingHeader("Session")), RaopError::Protocol(_)));
+        assert!(matches!(RaopError::from(RtspError::IoError(io::Error::from(io::ErrorKind::ConnectionReset))), RaopError::Connection(_)));
+    }
+}
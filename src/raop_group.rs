@@ -0,0 +1,392 @@
+use crate::frames::Frames;
+use crate::ntp::NtpTime;
+use crate::raop_client::{RaopClient, MAX_BACKLOG};
+use crate::raop_error::RaopError;
+use crate::raop_params::RaopParams;
+use crate::sample_rate::SampleRate;
+use crate::volume::Volume;
+
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::{join_all, Abortable, AbortHandle};
+use futures::prelude::*;
+use log::{error, warn, info};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio::time::delay_for;
+
+// headroom on top of the largest member latency before the common start
+const START_MARGIN: Duration = Duration::from_millis(250);
+
+struct Member {
+    remote: SocketAddr,
+    client: Arc<Mutex<Option<RaopClient>>>,
+    sender: mpsc::Sender<Vec<u8>>,
+    abort_handle: AbortHandle,
+    handle: JoinHandle<()>,
+}
+
+impl Member {
+    // the member plays what is queued up before it hangs up
+    async fn teardown(self) {
+        drop(self.sender);
+
+        if let Err(err) = self.handle.await {
+            error!("feeding task of {} failed: {}", self.remote, err);
+        }
+
+        if let Some(client) = self.client.lock().await.take() {
+            if let Err(err) = client.teardown().await {
+                warn!("failed to teardown {}: {}", self.remote, err);
+            }
+        }
+    }
+}
+
+pub struct RaopGroup {
+    members: Vec<Member>,
+    start: NtpTime,
+
+    // the largest member latency, and how far after the start the next chunk plays
+    latency: Duration,
+    position: Duration,
+
+    // of the input format all members share
+    bytes_per_frame: usize,
+    sample_rate: SampleRate,
+}
+
+impl RaopGroup {
+    pub async fn connect(receivers: Vec<(RaopParams, SocketAddr)>) -> Result<RaopGroup, RaopError> {
+        let results = join_all(receivers.into_iter().map(|(params, remote)| {
+            RaopClient::connect(params, remote).map(move |result| (remote, result))
+        })).await;
+
+        let mut clients = Vec::with_capacity(results.len());
+
+        for (remote, result) in results {
+            match result {
+                Ok(client) => clients.push((remote, client)),
+                Err(err) => warn!("failed to connect to {}, leaving it out of the group: {}", remote, err),
+            }
+        }
+
+        if clients.is_empty() {
+            return Err(RaopError::Connection(io::Error::new(ErrorKind::NotConnected, "no receiver of the group could be connected")));
+        }
+
+        // every member is fed the same samples, a fallback codec can leave one taking another format
+        let format = clients[0].1.codec().input_format();
+        let sample_rate = clients[0].1.sample_rate();
+
+        if let Some((remote, client)) = clients.iter().find(|(_, client)| client.codec().input_format() != format || client.sample_rate() != sample_rate) {
+            let err = RaopError::Codec(format!("{} takes {} at {} Hz, {} takes {} at {} Hz", clients[0].0, format, sample_rate, remote, client.codec().input_format(), client.sample_rate()));
+
+            join_all(clients.into_iter().map(|(remote, client)| client.teardown().map(move |result| {
+                if let Err(err) = result {
+                    warn!("failed to teardown {}: {}", remote, err);
+                }
+            }))).await;
+
+            return Err(err);
+        }
+
+        // every member starts at the same wall-clock moment, far enough away for the slowest one
+        let latency = clients.iter().map(|(_, client)| client.latency() / client.sample_rate()).max().unwrap_or_default();
+        let start = NtpTime::now() + latency + START_MARGIN;
+
+        let bytes_per_frame = format.bytes_per_frame();
+
+        let mut members = Vec::with_capacity(clients.len());
+
+        for (remote, client) in clients {
+            client.start_at(start).await?;
+
+            info!("member {} joined the group, latency is {} ms", remote, (client.latency() / client.sample_rate()).as_millis());
+
+            let client = Arc::new(Mutex::new(Some(client)));
+            let (sender, receiver) = mpsc::channel(MAX_BACKLOG as usize);
+            let (abort_handle, abort_registration) = AbortHandle::new_pair();
+
+            let future = feed(remote, Arc::clone(&client), receiver);
+            let future = Abortable::new(future, abort_registration).map(|_| {});
+            let handle = tokio::spawn(future);
+
+            members.push(Member { remote, client, sender, abort_handle, handle });
+        }
+
+        Ok(RaopGroup { members, start, latency, position: Duration::new(0, 0), bytes_per_frame, sample_rate })
+    }
+
+    pub fn start(&self) -> NtpTime {
+        self.start
+    }
+
+    pub fn members(&self) -> Vec<SocketAddr> {
+        self.members.iter().map(|member| member.remote).collect()
+    }
+
+    // waits until the next chunk is due, the group is fed at most the largest member latency ahead
+    pub async fn accept_frames(&self) {
+        let ahead = (self.start + self.position) - NtpTime::now();
+
+        if ahead > self.latency {
+            delay_for(ahead - self.latency).await;
+        }
+    }
+
+    // hands the chunk to every member without waiting for any of them
+    pub async fn send_chunk(&mut self, sample: &[u8]) -> Result<(), RaopError> {
+        let mut dropped = Vec::new();
+
+        for (index, member) in self.members.iter_mut().enumerate() {
+            match member.sender.try_send(sample.to_vec()) {
+                Ok(()) => {},
+                // seconds of audio are queued up, it could only play out of sync with the rest
+                Err(TrySendError::Full(_)) => {
+                    warn!("{} is not keeping up with the group", member.remote);
+                    dropped.push(index);
+                }
+                // the channel is only closed when the member gave up
+                Err(TrySendError::Closed(_)) => dropped.push(index),
+            }
+        }
+
+        for index in dropped.into_iter().rev() {
+            let member = self.members.remove(index);
+            warn!("removing {} from the group", member.remote);
+
+            // what is queued up for a stalled member would play late, and it can take its time to hang up
+            member.abort_handle.abort();
+            tokio::spawn(member.teardown());
+        }
+
+        self.position += Frames::from_usize(sample.len(), self.bytes_per_frame) / self.sample_rate;
+
+        if self.members.is_empty() {
+            return Err(RaopError::Connection(io::Error::new(ErrorKind::NotConnected, "all receivers of the group dropped out")));
+        }
+
+        Ok(())
+    }
+
+    pub async fn set_volume(&self, remote: SocketAddr, vol: Volume) -> Result<(), RaopError> {
+        let member = self.members.iter().find(|member| member.remote == remote).ok_or(RaopError::InvalidState("no such member in the group"))?;
+        let client = member.client.lock().await;
+        client.as_ref().ok_or(RaopError::InvalidState("member has left the group"))?.set_volume(vol).await
+    }
+
+    pub async fn teardown(self) -> Result<(), RaopError> {
+        for member in self.members {
+            member.teardown().await;
+        }
+
+        Ok(())
+    }
+}
+
+async fn feed(remote: SocketAddr, client: Arc<Mutex<Option<RaopClient>>>, mut receiver: mpsc::Receiver<Vec<u8>>) {
+    let mut playtime = Duration::new(0, 0);
+
+    while let Some(chunk) = receiver.recv().await {
+        if let Err(err) = feed_chunk(&client, &chunk, &mut playtime).await {
+            error!("{} dropped out of the group: {}", remote, err);
+            return;
+        }
+    }
+}
+
+// the client is locked only while it is used, the group can set its volume while the chunk is not due yet
+async fn feed_chunk(client: &Mutex<Option<RaopClient>>, chunk: &[u8], playtime: &mut Duration) -> Result<(), RaopError> {
+    let left = || RaopError::InvalidState("member has left the group");
+
+    let delay = client.lock().await.as_ref().ok_or_else(left)?.start_delay().await;
+    if let Some(delay) = delay {
+        delay_for(delay).await;
+    }
+
+    let delay = client.lock().await.as_ref().ok_or_else(left)?.frames_delay().await?;
+    if let Some(delay) = delay {
+        delay_for(delay).await;
+    }
+
+    client.lock().await.as_mut().ok_or_else(left)?.send_chunk(chunk, playtime).await
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use tokio::time::{delay_for, timeout};
+
+    use crate::codec;
+    use crate::frames::Frames;
+    use crate::raop_error::RaopError;
+    use crate::raop_group::RaopGroup;
+    use crate::raop_params::RaopParams;
+    use crate::sample_rate::SampleRate;
+    use crate::test_receiver::Receiver;
+    use crate::volume::Volume;
+
+    const CHUNK: [u8; 352 * 4] = [0; 352 * 4];
+
+    async fn connect(remotes: &[SocketAddr]) -> RaopGroup {
+        let receivers = remotes.iter().map(|remote| {
+            let mut params = RaopParams::new();
+            params.set_desired_latency(Frames::new(11025));
+            (params, *remote)
+        });
+
+        RaopGroup::connect(receivers.collect()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_shared_timestamp_base() {
+        let mut first = Receiver::start().await;
+        let mut second = Receiver::start_with_latency(Some(22050)).await;
+
+        let mut group = connect(&[first.addr, second.addr]).await;
+        assert_eq!(group.members(), vec![first.addr, second.addr]);
+
+        group.accept_frames().await;
+        group.send_chunk(&CHUNK).await.unwrap();
+
+        // both hear their first frame at the start of the group, the second one asked for 11025 frames more latency
+        let start_ts = u64::from(group.start().into_timestamp(SampleRate::Hz44100)) as u32;
+        assert_eq!(first.audio().await.timestamp, start_ts.wrapping_sub(22050));
+        assert_eq!(second.audio().await.timestamp, start_ts.wrapping_sub(33075));
+
+        group.teardown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_codec_mismatch() {
+        let mut first = Receiver::start().await;
+        let mut second = Receiver::start().await;
+
+        let mut params = RaopParams::new();
+        params.set_codec(codec::new_encoder(true, Frames::new(352), SampleRate::Hz44100, 24, 2).unwrap());
+
+        // 16-bit samples for the first, 24-bit ones for the second
+        match RaopGroup::connect(vec![(RaopParams::new(), first.addr), (params, second.addr)]).await {
+            Err(RaopError::Codec(_)) => {},
+            Err(err) => panic!("expected the group to be rejected for its codecs, got {:?}", err),
+            Ok(_) => panic!("expected the group to be rejected for its codecs"),
+        }
+
+        first.request("TEARDOWN").await;
+        second.request("TEARDOWN").await;
+    }
+
+    #[tokio::test]
+    async fn test_member_volume() {
+        let mut first = Receiver::start().await;
+        let mut second = Receiver::start().await;
+
+        let group = connect(&[first.addr, second.addr]).await;
+
+        group.set_volume(first.addr, Volume::from_percent(50)).await.unwrap();
+        assert!(first.request("SET_PARAMETER").await.text().starts_with("volume: "));
+        assert!(second.requests().await.iter().all(|request| request.method != "SET_PARAMETER"));
+
+        match group.set_volume("127.0.0.1:1".parse().unwrap(), Volume::from_percent(50)).await {
+            Err(RaopError::InvalidState(_)) => {},
+            result => panic!("expected an unknown member to be rejected, got {:?}", result),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_volume_while_waiting() {
+        let mut first = Receiver::start().await;
+        let mut group = connect(&[first.addr]).await;
+
+        // the member waits for the start of the group before it sends the chunk
+        group.send_chunk(&CHUNK).await.unwrap();
+        delay_for(Duration::from_millis(50)).await;
+
+        let begin = Instant::now();
+        group.set_volume(first.addr, Volume::from_percent(50)).await.unwrap();
+        assert!(begin.elapsed() < Duration::from_millis(100));
+
+        first.request("SET_PARAMETER").await;
+        assert!(first.audio().await.first);
+    }
+
+    #[tokio::test]
+    async fn test_accept_frames() {
+        let first = Receiver::start().await;
+        let mut group = connect(&[first.addr]).await;
+        let begin = Instant::now();
+
+        for _ in 0..125 {
+            group.send_chunk(&CHUNK).await.unwrap();
+        }
+
+        // a second of audio later than the start of the group, minus the latency it may be fed ahead
+        group.accept_frames().await;
+        assert!(begin.elapsed() >= Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_teardown_drains() {
+        let mut first = Receiver::start().await;
+        let mut group = connect(&[first.addr]).await;
+
+        // queued up without waiting, as the end of a track can be
+        for _ in 0..20 {
+            group.send_chunk(&CHUNK).await.unwrap();
+        }
+
+        group.teardown().await.unwrap();
+
+        assert_eq!(first.audio_packets().await.len(), 20);
+        first.request("TEARDOWN").await;
+    }
+
+    #[tokio::test]
+    async fn test_stalled_member() {
+        let first = Receiver::start().await;
+        let mut second = Receiver::start().await;
+
+        let mut group = connect(&[first.addr, second.addr]).await;
+
+        // the feeding task of the first member waits for its client, until the channel is full
+        let client = Arc::clone(&group.members[0].client);
+        let _stalled = client.lock().await;
+        while group.members[0].sender.try_send(CHUNK.to_vec()).is_ok() {}
+
+        timeout(Duration::from_secs(1), group.send_chunk(&CHUNK)).await.expect("the group waited for a stalled member").unwrap();
+        assert_eq!(group.members(), vec![second.addr]);
+
+        group.accept_frames().await;
+        assert!(second.audio().await.first);
+    }
+
+    #[tokio::test]
+    async fn test_member_dropped() {
+        let first = Receiver::start().await;
+        let second = Receiver::start().await;
+
+        let mut group = connect(&[first.addr, second.addr]).await;
+
+        group.members[0].abort_handle.abort();
+        delay_for(Duration::from_millis(50)).await;
+
+        group.send_chunk(&CHUNK).await.unwrap();
+        assert_eq!(group.members(), vec![second.addr]);
+
+        group.members[0].abort_handle.abort();
+        delay_for(Duration::from_millis(50)).await;
+
+        match group.send_chunk(&CHUNK).await {
+            Err(RaopError::Connection(_)) => {},
+            result => panic!("expected the group to fail without members, got {:?}", result),
+        }
+    }
+}
@@ -1,42 +1,166 @@
-use crate::codec::Codec;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt::{self, Formatter, Display};
+use std::time::Duration;
+
+use crate::artwork::ArtworkScaler;
+use crate::codec::{self, AudioEncoder, PcmCodec};
 use crate::crypto::Crypto;
+use crate::discovery::RaopReceiver;
 use crate::frames::Frames;
 use crate::raop_client::MAX_SAMPLES_PER_CHUNK;
 use crate::sample_rate::SampleRate;
 
+#[derive(Debug)]
+pub enum RaopParamsError {
+    InvalidSampleRate(String),
+    InvalidSampleSize(String),
+    InvalidChannels(String),
+    UnsupportedCodecs(String),
+    UnsupportedEncryption(String),
+}
+
+impl Display for RaopParamsError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            RaopParamsError::InvalidSampleRate(value) => write!(f, "receiver advertises an invalid sample rate: sr={}", value),
+            RaopParamsError::InvalidSampleSize(value) => write!(f, "receiver advertises an invalid sample size: ss={}", value),
+            RaopParamsError::InvalidChannels(value) => write!(f, "receiver advertises an invalid channel count: ch={}", value),
+            RaopParamsError::UnsupportedCodecs(value) => write!(f, "receiver only accepts codecs cn={}, but only PCM (0) and ALAC (1) are available", value),
+            RaopParamsError::UnsupportedEncryption(value) => write!(f, "receiver only accepts encryption types et={}, but only none (0) and RSA (1) are available", value),
+        }
+    }
+}
+
+impl Error for RaopParamsError {}
+
 pub struct RaopParams {
+    pub(super) artwork_scaler: Option<ArtworkScaler>,
     pub(super) auth: bool,
-    pub(super) codec: Codec,
+    pub(super) codec: Box<dyn AudioEncoder>,
+    pub(super) connect_timeout: Option<Duration>,
     pub(super) crypto: Crypto,
     pub(super) desired_latency: Frames,
     pub(super) et: Option<String>,
+    pub(super) handshake_timeout: Option<Duration>,
+    pub(super) mac_address: Option<[u8; 6]>,
     pub(super) md: Option<String>,
+    pub(super) password: Option<String>,
+    pub(super) password_required: bool,
+    pub(super) reconnect_attempts: u32,
+    pub(super) request_timeout: Option<Duration>,
     pub(super) secret: Option<String>,
 }
 
 impl Default for RaopParams {
     fn default() -> Self {
         RaopParams {
+            artwork_scaler: None,
             auth: false,
-            codec: Codec::new(false, MAX_SAMPLES_PER_CHUNK, SampleRate::Hz44100, 16, 2),
+            codec: Box::new(PcmCodec::new(MAX_SAMPLES_PER_CHUNK, SampleRate::Hz44100, 16, 2)),
+            connect_timeout: Some(Duration::from_secs(5)),
             crypto: Crypto::new(false),
             desired_latency: Frames::new(44100),
             et: None,
+            handshake_timeout: Some(Duration::from_secs(30)),
+            mac_address: None,
             md: None,
+            password: None,
+            password_required: false,
+            reconnect_attempts: 5,
+            request_timeout: Some(Duration::from_secs(10)),
             secret: None,
         }
     }
 }
 
+fn txt_list(value: &str) -> Vec<&str> {
+    value.split(',').map(str::trim).collect()
+}
+
 impl RaopParams {
     pub fn new() -> Self {
         Self::default()
     }
 
-    pub fn set_codec(&mut self, codec: Codec) {
+    pub fn from_txt_record(txt: &HashMap<String, String>) -> Result<Self, RaopParamsError> {
+        let get = |key: &str| txt.get(key).map(String::as_str);
+
+        let sample_rate = match get("sr") {
+            Some(value) => value.parse::<u64>().ok().and_then(|value| SampleRate::try_from(value).ok()).ok_or_else(|| RaopParamsError::InvalidSampleRate(value.to_owned()))?,
+            None => SampleRate::Hz44100,
+        };
+
+        let sample_size = match get("ss") {
+            Some(value) => value.parse::<u32>().ok().filter(|size| *size == 16 || *size == 24).ok_or_else(|| RaopParamsError::InvalidSampleSize(value.to_owned()))?,
+            None => 16,
+        };
+
+        let channels = match get("ch") {
+            Some(value) => value.parse::<u8>().ok().filter(|channels| *channels == 1 || *channels == 2).ok_or_else(|| RaopParamsError::InvalidChannels(value.to_owned()))?,
+            None => 2,
+        };
+
+        // receivers not advertising a codec list are assumed to be AirPort Express alikes
+        let cn = get("cn").unwrap_or("0,1");
+        let codecs = txt_list(cn);
+
+        let alac = if codecs.contains(&"1") {
+            true
+        } else if codecs.contains(&"0") {
+            false
+        } else {
+            return Err(RaopParamsError::UnsupportedCodecs(cn.to_owned()));
+        };
+
+        let codec = codec::new_encoder(alac, MAX_SAMPLES_PER_CHUNK, sample_rate, sample_size, channels).map_err(|_| RaopParamsError::InvalidSampleSize(sample_size.to_string()))?;
+
+        let et = get("et").unwrap_or("0");
+        let encryption_types = txt_list(et);
+
+        let crypto = if encryption_types.contains(&"0") {
+            Crypto::new(false)
+        } else if encryption_types.contains(&"1") {
+            Crypto::new(true)
+        } else {
+            return Err(RaopParamsError::UnsupportedEncryption(et.to_owned()));
+        };
+
+        Ok(RaopParams {
+            codec,
+            crypto,
+            et: Some(et.to_owned()),
+            md: get("md").map(str::to_owned),
+            password_required: get("pw").map(|pw| pw.eq_ignore_ascii_case("true")).unwrap_or(false),
+            ..Self::default()
+        })
+    }
+
+    // like from_txt_record, with the MAC address the receiver announces in its name
+    pub fn from_receiver(receiver: &RaopReceiver) -> Result<Self, RaopParamsError> {
+        let mut params = Self::from_txt_record(&receiver.txt)?;
+        params.mac_address = receiver.mac_address();
+        Ok(params)
+    }
+
+    pub fn set_artwork_scaler(&mut self, artwork_scaler: Option<ArtworkScaler>) {
+        self.artwork_scaler = artwork_scaler;
+    }
+
+    pub fn set_auth(&mut self, auth: bool) {
+        self.auth = auth;
+    }
+
+    pub fn set_codec(&mut self, codec: Box<dyn AudioEncoder>) {
         self.codec = codec;
     }
 
+    // bounds establishing the TCP connection to the receiver
+    pub fn set_connect_timeout(&mut self, connect_timeout: Option<Duration>) {
+        self.connect_timeout = connect_timeout;
+    }
+
     pub fn set_crypto(&mut self, crypto: Crypto) {
         self.crypto = crypto;
     }
@@ -44,4 +168,111 @@ impl RaopParams {
     pub fn set_desired_latency(&mut self, desired_latency: Frames) {
         self.desired_latency = desired_latency;
     }
+
+    pub fn set_et(&mut self, et: Option<String>) {
+        self.et = et;
+    }
+
+    // bounds everything from the first request up to RECORD
+    pub fn set_handshake_timeout(&mut self, handshake_timeout: Option<Duration>) {
+        self.handshake_timeout = handshake_timeout;
+    }
+
+    pub fn set_mac_address(&mut self, mac_address: Option<[u8; 6]>) {
+        self.mac_address = mac_address;
+    }
+
+    pub fn set_md(&mut self, md: Option<String>) {
+        self.md = md;
+    }
+
+    pub fn set_password(&mut self, password: Option<String>) {
+        self.password = password;
+    }
+
+    // a lost connection is re-established this many times before sending fails, 0 disables recovering
+    pub fn set_reconnect_attempts(&mut self, reconnect_attempts: u32) {
+        self.reconnect_attempts = reconnect_attempts;
+    }
+
+    // bounds waiting for the response to a single RTSP request, for the whole session
+    pub fn set_request_timeout(&mut self, request_timeout: Option<Duration>) {
+        self.request_timeout = request_timeout;
+    }
+
+    pub fn set_secret(&mut self, secret: Option<String>) {
+        self.secret = secret;
+    }
+
+    pub fn codec(&self) -> &dyn AudioEncoder {
+        self.codec.as_ref()
+    }
+
+    pub fn password_required(&self) -> bool {
+        self.password_required
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::discovery::RaopReceiver;
+
+    use std::collections::HashMap;
+
+    fn txt(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn test_from_txt_record() {
+        let params = super::RaopParams::from_txt_record(&txt(&[("cn", "0,1"), ("et", "0,1"), ("md", "0,1,2"), ("sr", "44100"), ("ss", "16"), ("ch", "2"), ("pw", "true")])).unwrap();
+
+        assert_eq!(params.codec.name(), "ALAC");
+        assert!(params.crypto.is_clear());
+        assert_eq!(params.md.as_deref(), Some("0,1,2"));
+        assert!(params.password_required());
+    }
+
+    #[test]
+    fn test_from_txt_record_mismatch() {
+        let params = super::RaopParams::from_txt_record(&txt(&[("cn", "0"), ("et", "1")])).unwrap();
+        assert_eq!(params.codec.name(), "PCM");
+        assert!(!params.crypto.is_clear());
+
+        match super::RaopParams::from_txt_record(&txt(&[("cn", "2,3")])) {
+            Err(super::RaopParamsError::UnsupportedCodecs(cn)) => assert_eq!(cn, "2,3"),
+            _ => panic!("expected an unsupported codecs error"),
+        }
+
+        match super::RaopParams::from_txt_record(&txt(&[("et", "3,5")])) {
+            Err(super::RaopParamsError::UnsupportedEncryption(et)) => assert_eq!(et, "3,5"),
+            _ => panic!("expected an unsupported encryption error"),
+        }
+    }
+
+    #[test]
+    fn test_from_txt_record_sample_size() {
+        let params = super::RaopParams::from_txt_record(&txt(&[("cn", "0,1"), ("ss", "24")])).unwrap();
+        assert_eq!(params.codec.sample_size(), 24);
+        assert!(params.codec.sdp().contains("a=fmtp:96 352 0 24 "));
+
+        // receivers without 24-bit support get 16-bit audio
+        let params = super::RaopParams::from_txt_record(&txt(&[("cn", "0"), ("ss", "16")])).unwrap();
+        assert_eq!(params.codec.sample_size(), 16);
+        assert!(params.codec.sdp().contains("L16/44100/2"));
+
+        assert!(super::RaopParams::from_txt_record(&txt(&[("ss", "32")])).is_err());
+    }
+
+    #[test]
+    fn test_from_receiver() {
+        let mut receiver = RaopReceiver { name: "0050C212A23F@Living Room".to_owned(), addresses: vec![], port: 5000, txt: txt(&[("cn", "0,1"), ("et", "0,1")]) };
+
+        let params = super::RaopParams::from_receiver(&receiver).unwrap();
+        assert_eq!(params.codec.name(), "ALAC");
+        assert_eq!(params.mac_address, Some([0x00, 0x50, 0xc2, 0x12, 0xa2, 0x3f]));
+
+        receiver.name = "Living Room".to_owned();
+        assert_eq!(super::RaopParams::from_receiver(&receiver).unwrap().mac_address, None);
+    }
 }
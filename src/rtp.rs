@@ -123,7 +123,6 @@ impl<'a> Serializable for RtpAudioRetransmissionPacket<'a> {
 
 #[derive(Debug)]
 pub struct RtpLostPacket {
-    header: RtpHeader,
     pub seq_number: u16,
     pub n: u16,
 }
@@ -132,11 +131,11 @@ impl Deserializable for RtpLostPacket {
     const SIZE: usize = RtpHeader::SIZE + 2 + 2;
 
     fn deserialize(reader: &mut dyn Read) -> io::Result<RtpLostPacket> {
-        let header = RtpHeader::deserialize(reader)?;
+        RtpHeader::deserialize(reader)?;
         let seq_number = reader.read_u16::<BE>()?;
         let n = reader.read_u16::<BE>()?;
 
-        Ok(RtpLostPacket { header, seq_number, n })
+        Ok(RtpLostPacket { seq_number, n })
     }
 }
 
@@ -0,0 +1,97 @@
+use openssl::error::ErrorStack;
+use openssl::hash::{hash, MessageDigest};
+
+// AirPlay receivers accept any user name, iTunes always sent this one
+const USERNAME: &str = "iTunes";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DigestChallenge {
+    realm: String,
+    nonce: String,
+}
+
+fn md5_hex(input: &str) -> Result<String, ErrorStack> {
+    Ok(hex::encode(hash(MessageDigest::md5(), input.as_bytes())?))
+}
+
+fn parse_params(params: &str) -> Vec<(String, String)> {
+    let mut result = Vec::new();
+    let mut rest = params.trim();
+
+    while !rest.is_empty() {
+        let eq = match rest.find('=') {
+            Some(eq) => eq,
+            None => break,
+        };
+
+        let key = rest[..eq].trim().to_lowercase();
+        rest = rest[eq + 1..].trim_start();
+
+        let value = if rest.starts_with('"') {
+            let end = rest[1..].find('"').map(|end| end + 1).unwrap_or(rest.len());
+            let value = rest[1..end].to_owned();
+            rest = &rest[(end + 1).min(rest.len())..];
+            value
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            let value = rest[..end].trim().to_owned();
+            rest = &rest[end..];
+            value
+        };
+
+        result.push((key, value));
+        rest = rest.trim_start().trim_start_matches(',').trim_start();
+    }
+
+    result
+}
+
+impl DigestChallenge {
+    pub fn parse(header: &str) -> Option<DigestChallenge> {
+        let header = header.trim();
+
+        if header.len() < 7 || !header[..7].eq_ignore_ascii_case("digest ") {
+            return None;
+        }
+
+        let params = parse_params(&header[7..]);
+        let get = |name: &str| params.iter().find(|param| param.0 == name).map(|param| param.1.clone());
+
+        Some(DigestChallenge { realm: get("realm")?, nonce: get("nonce")? })
+    }
+
+    pub fn authorization(&self, password: &str, method: &str, uri: &str) -> Result<String, ErrorStack> {
+        let ha1 = md5_hex(&format!("{}:{}:{}", USERNAME, self.realm, password))?;
+        let ha2 = md5_hex(&format!("{}:{}", method, uri))?;
+        let response = md5_hex(&format!("{}:{}:{}", ha1, self.nonce, ha2))?;
+
+        Ok(format!(
+            "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", response=\"{}\"",
+            USERNAME,
+            self.realm,
+            self.nonce,
+            uri,
+            response,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_parse_challenge() {
+        let challenge = super::DigestChallenge::parse("Digest realm=\"raop\", nonce=\"deadbeef\"").unwrap();
+        assert_eq!(challenge, super::DigestChallenge { realm: String::from("raop"), nonce: String::from("deadbeef") });
+
+        assert_eq!(super::DigestChallenge::parse("Basic realm=\"raop\""), None);
+        assert_eq!(super::DigestChallenge::parse("Digest realm=\"raop\""), None);
+    }
+
+    #[test]
+    fn test_authorization() {
+        let challenge = super::DigestChallenge::parse("Digest realm=\"raop\",nonce=deadbeef").unwrap();
+        let authorization = challenge.authorization("secret", "ANNOUNCE", "rtsp://192.168.1.42/1234567890").unwrap();
+
+        assert_eq!(authorization, "Digest username=\"iTunes\", realm=\"raop\", nonce=\"deadbeef\", uri=\"rtsp://192.168.1.42/1234567890\", response=\"b5b1efc9ce8b8ad71026e530bdf7aee4\"");
+    }
+}
@@ -4,6 +4,9 @@ use std::string::FromUtf8Error;
 
 use hex::FromHexError;
 
+use crate::curve25519::Curve25519Error;
+use crate::srp::SrpError;
+
 use super::response::ParseResponseError;
 
 #[derive(Debug)]
@@ -13,6 +16,13 @@ pub enum RtspError {
     ParseResponseError(ParseResponseError),
     DecodeResponseError(FromUtf8Error),
     OpenSslError(openssl::error::ErrorStack),
+    Curve25519Error(Curve25519Error),
+    SrpError(SrpError),
+    PairingError(&'static str),
+    MissingHeader(&'static str),
+    InvalidHeader(&'static str, String),
+    NoSession,
+    Timeout(&'static str),
     ClientError { status: u16, headers: Vec<(String, String)>, body: String },
     ServerError { status: u16, headers: Vec<(String, String)>, body: String },
     UnknownError { status: u16, headers: Vec<(String, String)>, body: String },
@@ -31,6 +41,8 @@ impl Error for RtspError {
             RtspError::FromHexError(source) => Some(source),
             RtspError::ParseResponseError(source) => Some(source),
             RtspError::DecodeResponseError(source) => Some(source),
+            RtspError::SrpError(source) => Some(source),
+            RtspError::Curve25519Error(source) => Some(source),
             _ => None,
         }
     }
@@ -54,6 +66,18 @@ impl From<openssl::error::ErrorStack> for RtspError {
     }
 }
 
+impl From<Curve25519Error> for RtspError {
+    fn from(error: Curve25519Error) -> Self {
+        RtspError::Curve25519Error(error)
+    }
+}
+
+impl From<SrpError> for RtspError {
+    fn from(error: SrpError) -> Self {
+        RtspError::SrpError(error)
+    }
+}
+
 impl From<ParseResponseError> for RtspError {
     fn from(error: ParseResponseError) -> Self {
         RtspError::ParseResponseError(error)
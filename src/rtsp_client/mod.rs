@@ -1,26 +1,34 @@
-use std::io::Write;
-use std::net::IpAddr;
+use std::io::{self, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr};
+use std::time::Duration;
 
 use hex::FromHex;
-use log::{error, debug};
+use log::{error, info, debug};
 use openssl::sha::Sha512;
-use openssl::symm::{Cipher, Mode, Crypter};
+use openssl::symm::{encrypt_aead, Cipher, Mode, Crypter};
 use rand::random;
 use tokio::io::BufReader;
-use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::net::TcpStream;
 use tokio::prelude::*;
+use tokio::sync::oneshot;
 
 use crate::curve25519;
+use crate::deadline::within;
 use crate::frames::Frames;
 use crate::meta_data::MetaDataItem;
+use crate::plist::{self, PlistValue};
 use crate::serialization::Serializable;
+use crate::srp::SrpClient;
 
+mod digest;
 mod error;
 mod response;
 
+use self::digest::DigestChallenge;
 pub use self::error::RtspError;
 use self::response::{Response, ResponseBuilder};
 
+#[derive(Clone, Copy)]
 enum Body<'a> {
     Text { content_type: &'a str, content: &'a str },
     Blob { content_type: &'a str, content: &'a [u8] },
@@ -74,11 +82,19 @@ pub struct RTSPClient {
     headers: Vec<(String, String)>,
     session: Option<String>,
     user_agent: String,
+    password: Option<String>,
+    digest: Option<DigestChallenge>,
+    request_timeout: Option<Duration>,
 }
 
 impl RTSPClient {
-    pub async fn connect<A: ToSocketAddrs>(addr: A, sid: &str, user_agent: &str, headers: &[(&str, &str)]) -> Result<RTSPClient, Box<dyn std::error::Error>> {
-        let socket = TcpStream::connect(addr).await?;
+    pub async fn connect(addr: SocketAddr, sid: &str, user_agent: &str, headers: &[(&str, &str)], timeout: Option<Duration>) -> Result<RTSPClient, RtspError> {
+        let socket = within(timeout, connect_std(addr, timeout)).await.ok_or(RtspError::Timeout("connect"))??;
+
+        RTSPClient::from_stream(socket, sid, user_agent, headers)
+    }
+
+    pub fn from_stream(socket: TcpStream, sid: &str, user_agent: &str, headers: &[(&str, &str)]) -> Result<RTSPClient, RtspError> {
         let peer_addr = socket.peer_addr()?;
 
         Ok(RTSPClient {
@@ -88,9 +104,21 @@ impl RTSPClient {
             headers: headers.iter().map(|header| (header.0.to_owned(), header.1.to_owned())).collect(),
             session: None,
             user_agent: user_agent.to_owned(),
+            password: None,
+            digest: None,
+            request_timeout: None,
         })
     }
 
+    pub fn set_password(&mut self, password: Option<String>) {
+        self.password = password;
+        self.digest = None;
+    }
+
+    pub fn set_request_timeout(&mut self, request_timeout: Option<Duration>) {
+        self.request_timeout = request_timeout;
+    }
+
     // bool rtspcl_set_useragent(struct rtspcl_s *p, const char *name);
 
     // bool rtspcl_is_connected(struct rtspcl_s *p);
@@ -103,11 +131,11 @@ impl RTSPClient {
     pub async fn pair_verify(&mut self, secret_hex: &str) -> Result<(), RtspError> {
         // retrieve authentication keys from secret
         let secret = <[u8; curve25519::SECRET_KEY_SIZE]>::from_hex(secret_hex)?;
-        let (auth_priv, auth_pub) = curve25519::create_key_pair(&secret);
+        let (_, auth_pub) = curve25519::create_key_pair(&secret)?;
 
         // create a verification public key
         let verify_secret: [u8; curve25519::SECRET_KEY_SIZE] = random();
-        let verify_pub = curve25519::calculate_public_key(&verify_secret);
+        let verify_pub = curve25519::calculate_public_key(&verify_secret)?;
 
         // POST the auth_pub and verify_pub concataned
         let mut buf = Vec::with_capacity(4 + curve25519::PUBLIC_KEY_SIZE * 2);
@@ -120,13 +148,16 @@ impl RTSPClient {
 
         drop(buf);
 
-        // FIXME: flag to self.exec_request should make it return binary response
-        let content = content.as_bytes();
+        if content.len() < curve25519::PUBLIC_KEY_SIZE {
+            error!("AppleTV verify step 1 returned {} bytes", content.len());
+            return Err(RtspError::PairingError("pair-verify response too short"));
+        }
 
         // get atv_pub and atv_data then create shared secret
-        let atv_pub = &content[0..curve25519::PUBLIC_KEY_SIZE];
+        let mut atv_pub = [0u8; curve25519::PUBLIC_KEY_SIZE];
+        atv_pub.copy_from_slice(&content[0..curve25519::PUBLIC_KEY_SIZE]);
         let atv_data = &content[curve25519::PUBLIC_KEY_SIZE..];
-        let shared_secret = curve25519::create_shared_key(&atv_pub, &verify_secret);
+        let shared_secret = curve25519::create_shared_key(&atv_pub, &verify_secret)?;
 
         // build AES-key & AES-iv from shared secret digest
         let aes_key = {
@@ -148,7 +179,7 @@ impl RTSPClient {
             let mut message = Vec::with_capacity(curve25519::PUBLIC_KEY_SIZE * 2);
             message.extend_from_slice(&verify_pub);
             message.extend_from_slice(&atv_pub);
-            curve25519::sign_message(&auth_priv, &message)
+            curve25519::sign_message(&secret, &message)?
         };
 
         // encrypt the signed result + atv_data, add 4 NULL bytes at the beginning
@@ -172,9 +203,83 @@ impl RTSPClient {
             .map(|_| ())
     }
 
+    pub async fn pair_pin_start(&mut self) -> Result<(), RtspError> {
+        self.exec_request("POST", Body::None, vec!(), Some("/pair-pin-start")).await
+            .inspect_err(|_| error!("AppleTV refused to start pairing"))
+            .map(|_| ())
+    }
+
+    async fn pair_setup_step(&mut self, request: PlistValue) -> Result<PlistValue, RtspError> {
+        let body = plist::to_bytes(&request);
+        let (_, content) = self.exec_request("POST", Body::Blob { content_type: "application/x-apple-binary-plist", content: &body }, vec!(), Some("/pair-setup-pin")).await?;
+
+        plist::from_bytes(&content).map_err(|_| RtspError::PairingError("pair-setup response is not a binary plist"))
+    }
+
+    pub async fn pair_setup(&mut self, pin: &str) -> Result<String, RtspError> {
+        let user = hex::encode_upper(random::<[u8; 8]>());
+
+        // the new identity, its secret is what pair_verify needs afterwards
+        let secret: [u8; curve25519::SECRET_KEY_SIZE] = random();
+        let (_, auth_pub) = curve25519::create_key_pair(&secret)?;
+
+        // step 1: get the salt and public key of the AppleTV
+        let response = self.pair_setup_step(PlistValue::Dictionary(vec![
+            (String::from("method"), PlistValue::String(String::from("pin"))),
+            (String::from("user"), PlistValue::String(user.clone())),
+        ])).await.inspect_err(|_| error!("AppleTV pair-setup step 1 failed"))?;
+
+        let atv_pub = response.get("pk").and_then(PlistValue::as_data).ok_or(RtspError::PairingError("no public key in pair-setup response"))?;
+        let salt = response.get("salt").and_then(PlistValue::as_data).ok_or(RtspError::PairingError("no salt in pair-setup response"))?;
+
+        // step 2: prove that we know the PIN, and check that the AppleTV does too
+        let srp = SrpClient::new(&user, pin)?;
+        let session = srp.process(salt, atv_pub)?;
+
+        let response = self.pair_setup_step(PlistValue::Dictionary(vec![
+            (String::from("pk"), PlistValue::Data(srp.public_key())),
+            (String::from("proof"), PlistValue::Data(session.proof().to_vec())),
+        ])).await.inspect_err(|_| error!("AppleTV pair-setup step 2 failed (wrong PIN?)"))?;
+
+        let atv_proof = response.get("proof").and_then(PlistValue::as_data).ok_or(RtspError::PairingError("no proof in pair-setup response"))?;
+
+        if !session.verify_server(atv_proof) {
+            error!("AppleTV pair-setup proof mismatch");
+            return Err(RtspError::PairingError("AppleTV proof does not match"));
+        }
+
+        // step 3: hand over our public key, encrypted with the shared session key
+        let aes_key = {
+            let mut hasher = Sha512::new();
+            hasher.update(b"Pair-Setup-AES-Key");
+            hasher.update(session.key());
+            hasher.finish()
+        };
+
+        let mut aes_iv = {
+            let mut hasher = Sha512::new();
+            hasher.update(b"Pair-Setup-AES-IV");
+            hasher.update(session.key());
+            hasher.finish()
+        };
+        aes_iv[15] = aes_iv[15].wrapping_add(1);
+
+        let mut tag = [0u8; 16];
+        let epk = encrypt_aead(Cipher::aes_128_gcm(), &aes_key[0..16], Some(&aes_iv[0..16]), &[], &auth_pub, &mut tag)?;
+
+        self.pair_setup_step(PlistValue::Dictionary(vec![
+            (String::from("epk"), PlistValue::Data(epk)),
+            (String::from("authTag"), PlistValue::Data(tag.to_vec())),
+        ])).await.inspect_err(|_| error!("AppleTV pair-setup step 3 failed"))?;
+
+        info!("paired with AppleTV as {}", user);
+
+        Ok(hex::encode(secret))
+    }
+
     pub async fn auth_setup(&mut self) -> Result<(), RtspError> {
         let secret: [u8; curve25519::SECRET_KEY_SIZE] = random();
-        let pub_key = curve25519::calculate_public_key(&secret);
+        let pub_key = curve25519::calculate_public_key(&secret)?;
 
         let mut buf = Vec::with_capacity(1 + curve25519::PUBLIC_KEY_SIZE);
         buf.push(0x01);
@@ -185,11 +290,11 @@ impl RTSPClient {
             .map(|_| ())
     }
 
-    pub async fn announce_sdp(&mut self, sdp: &str) -> Result<(), RtspError> {
-        self.exec_request("ANNOUNCE", Body::Text { content_type: "application/sdp", content: sdp }, vec!(), None).await.map(|_| ())
+    pub async fn announce_sdp(&mut self, sdp: &str) -> Result<Vec<(String, String)>, RtspError> {
+        self.exec_request("ANNOUNCE", Body::Text { content_type: "application/sdp", content: sdp }, vec!(), None).await.map(|result| result.0)
     }
 
-    pub async fn setup(&mut self, control_port: u16, timing_port: u16) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
+    pub async fn setup(&mut self, control_port: u16, timing_port: u16) -> Result<Vec<(String, String)>, RtspError> {
         let transport = format!("RTP/AVP/UDP;unicast;interleaved=0-1;mode=record;control_port={};timing_port={}", control_port, timing_port);
         let (headers, _) = self.exec_request("SETUP", Body::None, vec!(("Transport", &transport)), None).await?;
         let session = headers.iter().find(|header| header.0.to_lowercase() == "session").map(|header| header.1.as_str());
@@ -199,7 +304,7 @@ impl RTSPClient {
             debug!("got session from remote: {}", session);
         } else {
             error!("no session in response");
-            panic!("no session in response");
+            return Err(RtspError::MissingHeader("Session"));
         }
 
         Ok(headers)
@@ -208,7 +313,7 @@ impl RTSPClient {
     pub async fn record(&mut self, start_seq: u16, start_ts: Frames) -> Result<Vec<(String, String)>, RtspError> {
         if self.session.is_none() {
             error!("no session in progress");
-            panic!("no session in progress");
+            return Err(RtspError::NoSession);
         }
 
         let info = format!("seq={};rtptime={}", start_seq, start_ts);
@@ -222,6 +327,8 @@ impl RTSPClient {
     }
 
     pub async fn set_meta_data(&mut self, timestamp: Frames, meta_data: MetaDataItem) -> Result<(), RtspError> {
+        debug!("meta data {:?}", meta_data);
+
         let rtptime = format!("rtptime={}", timestamp);
         let body = Body::Blob { content_type: "application/x-dmap-tagged", content: &meta_data.as_bytes() };
 
@@ -237,8 +344,14 @@ impl RTSPClient {
         self.exec_request("TEARDOWN", Body::None, vec!(), None).await.map(|_| ())
     }
 
+    pub async fn set_artwork(&mut self, timestamp: Frames, content_type: &str, image: &[u8]) -> Result<(), RtspError> {
+        let rtptime = format!("rtptime={}", timestamp);
+        let body = Body::Blob { content_type, content: image };
+
+        self.exec_request("SET_PARAMETER", body, vec![("RTP-Info", &rtptime)], None).await.map(|_| ())
+    }
+
     // bool rtspcl_set_daap(struct rtspcl_s *p, u32_t timestamp, int count, va_list args);
-    // bool rtspcl_set_artwork(struct rtspcl_s *p, u32_t timestamp, char *content_type, int size, char *image);
 
     pub fn add_exthds(&mut self, key: &str, data: &str) {
         self.headers.push((key.to_owned(), data.to_owned()));
@@ -248,15 +361,38 @@ impl RTSPClient {
         self.headers.retain(|header| header.0 != key);
     }
 
-    pub fn local_ip(&self) -> Result<IpAddr, Box<dyn std::error::Error>> {
+    pub fn local_ip(&self) -> Result<IpAddr, RtspError> {
         Ok(self.socket.get_ref().local_addr()?.ip())
     }
 
-    async fn exec_request(&mut self, cmd: &str, body: Body<'_>, headers: Vec<(&str, &str)>, url: Option<&str>) -> Result<Response, RtspError> {
-        let url = url.unwrap_or_else(|| self.url.as_str());
+    async fn exec_request(&mut self, cmd: &'static str, body: Body<'_>, headers: Vec<(&str, &str)>, url: Option<&str>) -> Result<Response, RtspError> {
+        let url = url.map(str::to_owned).unwrap_or_else(|| self.url.clone());
+        let response = self.send_request(cmd, body, &headers, &url).await;
+
+        // password protected receivers answer with a digest challenge, retry once with credentials
+        let challenge = match response {
+            Err(RtspError::ClientError { status: 401, headers: ref response_headers, .. }) if self.password.is_some() => {
+                response_headers.iter()
+                    .find(|header| header.0.to_lowercase() == "www-authenticate")
+                    .and_then(|header| DigestChallenge::parse(&header.1))
+            }
+            _ => None,
+        };
+
+        match challenge {
+            Some(challenge) => {
+                debug!("receiver requires authentication, retrying with digest");
+                self.digest = Some(challenge);
+                self.send_request(cmd, body, &headers, &url).await
+            }
+            None => response,
+        }
+    }
+
+    async fn send_request(&mut self, cmd: &'static str, body: Body<'_>, headers: &[(&str, &str)], url: &str) -> Result<Response, RtspError> {
         let mut req = RequestBuilder::new(cmd, url);
 
-        for (key, value) in &headers {
+        for (key, value) in headers {
             req.header(key, value);
         }
 
@@ -282,13 +418,33 @@ impl RTSPClient {
             req.header("Session", session);
         }
 
+        if let (Some(ref digest), Some(ref password)) = (&self.digest, &self.password) {
+            req.header("Authorization", &digest.authorization(password, cmd, url)?);
+        }
+
         let req = req.body(body);
 
-        self.socket.get_mut().write_all(&req).await?;
+        match within(self.request_timeout, self.transmit(&req)).await {
+            Some(response) => response,
+            None => {
+                // a late response would be taken for the answer to the next request, so give up on the connection
+                error!("no response to {} in time, closing connection", cmd);
+                let _ = self.socket.get_ref().shutdown(Shutdown::Both);
+                Err(RtspError::Timeout(cmd))
+            }
+        }
+    }
+
+    async fn transmit(&mut self, req: &[u8]) -> Result<Response, RtspError> {
+        self.socket.get_mut().write_all(req).await?;
 
         let mut response = {
             let mut line = String::new();
-            self.socket.read_line(&mut line).await?;
+
+            if self.socket.read_line(&mut line).await? == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "receiver closed the connection").into());
+            }
+
             ResponseBuilder::new(&line)?
         };
 
@@ -305,3 +461,203 @@ impl RTSPClient {
         Ok(response.body(data)?)
     }
 }
+
+// tokio's own connect trips over the socket address layout of newer compilers, so connect with std on a thread
+async fn connect_std(addr: SocketAddr, timeout: Option<Duration>) -> io::Result<TcpStream> {
+    let (sender, receiver) = oneshot::channel();
+
+    std::thread::spawn(move || {
+        let socket = match timeout {
+            Some(timeout) => std::net::TcpStream::connect_timeout(&addr, timeout),
+            None => std::net::TcpStream::connect(addr),
+        };
+
+        // nobody is waiting anymore when connecting was cancelled
+        let _ = sender.send(socket);
+    });
+
+    let socket = receiver.await.map_err(|_| io::Error::new(io::ErrorKind::NotConnected, "connecting thread died"))??;
+    TcpStream::from_std(socket)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use hex::FromHex;
+    use openssl::sha::Sha512;
+    use openssl::symm::{decrypt_aead, Cipher};
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{TcpListener, TcpStream};
+
+    use crate::curve25519;
+    use crate::plist::{self, PlistValue};
+    use crate::srp::SrpServer;
+
+    async fn read_request(socket: &mut BufReader<TcpStream>) -> (String, String, Vec<u8>) {
+        let mut request_line = String::new();
+        socket.read_line(&mut request_line).await.unwrap();
+
+        let mut cseq = String::new();
+        let mut content_length = 0;
+
+        loop {
+            let mut line = String::new();
+            socket.read_line(&mut line).await.unwrap();
+            if line.trim() == "" { break; }
+
+            let (key, value) = line.split_at(line.find(':').unwrap());
+            let value = value[1..].trim();
+
+            match key.to_lowercase().as_str() {
+                "cseq" => cseq = value.to_owned(),
+                "content-length" => content_length = value.parse().unwrap(),
+                _ => {},
+            }
+        }
+
+        let mut body = vec![0u8; content_length];
+        socket.read_exact(&mut body).await.unwrap();
+
+        let uri = request_line.split(' ').nth(1).unwrap().to_owned();
+        (uri, cseq, body)
+    }
+
+    async fn write_response(socket: &mut BufReader<TcpStream>, status: &str, cseq: &str, body: Option<PlistValue>) {
+        let body = body.map(|body| plist::to_bytes(&body)).unwrap_or_default();
+        let head = format!("RTSP/1.0 {}\r\nCSeq: {}\r\nContent-Type: application/x-apple-binary-plist\r\nContent-Length: {}\r\n\r\n", status, cseq, body.len());
+
+        socket.get_mut().write_all(head.as_bytes()).await.unwrap();
+        socket.get_mut().write_all(&body).await.unwrap();
+    }
+
+    // tokio's own bind and connect trip over the socket address layout of newer compilers
+    fn connect(addr: std::net::SocketAddr) -> super::RTSPClient {
+        let socket = TcpStream::from_std(std::net::TcpStream::connect(addr).unwrap()).unwrap();
+        super::RTSPClient::from_stream(socket, "1234567890", "iTunes/7.6.2 (Windows; N;)", &[]).unwrap()
+    }
+
+    // behaves like an AppleTV showing the given PIN, returns the public key the client handed over
+    async fn simulate_apple_tv(listener: &mut TcpListener, pin: &str) -> Option<Vec<u8>> {
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut socket = BufReader::new(socket);
+
+        let (uri, cseq, _) = read_request(&mut socket).await;
+        assert_eq!(uri, "/pair-pin-start");
+        write_response(&mut socket, "200 OK", &cseq, None).await;
+
+        let (uri, cseq, body) = read_request(&mut socket).await;
+        assert_eq!(uri, "/pair-setup-pin");
+        let request = plist::from_bytes(&body).unwrap();
+        assert_eq!(request.get("method"), Some(&PlistValue::String(String::from("pin"))));
+
+        let user = match request.get("user") {
+            Some(PlistValue::String(user)) => user.clone(),
+            _ => panic!("no user in pair-setup request"),
+        };
+
+        let server = SrpServer::new(&user, pin);
+        write_response(&mut socket, "200 OK", &cseq, Some(PlistValue::Dictionary(vec![
+            (String::from("pk"), PlistValue::Data(server.public_key())),
+            (String::from("salt"), PlistValue::Data(server.salt())),
+        ]))).await;
+
+        let (_, cseq, body) = read_request(&mut socket).await;
+        let request = plist::from_bytes(&body).unwrap();
+        let verified = server.verify_client(request.get("pk")?.as_data()?, request.get("proof")?.as_data()?);
+
+        let (key, proof) = match verified {
+            Some(verified) => verified,
+            None => {
+                write_response(&mut socket, "470 Connection Authorization Required", &cseq, None).await;
+                return None;
+            }
+        };
+
+        write_response(&mut socket, "200 OK", &cseq, Some(PlistValue::Dictionary(vec![
+            (String::from("proof"), PlistValue::Data(proof)),
+        ]))).await;
+
+        let (_, cseq, body) = read_request(&mut socket).await;
+        let request = plist::from_bytes(&body).unwrap();
+
+        let mut hasher = Sha512::new();
+        hasher.update(b"Pair-Setup-AES-Key");
+        hasher.update(&key);
+        let aes_key = hasher.finish();
+
+        let mut hasher = Sha512::new();
+        hasher.update(b"Pair-Setup-AES-IV");
+        hasher.update(&key);
+        let mut aes_iv = hasher.finish();
+        aes_iv[15] = aes_iv[15].wrapping_add(1);
+
+        let epk = decrypt_aead(Cipher::aes_128_gcm(), &aes_key[0..16], Some(&aes_iv[0..16]), &[], request.get("epk")?.as_data()?, request.get("authTag")?.as_data()?).unwrap();
+
+        write_response(&mut socket, "200 OK", &cseq, Some(PlistValue::Dictionary(vec![]))).await;
+
+        Some(epk)
+    }
+
+    #[tokio::test]
+    async fn test_pair_setup() {
+        let mut listener = TcpListener::from_std(std::net::TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, 0)).unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let apple_tv = tokio::spawn(async move { simulate_apple_tv(&mut listener, "1234").await });
+
+        let mut client = connect(addr);
+        client.pair_pin_start().await.unwrap();
+        let secret = client.pair_setup("1234").await.unwrap();
+
+        // the receiver must have learned the key pair_verify will later authenticate with
+        let secret = <[u8; curve25519::SECRET_KEY_SIZE]>::from_hex(secret).unwrap();
+        let (_, auth_pub) = curve25519::create_key_pair(&secret).unwrap();
+
+        assert_eq!(apple_tv.await.unwrap(), Some(auth_pub.to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_pair_setup_wrong_pin() {
+        let mut listener = TcpListener::from_std(std::net::TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, 0)).unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let apple_tv = tokio::spawn(async move { simulate_apple_tv(&mut listener, "1234").await });
+
+        let mut client = connect(addr);
+        client.pair_pin_start().await.unwrap();
+
+        match client.pair_setup("4321").await {
+            Err(super::RtspError::ClientError { status: 470, .. }) => {},
+            result => panic!("expected pairing to be refused, got {:?}", result),
+        }
+
+        assert_eq!(apple_tv.await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout() {
+        let mut listener = TcpListener::from_std(std::net::TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, 0)).unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // reads requests, but never answers them
+        let receiver = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut requests = String::new();
+            socket.read_to_string(&mut requests).await.unwrap();
+            requests
+        });
+
+        let mut client = connect(addr);
+        client.set_request_timeout(Some(Duration::from_millis(100)));
+
+        match client.options(vec![]).await {
+            Err(super::RtspError::Timeout("OPTIONS")) => {},
+            result => panic!("expected the request to time out, got {:?}", result),
+        }
+
+        // the connection is closed, so a late answer can't be taken for the next response
+        assert!(receiver.await.unwrap().starts_with("OPTIONS * RTSP/1.0\r\n"));
+        assert!(client.options(vec![]).await.is_err());
+    }
+}
@@ -7,7 +7,7 @@ use log::debug;
 
 use super::RtspError;
 
-pub type Response = (Vec<(String, String)>, String);
+pub type Response = (Vec<(String, String)>, Vec<u8>);
 
 #[derive(Debug)]
 pub enum ParseResponseError {
@@ -69,17 +69,16 @@ impl ResponseBuilder {
     }
 
     pub fn body(self, data: Vec<u8>) -> Result<Response, RtspError> {
-        let content = String::from_utf8(data)?;
-
-        for line in content.lines() {
-            debug!("<---- {}", line);
+        match std::str::from_utf8(&data) {
+            Ok(content) => for line in content.lines() { debug!("<---- {}", line); },
+            Err(_) => debug!("<---- ({} bytes binary data)", data.len()),
         }
 
         match self.status {
-            200..=299 => Ok((self.headers, content)),
-            400..=499 => Err(RtspError::ClientError { status: self.status, headers: self.headers, body: content }),
-            500..=599 => Err(RtspError::ServerError { status: self.status, headers: self.headers, body: content }),
-            _ => Err(RtspError::UnknownError { status: self.status, headers: self.headers, body: content })
+            200..=299 => Ok((self.headers, data)),
+            400..=499 => Err(RtspError::ClientError { status: self.status, headers: self.headers, body: String::from_utf8(data)? }),
+            500..=599 => Err(RtspError::ServerError { status: self.status, headers: self.headers, body: String::from_utf8(data)? }),
+            _ => Err(RtspError::UnknownError { status: self.status, headers: self.headers, body: String::from_utf8(data)? })
         }
     }
 }
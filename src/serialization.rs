@@ -15,3 +15,16 @@ pub trait Deserializable: Sized {
     const SIZE: usize;
     fn deserialize(reader: &mut dyn Read) -> io::Result<Self>;
 }
+
+// reads len bytes of a length-prefixed field, what names the field when the data ends before it
+pub fn read_exact_vec(reader: &mut dyn Read, len: u64, what: &str) -> io::Result<Vec<u8>> {
+    // don't trust the length for the allocation, a short read is caught below
+    let mut data = Vec::new();
+    reader.take(len).read_to_end(&mut data)?;
+
+    if data.len() as u64 != len {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("truncated {}", what)));
+    }
+
+    Ok(data)
+}
@@ -0,0 +1,288 @@
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt::{self, Formatter, Display};
+
+use openssl::bn::{BigNum, BigNumContext, BigNumRef};
+use openssl::error::ErrorStack;
+use openssl::sha::Sha1;
+use rand::random;
+
+// 2048-bit group from RFC 5054, appendix A
+const PRIME_2048: &str = "AC6BDB41324A9A9BF166DE5E1389582FAF72B6651987EE07FC3192943DB56050A37329CBB4A099ED8193E0757767A13DD52312AB4B03310DCD7F48A9DA04FD50E8083969EDB767B0CF6095179A163AB3661A05FBD5FAAAE82918A9962F0B93B855F97993EC975EEAA80D740ADBF4FF747359D041D5C33EA71D281E446B14773BCA97B43A23FB801676BD207A436C6481F1D2B9078717461A5B9D32E688F87748544523B524B0D57D5EA77A2775D2ECFA032CFBDBF52FB3786160279004E57AE6AF874E7303CE53299CCC041C7BC308D82A5698F3A8D0C38271AE35F8E9DBFBB694B5C803D89F7AE435DE236D525F54759B65E372FCD68EF20FA7111F9E4AFF73";
+const GENERATOR: u32 = 2;
+
+#[derive(Debug)]
+pub enum SrpError {
+    OpenSslError(ErrorStack),
+    InvalidPublicKey,
+}
+
+impl Display for SrpError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for SrpError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SrpError::OpenSslError(source) => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<ErrorStack> for SrpError {
+    fn from(error: ErrorStack) -> Self {
+        SrpError::OpenSslError(error)
+    }
+}
+
+struct Group {
+    n: BigNum,
+    g: BigNum,
+    k: BigNum,
+}
+
+impl Group {
+    fn new() -> Result<Group, ErrorStack> {
+        let n = BigNum::from_hex_str(PRIME_2048)?;
+        let g = BigNum::from_u32(GENERATOR)?;
+        let k = BigNum::from_slice(&sha1(&[&n.to_vec(), &pad(&g, &n)]))?;
+
+        Ok(Group { n, g, k })
+    }
+}
+
+fn sha1(parts: &[&[u8]]) -> [u8; 20] {
+    let mut hasher = Sha1::new();
+    for part in parts { hasher.update(part); }
+    hasher.finish()
+}
+
+fn pad(value: &BigNumRef, n: &BigNumRef) -> Vec<u8> {
+    let bytes = value.to_vec();
+    let mut padded = vec![0u8; n.num_bytes() as usize - bytes.len()];
+    padded.extend_from_slice(&bytes);
+    padded
+}
+
+// x = H(s | H(I ":" P))
+fn private_key(salt: &[u8], username: &str, password: &str) -> Result<BigNum, ErrorStack> {
+    let inner = sha1(&[username.as_bytes(), b":", password.as_bytes()]);
+    BigNum::from_slice(&sha1(&[salt, &inner]))
+}
+
+// Apple derives a 40 byte session key from the premaster secret instead of plain H(S)
+fn session_key(premaster: &BigNumRef) -> Vec<u8> {
+    let premaster = premaster.to_vec();
+    let mut key = sha1(&[&premaster, &[0, 0, 0, 0]]).to_vec();
+    key.extend_from_slice(&sha1(&[&premaster, &[0, 0, 0, 1]]));
+    key
+}
+
+// M1 = H(H(N) xor H(g) | H(I) | s | A | B | K)
+fn client_proof(group: &Group, username: &str, salt: &[u8], a: &BigNumRef, b: &BigNumRef, key: &[u8]) -> [u8; 20] {
+    let hn = sha1(&[&group.n.to_vec()]);
+    let hg = sha1(&[&group.g.to_vec()]);
+    let hxor = hn.iter().zip(hg.iter()).map(|(n, g)| n ^ g).collect::<Vec<u8>>();
+
+    sha1(&[&hxor, &sha1(&[username.as_bytes()]), salt, &a.to_vec(), &b.to_vec(), key])
+}
+
+// M2 = H(A | M1 | K)
+fn server_proof(a: &BigNumRef, proof: &[u8], key: &[u8]) -> [u8; 20] {
+    sha1(&[&a.to_vec(), proof, key])
+}
+
+pub struct SrpClient {
+    group: Group,
+    username: String,
+    password: String,
+    secret: BigNum,
+    public: BigNum,
+}
+
+pub struct SrpSession {
+    key: Vec<u8>,
+    proof: [u8; 20],
+    server_proof: [u8; 20],
+}
+
+impl SrpClient {
+    pub fn new(username: &str, password: &str) -> Result<SrpClient, SrpError> {
+        let group = Group::new()?;
+        let secret = BigNum::from_slice(&random::<[u8; 32]>())?;
+
+        let mut ctx = BigNumContext::new()?;
+        let mut public = BigNum::new()?;
+        public.mod_exp(&group.g, &secret, &group.n, &mut ctx)?;
+
+        Ok(SrpClient { group, username: username.to_owned(), password: password.to_owned(), secret, public })
+    }
+
+    pub fn public_key(&self) -> Vec<u8> {
+        self.public.to_vec()
+    }
+
+    pub fn process(&self, salt: &[u8], server_public: &[u8]) -> Result<SrpSession, SrpError> {
+        let mut ctx = BigNumContext::new()?;
+        let n = &self.group.n;
+
+        // B must be within ]0, N[ so that B % N is not zero
+        let b = BigNum::from_slice(server_public)?;
+        if b.num_bits() == 0 || b.ucmp(n) != Ordering::Less { return Err(SrpError::InvalidPublicKey); }
+
+        // u = H(PAD(A) | PAD(B))
+        let u = BigNum::from_slice(&sha1(&[&pad(&self.public, n), &pad(&b, n)]))?;
+        if u.num_bits() == 0 { return Err(SrpError::InvalidPublicKey); }
+
+        let x = private_key(salt, &self.username, &self.password)?;
+
+        // S = (B - k * g^x) ^ (a + u * x) % N
+        let mut gx = BigNum::new()?;
+        gx.mod_exp(&self.group.g, &x, n, &mut ctx)?;
+
+        let mut kgx = BigNum::new()?;
+        kgx.mod_mul(&self.group.k, &gx, n, &mut ctx)?;
+
+        let mut base = BigNum::new()?;
+        base.mod_sub(&b, &kgx, n, &mut ctx)?;
+
+        let mut ux = BigNum::new()?;
+        ux.checked_mul(&u, &x, &mut ctx)?;
+
+        let mut exponent = BigNum::new()?;
+        exponent.checked_add(&self.secret, &ux)?;
+
+        let mut premaster = BigNum::new()?;
+        premaster.mod_exp(&base, &exponent, n, &mut ctx)?;
+
+        let key = session_key(&premaster);
+        let proof = client_proof(&self.group, &self.username, salt, &self.public, &b, &key);
+        let server_proof = server_proof(&self.public, &proof, &key);
+
+        Ok(SrpSession { key, proof, server_proof })
+    }
+}
+
+impl SrpSession {
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    pub fn proof(&self) -> &[u8] {
+        &self.proof
+    }
+
+    pub fn verify_server(&self, proof: &[u8]) -> bool {
+        openssl::memcmp::eq(&self.server_proof, proof)
+    }
+}
+
+// The receiver side of the exchange, used to simulate an Apple TV
+#[cfg(test)]
+pub struct SrpServer {
+    group: Group,
+    username: String,
+    salt: [u8; 16],
+    verifier: BigNum,
+    secret: BigNum,
+    public: BigNum,
+}
+
+#[cfg(test)]
+impl SrpServer {
+    pub fn new(username: &str, password: &str) -> SrpServer {
+        let group = Group::new().unwrap();
+        let mut ctx = BigNumContext::new().unwrap();
+
+        let salt: [u8; 16] = random();
+        let x = private_key(&salt, username, password).unwrap();
+
+        let mut verifier = BigNum::new().unwrap();
+        verifier.mod_exp(&group.g, &x, &group.n, &mut ctx).unwrap();
+
+        // B = k * v + g^b % N
+        let secret = BigNum::from_slice(&random::<[u8; 32]>()).unwrap();
+        let mut gb = BigNum::new().unwrap();
+        gb.mod_exp(&group.g, &secret, &group.n, &mut ctx).unwrap();
+        let mut kv = BigNum::new().unwrap();
+        kv.mod_mul(&group.k, &verifier, &group.n, &mut ctx).unwrap();
+        let mut public = BigNum::new().unwrap();
+        public.mod_add(&kv, &gb, &group.n, &mut ctx).unwrap();
+
+        SrpServer { group, username: username.to_owned(), salt, verifier, secret, public }
+    }
+
+    pub fn salt(&self) -> Vec<u8> {
+        self.salt.to_vec()
+    }
+
+    pub fn public_key(&self) -> Vec<u8> {
+        self.public.to_vec()
+    }
+
+    // checks the client proof, returning the session key and the server proof
+    pub fn verify_client(&self, client_public: &[u8], proof: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+        let n = &self.group.n;
+        let mut ctx = BigNumContext::new().unwrap();
+
+        let a = BigNum::from_slice(client_public).unwrap();
+        let u = BigNum::from_slice(&sha1(&[&pad(&a, n), &pad(&self.public, n)])).unwrap();
+
+        // S = (A * v^u) ^ b % N
+        let mut vu = BigNum::new().unwrap();
+        vu.mod_exp(&self.verifier, &u, n, &mut ctx).unwrap();
+        let mut base = BigNum::new().unwrap();
+        base.mod_mul(&a, &vu, n, &mut ctx).unwrap();
+        let mut premaster = BigNum::new().unwrap();
+        premaster.mod_exp(&base, &self.secret, n, &mut ctx).unwrap();
+
+        let key = session_key(&premaster);
+        let expected = client_proof(&self.group, &self.username, &self.salt, &a, &self.public, &key);
+
+        if expected[..] != *proof {
+            return None;
+        }
+
+        let server_proof = server_proof(&a, proof, &key).to_vec();
+        Some((key, server_proof))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_handshake() {
+        let server = super::SrpServer::new("366B4165DD64AD3A", "1234");
+        let client = super::SrpClient::new("366B4165DD64AD3A", "1234").unwrap();
+
+        let session = client.process(&server.salt(), &server.public_key()).unwrap();
+        let (key, server_proof) = server.verify_client(&client.public_key(), session.proof()).unwrap();
+
+        assert_eq!(session.key(), &key[..]);
+        assert_eq!(session.key().len(), 40);
+        assert!(session.verify_server(&server_proof));
+    }
+
+    #[test]
+    fn test_wrong_pin() {
+        let server = super::SrpServer::new("366B4165DD64AD3A", "1234");
+        let client = super::SrpClient::new("366B4165DD64AD3A", "4321").unwrap();
+
+        let session = client.process(&server.salt(), &server.public_key()).unwrap();
+
+        assert!(server.verify_client(&client.public_key(), session.proof()).is_none());
+    }
+
+    #[test]
+    fn test_reject_zero_public_key() {
+        let client = super::SrpClient::new("366B4165DD64AD3A", "1234").unwrap();
+
+        match client.process(&[0; 16], &[0; 256]) {
+            Err(super::SrpError::InvalidPublicKey) => {},
+            _ => panic!("expected an invalid public key error"),
+        }
+    }
+}
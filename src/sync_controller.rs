@@ -23,7 +23,8 @@ pub struct SyncController {
 }
 
 impl SyncController {
-    pub fn start(socket: UdpSocket, status_mutex: Arc<Mutex<Status>>, sane_mutex: Arc<Mutex<Sane>>, retransmit: Arc<Beefeater<u32>>, latency: Frames, sample_rate: SampleRate) -> SyncController {
+    // lost is set when the control channel breaks
+    pub fn start(socket: UdpSocket, status_mutex: Arc<Mutex<Status>>, sane_mutex: Arc<Mutex<Sane>>, retransmit: Arc<Beefeater<u32>>, latency: Frames, sample_rate: SampleRate, lost: Arc<Beefeater<bool>>) -> SyncController {
         let (recv, send) = socket.split();
         let (abort_handle, abort_registration) = AbortHandle::new_pair();
 
@@ -32,7 +33,23 @@ impl SyncController {
         let receiving = receive(recv, Arc::clone(&send_mutex), Arc::clone(&status_mutex), sane_mutex, retransmit);
         let sending = send_sync_every_second(Arc::clone(&send_mutex), status_mutex, latency, sample_rate);
 
-        let pair = join(receiving.map(|result| { result.unwrap(); }), sending.map(|result| { result.unwrap(); }));
+        let receiving = {
+            let lost = Arc::clone(&lost);
+            receiving.map(move |result| {
+                if let Err(err) = result {
+                    error!("receiving retransmit requests stopped: {}", err);
+                    lost.store(true);
+                }
+            })
+        };
+        let sending = sending.map(move |result| {
+            if let Err(err) = result {
+                error!("sending sync packets stopped: {}", err);
+                lost.store(true);
+            }
+        });
+
+        let pair = join(receiving, sending);
         let future = Abortable::new(pair, abort_registration).map(|_| {});
 
         tokio::spawn(future);
@@ -51,6 +68,12 @@ impl SyncController {
     }
 }
 
+impl Drop for SyncController {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
 async fn send_sync_paket(mutex: Arc<Mutex<SendHalf>>, rsp: RtpSyncPacket) -> Result<(), std::io::Error> {
     let n = {
         let mut send = mutex.lock().await;
@@ -93,21 +116,24 @@ async fn receive(mut recv: RecvHalf, send_mutex: Arc<Mutex<SendHalf>>, status_mu
             let status = status_mutex.lock().await;
 
             for i in 0..lost.n {
-                let index = ((lost.seq_number + i) % MAX_BACKLOG) as usize;
+                let seq_number = lost.seq_number.wrapping_add(i);
+                let index = (seq_number % MAX_BACKLOG) as usize;
 
-                if status.backlog[index].as_ref().map(|e| e.seq_number).unwrap_or(0) == lost.seq_number + i {
+                if status.backlog[index].as_ref().map(|e| e.seq_number).unwrap_or(0) == seq_number {
                     if let Some(ref entry) = status.backlog[index] {
                         retransmit.add_assign(1);
                         {
                             let mut send = send_mutex.lock().await;
-                            send.send(&RtpAudioRetransmissionPacket::wrap(&entry.packet).as_bytes()).await.unwrap();
+                            if let Err(err) = send.send(&RtpAudioRetransmissionPacket::wrap(&entry.packet).as_bytes()).await {
+                                error!("failed to retransmit packet {}: {}", seq_number, err);
+                            }
                         }
                     } else {
                         // packet have been released meanwhile, be extra cautious
                         missed += 1;
                     }
                 } else {
-                    warn!("lost packet out of backlog {}", lost.seq_number + i);
+                    warn!("lost packet out of backlog {}", seq_number);
                 }
             }
         }
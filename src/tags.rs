@@ -0,0 +1,618 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::time::Duration;
+
+use byteorder::{BE, LE, ByteOrder, ReadBytesExt};
+use log::debug;
+
+use crate::meta_data::TrackMetadata;
+use crate::serialization::read_exact_vec;
+
+// front cover in both ID3v2 APIC frames and FLAC PICTURE blocks
+const PICTURE_TYPE_FRONT_COVER: u8 = 3;
+
+const FLAC_STREAMINFO: u8 = 0;
+const FLAC_VORBIS_COMMENT: u8 = 4;
+const FLAC_PICTURE: u8 = 6;
+
+#[derive(Clone, Default)]
+pub struct Tags {
+    pub metadata: TrackMetadata,
+    pub artwork: Option<Vec<u8>>,
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    let value = value.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+    if value.is_empty() { None } else { Some(value.to_owned()) }
+}
+
+// "3/12" style numbers, as used by TRCK, TPOS and some Vorbis comments
+fn parse_number_pair(value: &str) -> (Option<u16>, Option<u16>) {
+    let mut parts = value.splitn(2, '/');
+    let number = parts.next().and_then(|number| number.trim().parse().ok());
+    let count = parts.next().and_then(|count| count.trim().parse().ok());
+    (number, count)
+}
+
+fn parse_year(value: &str) -> Option<u16> {
+    value.trim().get(0..4).and_then(|year| year.parse().ok())
+}
+
+impl Tags {
+    pub fn from_file(path: &str) -> io::Result<Tags> {
+        Tags::read(&mut BufReader::new(File::open(path)?))
+    }
+
+    // files in a format without tags give empty tags
+    pub fn read<R: Read + Seek>(reader: &mut R) -> io::Result<Tags> {
+        let mut magic = [0u8; 12];
+        let n = reader.read(&mut magic)?;
+        reader.seek(SeekFrom::Start(0))?;
+
+        let mut tags = Tags::default();
+
+        if n >= 3 && &magic[0..3] == b"ID3" {
+            tags.read_id3v2(reader)?;
+        } else if n >= 4 && &magic[0..4] == b"fLaC" {
+            tags.read_flac(reader)?;
+        } else if n >= 12 && &magic[0..4] == b"RIFF" && &magic[8..12] == b"WAVE" {
+            tags.read_riff(reader)?;
+        } else {
+            debug!("no known tag format found");
+        }
+
+        Ok(tags)
+    }
+
+    fn set_text(&mut self, key: &str, value: &str) {
+        let metadata = &mut self.metadata;
+
+        match key {
+            "title" => metadata.title = non_empty(value),
+            "artist" => metadata.artist = non_empty(value),
+            "album" => metadata.album = non_empty(value),
+            "genre" => metadata.genre = non_empty(value),
+            "composer" => metadata.composer = non_empty(value),
+            "year" => metadata.year = parse_year(value),
+            "track" => {
+                let (number, count) = parse_number_pair(value);
+                metadata.track_number = number;
+                metadata.track_count = count.or(metadata.track_count);
+            }
+            "track_count" => metadata.track_count = value.trim().parse().ok(),
+            "disc" => {
+                let (number, count) = parse_number_pair(value);
+                metadata.disc_number = number;
+                metadata.disc_count = count.or(metadata.disc_count);
+            }
+            "disc_count" => metadata.disc_count = value.trim().parse().ok(),
+            "duration" => metadata.duration = value.trim().parse().ok().map(Duration::from_millis),
+            _ => {},
+        }
+    }
+
+    fn read_id3v2(&mut self, reader: &mut dyn Read) -> io::Result<()> {
+        let mut header = [0u8; 10];
+        reader.read_exact(&mut header)?;
+
+        let version = header[3];
+        let flags = header[5];
+        let size = syncsafe(&header[6..10]);
+
+        if !(2..=4).contains(&version) {
+            return Err(invalid_data("unsupported ID3v2 version"));
+        }
+
+        let mut data = read_exact_vec(reader, size as u64, "ID3 frames")?;
+
+        // whole tag unsynchronisation, v2.4 does this per frame instead
+        if flags & 0x80 != 0 && version < 4 {
+            data = resynchronise(&data);
+        }
+
+        let mut pos = 0;
+
+        // skip the extended header, v2.3 doesn't count its own size field
+        if flags & 0x40 != 0 && version > 2 {
+            let size = data.get(0..4).ok_or_else(|| invalid_data("truncated extended header"))?;
+            pos = if version == 4 { syncsafe(size) } else { BE::read_u32(size) as usize + 4 };
+        }
+
+        let (id_size, header_size) = if version == 2 { (3, 6) } else { (4, 10) };
+
+        while pos + header_size <= data.len() {
+            let id = &data[pos..pos + id_size];
+
+            // the rest is padding
+            if id[0] == 0 { break; }
+
+            let len = match version {
+                2 => BE::read_u24(&data[pos + 3..pos + 6]) as usize,
+                3 => BE::read_u32(&data[pos + 4..pos + 8]) as usize,
+                _ => syncsafe(&data[pos + 4..pos + 8]),
+            };
+
+            let frame_flags = if version == 2 { 0 } else { data[pos + 9] };
+            let start = pos + header_size;
+            let end = start.checked_add(len).filter(|end| *end <= data.len()).ok_or_else(|| invalid_data("ID3v2 frame out of bounds"))?;
+
+            // compressed or encrypted frames are skipped
+            let skip = match version {
+                3 => frame_flags & 0xC0 != 0,
+                4 => frame_flags & 0x0C != 0,
+                _ => false,
+            };
+
+            if !skip {
+                let mut frame = &data[start..end];
+
+                // v2.4 may prefix the frame with its decoded size
+                if version == 4 && frame_flags & 0x01 != 0 { frame = &frame[4.min(frame.len())..]; }
+
+                if version == 4 && frame_flags & 0x02 != 0 {
+                    self.read_id3v2_frame(&String::from_utf8_lossy(id), &resynchronise(frame));
+                } else {
+                    self.read_id3v2_frame(&String::from_utf8_lossy(id), frame);
+                }
+            }
+
+            pos = end;
+        }
+
+        Ok(())
+    }
+
+    fn read_id3v2_frame(&mut self, id: &str, frame: &[u8]) {
+        let key = match id {
+            "TIT2" | "TT2" => "title",
+            "TPE1" | "TP1" => "artist",
+            "TALB" | "TAL" => "album",
+            "TCON" | "TCO" => "genre",
+            "TCOM" | "TCM" => "composer",
+            "TRCK" | "TRK" => "track",
+            "TPOS" | "TPA" => "disc",
+            "TYER" | "TYE" | "TDRC" => "year",
+            "TLEN" | "TLE" => "duration",
+            "APIC" | "PIC" => return self.read_id3v2_picture(id == "PIC", frame),
+            _ => return,
+        };
+
+        if frame.is_empty() { return; }
+
+        // v2.4 separates multiple values with NUL, the first one is used
+        let text = decode_id3v2_text(frame[0], &frame[1..]).0;
+
+        // genres may be given as a reference to the ID3v1 list, "(17)Rock"
+        let text = if key == "genre" && text.starts_with('(') {
+            text.find(')').map(|end| text[end + 1..].to_owned()).filter(|rest| !rest.is_empty()).unwrap_or(text)
+        } else {
+            text
+        };
+
+        self.set_text(key, &text);
+    }
+
+    fn read_id3v2_picture(&mut self, v22: bool, frame: &[u8]) {
+        if frame.is_empty() { return; }
+
+        let encoding = frame[0];
+
+        // v2.2 has a three character image format instead of a MIME type
+        let pos = if v22 {
+            4
+        } else {
+            match frame[1..].iter().position(|b| *b == 0) {
+                Some(end) => 1 + end + 1,
+                None => return,
+            }
+        };
+
+        let picture_type = match frame.get(pos) {
+            Some(picture_type) => *picture_type,
+            None => return,
+        };
+
+        let (_, len) = decode_id3v2_text(encoding, &frame[pos + 1..]);
+        let data = &frame[(pos + 1 + len).min(frame.len())..];
+
+        self.set_artwork(picture_type, data);
+    }
+
+    fn set_artwork(&mut self, picture_type: u8, data: &[u8]) {
+        if data.is_empty() { return; }
+
+        if self.artwork.is_none() || picture_type == PICTURE_TYPE_FRONT_COVER {
+            self.artwork = Some(data.to_vec());
+        }
+    }
+
+    fn read_flac(&mut self, reader: &mut dyn Read) -> io::Result<()> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+
+        loop {
+            let header = reader.read_u32::<BE>()?;
+            let last = header & 0x8000_0000 != 0;
+            let block_type = ((header >> 24) & 0x7F) as u8;
+            let len = (header & 0x00FF_FFFF) as usize;
+
+            let block = read_exact_vec(reader, len as u64, "metadata block")?;
+
+            match block_type {
+                FLAC_STREAMINFO => self.read_flac_streaminfo(&block),
+                FLAC_VORBIS_COMMENT => self.read_vorbis_comment(&block)?,
+                FLAC_PICTURE => self.read_flac_picture(&block)?,
+                _ => {},
+            }
+
+            if last { break; }
+        }
+
+        Ok(())
+    }
+
+    fn read_flac_streaminfo(&mut self, block: &[u8]) {
+        if block.len() < 18 { return; }
+
+        let sample_rate = BE::read_u24(&block[10..13]) >> 4;
+        let total_samples = BE::read_u64(&block[10..18]) & 0x0F_FFFF_FFFF;
+
+        if sample_rate > 0 && total_samples > 0 {
+            self.metadata.duration = Some(Duration::from_millis(total_samples * 1000 / u64::from(sample_rate)));
+        }
+    }
+
+    fn read_vorbis_comment(&mut self, block: &[u8]) -> io::Result<()> {
+        let mut reader = block;
+
+        let vendor_len = reader.read_u32::<LE>()? as usize;
+        read_exact_vec(&mut reader, vendor_len as u64, "vendor string")?;
+
+        let count = reader.read_u32::<LE>()?;
+
+        for _ in 0..count {
+            let len = reader.read_u32::<LE>()? as usize;
+            let comment = read_exact_vec(&mut reader, len as u64, "comment")?;
+            let comment = String::from_utf8_lossy(&comment);
+
+            let (name, value) = match comment.find('=') {
+                Some(eq) => (comment[..eq].to_uppercase(), &comment[eq + 1..]),
+                None => continue,
+            };
+
+            let key = match name.as_str() {
+                "TITLE" => "title",
+                "ARTIST" => "artist",
+                "ALBUM" => "album",
+                "GENRE" => "genre",
+                "COMPOSER" => "composer",
+                "DATE" | "YEAR" => "year",
+                "TRACKNUMBER" => "track",
+                "TRACKTOTAL" | "TOTALTRACKS" => "track_count",
+                "DISCNUMBER" => "disc",
+                "DISCTOTAL" | "TOTALDISCS" => "disc_count",
+                _ => continue,
+            };
+
+            self.set_text(key, value);
+        }
+
+        Ok(())
+    }
+
+    fn read_flac_picture(&mut self, block: &[u8]) -> io::Result<()> {
+        let mut reader = block;
+
+        let picture_type = reader.read_u32::<BE>()?;
+        let mime_len = reader.read_u32::<BE>()? as usize;
+        read_exact_vec(&mut reader, mime_len as u64, "picture MIME type")?;
+        let description_len = reader.read_u32::<BE>()? as usize;
+        read_exact_vec(&mut reader, description_len as u64, "picture description")?;
+
+        // width, height, depth and number of colors
+        read_exact_vec(&mut reader, 16, "picture dimensions")?;
+
+        let len = reader.read_u32::<BE>()? as usize;
+        let data = read_exact_vec(&mut reader, len as u64, "picture data")?;
+
+        self.set_artwork(picture_type as u8, &data);
+
+        Ok(())
+    }
+
+    fn read_riff<R: Read + Seek>(&mut self, reader: &mut R) -> io::Result<()> {
+        let mut header = [0u8; 12];
+        reader.read_exact(&mut header)?;
+
+        let mut byte_rate = 0;
+        let mut data_size = 0;
+
+        loop {
+            let mut chunk_header = [0u8; 8];
+
+            match reader.read_exact(&mut chunk_header) {
+                Ok(()) => {},
+                Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err),
+            }
+
+            let id = &chunk_header[0..4];
+            let len = LE::read_u32(&chunk_header[4..8]) as usize;
+            // chunks are padded to an even size
+            let padded = len + len % 2;
+
+            match id {
+                b"fmt " if len >= 12 => {
+                    let chunk = read_exact_vec(reader, padded as u64, "fmt chunk")?;
+                    byte_rate = LE::read_u32(&chunk[8..12]);
+                }
+                b"LIST" if len >= 4 => {
+                    let chunk = read_exact_vec(reader, padded as u64, "LIST chunk")?;
+                    if &chunk[0..4] == b"INFO" { self.read_riff_info(&chunk[4..len]); }
+                }
+                b"id3 " | b"ID3 " => {
+                    let chunk = read_exact_vec(reader, padded as u64, "ID3 chunk")?;
+                    self.read_id3v2(&mut &chunk[..])?;
+                }
+                _ => {
+                    if id == b"data" { data_size = len; }
+                    reader.seek(SeekFrom::Current(padded as i64))?;
+                }
+            }
+        }
+
+        if self.metadata.duration.is_none() && byte_rate > 0 {
+            self.metadata.duration = Some(Duration::from_millis(data_size as u64 * 1000 / u64::from(byte_rate)));
+        }
+
+        Ok(())
+    }
+
+    fn read_riff_info(&mut self, mut data: &[u8]) {
+        while data.len() >= 8 {
+            let id = &data[0..4];
+            let len = LE::read_u32(&data[4..8]) as usize;
+
+            let value = match data.get(8..8 + len) {
+                Some(value) => String::from_utf8_lossy(value).into_owned(),
+                None => break,
+            };
+
+            let key = match id {
+                b"INAM" => "title",
+                b"IART" => "artist",
+                b"IPRD" => "album",
+                b"IGNR" => "genre",
+                b"ICRD" => "year",
+                b"ITRK" | b"IPRT" => "track",
+                _ => "",
+            };
+
+            self.set_text(key, &value);
+
+            data = &data[(8 + len + len % 2).min(data.len())..];
+        }
+    }
+}
+
+fn syncsafe(data: &[u8]) -> usize {
+    data.iter().fold(0, |size, b| (size << 7) | (*b & 0x7F) as usize)
+}
+
+// removes the zero bytes inserted after every 0xFF
+fn resynchronise(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len());
+
+    for (i, b) in data.iter().enumerate() {
+        if *b == 0 && i > 0 && data[i - 1] == 0xFF { continue; }
+        result.push(*b);
+    }
+
+    result
+}
+
+// returns the first string and the number of bytes it used, including its terminator
+fn decode_id3v2_text(encoding: u8, data: &[u8]) -> (String, usize) {
+    match encoding {
+        1 | 2 => {
+            let end = data.chunks(2).position(|unit| unit == [0, 0]).map(|end| end * 2);
+            let text = &data[..end.unwrap_or_else(|| data.len() - data.len() % 2)];
+
+            // encoding 1 starts with a byte order mark, 2 is always big endian
+            let (little_endian, text) = match text {
+                [0xFF, 0xFE, rest @ ..] => (true, rest),
+                [0xFE, 0xFF, rest @ ..] => (false, rest),
+                _ => (false, text),
+            };
+
+            let units = text.chunks_exact(2).map(|unit| if little_endian { LE::read_u16(unit) } else { BE::read_u16(unit) }).collect::<Vec<u16>>();
+            (String::from_utf16_lossy(&units), end.map(|end| end + 2).unwrap_or(data.len()))
+        }
+        _ => {
+            let end = data.iter().position(|b| *b == 0);
+            let text = &data[..end.unwrap_or(data.len())];
+
+            let text = if encoding == 3 {
+                String::from_utf8_lossy(text).into_owned()
+            } else {
+                text.iter().map(|b| *b as char).collect()
+            };
+
+            (text, end.map(|end| end + 1).unwrap_or(data.len()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use std::time::Duration;
+
+    use super::Tags;
+
+    fn id3v2_frame(id: &[u8], content: &[u8]) -> Vec<u8> {
+        let mut frame = id.to_vec();
+        frame.extend_from_slice(&(content.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(content);
+        frame
+    }
+
+    fn id3v2(version: u8, frames: &[Vec<u8>]) -> Vec<u8> {
+        let mut body = frames.concat();
+        body.extend_from_slice(&[0; 16]);
+
+        let size = body.len();
+        let mut tag = vec![b'I', b'D', b'3', version, 0, 0];
+        tag.extend_from_slice(&[(size >> 21) as u8 & 0x7F, (size >> 14) as u8 & 0x7F, (size >> 7) as u8 & 0x7F, size as u8 & 0x7F]);
+        tag.extend_from_slice(&body);
+        tag
+    }
+
+    #[test]
+    fn test_read_id3v2() {
+        let mut picture = vec![0];
+        picture.extend_from_slice(b"image/png\0");
+        picture.extend_from_slice(&[3, 0]);
+        picture.extend_from_slice(b"\x89PNG");
+
+        let data = id3v2(3, &[
+            id3v2_frame(b"TIT2", b"\x00Title"),
+            id3v2_frame(b"TPE1", b"\x01\xFF\xFEA\x00r\x00t\x00\x00\x00"),
+            id3v2_frame(b"TALB", b"\x03Alb\xC3\xB6m\x00"),
+            id3v2_frame(b"TCON", b"\x00(8)Jazz"),
+            id3v2_frame(b"TRCK", b"\x003/12"),
+            id3v2_frame(b"TYER", b"\x001959"),
+            id3v2_frame(b"APIC", &picture),
+        ]);
+
+        let tags = Tags::read(&mut Cursor::new(data)).unwrap();
+
+        assert_eq!(tags.metadata.title.as_deref(), Some("Title"));
+        assert_eq!(tags.metadata.artist.as_deref(), Some("Art"));
+        assert_eq!(tags.metadata.album.as_deref(), Some("Alböm"));
+        assert_eq!(tags.metadata.genre.as_deref(), Some("Jazz"));
+        assert_eq!(tags.metadata.track_number, Some(3));
+        assert_eq!(tags.metadata.track_count, Some(12));
+        assert_eq!(tags.metadata.year, Some(1959));
+        assert_eq!(tags.artwork.as_deref(), Some(b"\x89PNG".as_ref()));
+    }
+
+    #[test]
+    fn test_read_id3v24_syncsafe_frames() {
+        let mut frame = b"TIT2".to_vec();
+        frame.extend_from_slice(&[0, 0, 1, 0x05, 0, 0]);
+        frame.push(3);
+        frame.extend_from_slice(&[b'x'; 132]);
+
+        let tags = Tags::read(&mut Cursor::new(id3v2(4, &[frame]))).unwrap();
+
+        assert_eq!(tags.metadata.title.map(|title| title.len()), Some(132));
+    }
+
+    fn flac_block(block_type: u8, last: bool, content: &[u8]) -> Vec<u8> {
+        let mut block = vec![block_type | if last { 0x80 } else { 0 }];
+        block.extend_from_slice(&(content.len() as u32).to_be_bytes()[1..]);
+        block.extend_from_slice(content);
+        block
+    }
+
+    #[test]
+    fn test_read_flac() {
+        // 44100 Hz, stereo, 16 bits, 441000 samples
+        let mut streaminfo = vec![0x10, 0x00, 0x10, 0x00, 0, 0, 0, 0, 0, 0];
+        streaminfo.extend_from_slice(&[0x0A, 0xC4, 0x42, 0xF0, 0x00, 0x06, 0xBA, 0xA8]);
+        streaminfo.extend_from_slice(&[0; 16]);
+
+        let mut comments = Vec::new();
+        comments.extend_from_slice(&4u32.to_le_bytes());
+        comments.extend_from_slice(b"test");
+        comments.extend_from_slice(&3u32.to_le_bytes());
+        for comment in &["title=Title", "ARTIST=Artist", "DISCNUMBER=2"] {
+            comments.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            comments.extend_from_slice(comment.as_bytes());
+        }
+
+        let mut picture = Vec::new();
+        picture.extend_from_slice(&3u32.to_be_bytes());
+        picture.extend_from_slice(&10u32.to_be_bytes());
+        picture.extend_from_slice(b"image/jpeg");
+        picture.extend_from_slice(&0u32.to_be_bytes());
+        picture.extend_from_slice(&[0; 16]);
+        picture.extend_from_slice(&3u32.to_be_bytes());
+        picture.extend_from_slice(&[0xFF, 0xD8, 0xFF]);
+
+        let data = [
+            b"fLaC".to_vec(),
+            flac_block(0, false, &streaminfo),
+            flac_block(4, false, &comments),
+            flac_block(6, true, &picture),
+        ].concat();
+
+        let tags = Tags::read(&mut Cursor::new(data)).unwrap();
+
+        assert_eq!(tags.metadata.title.as_deref(), Some("Title"));
+        assert_eq!(tags.metadata.artist.as_deref(), Some("Artist"));
+        assert_eq!(tags.metadata.disc_number, Some(2));
+        assert_eq!(tags.metadata.duration, Some(Duration::from_secs(10)));
+        assert_eq!(tags.artwork, Some(vec![0xFF, 0xD8, 0xFF]));
+    }
+
+    #[test]
+    fn test_read_truncated() {
+        let error = |data: Vec<u8>| Tags::read(&mut Cursor::new(data)).err().unwrap().to_string();
+
+        // the comment claims more than the block holds
+        let mut comments = 0u32.to_le_bytes().to_vec();
+        comments.extend_from_slice(&1u32.to_le_bytes());
+        comments.extend_from_slice(&20u32.to_le_bytes());
+        comments.extend_from_slice(b"title=Title");
+
+        assert_eq!(error([b"fLaC".to_vec(), flac_block(4, true, &comments)].concat()), "truncated comment");
+
+        // and the block more than the file
+        let mut data = [b"fLaC".to_vec(), flac_block(4, true, &comments)].concat();
+        data.truncate(10);
+        assert_eq!(error(data), "truncated metadata block");
+    }
+
+    #[test]
+    fn test_read_riff_info() {
+        let mut info = b"INFO".to_vec();
+        info.extend_from_slice(b"INAM\x05\x00\x00\x00Title\x00");
+        info.extend_from_slice(b"IART\x06\x00\x00\x00Artist");
+
+        let mut fmt = vec![1, 0, 2, 0];
+        fmt.extend_from_slice(&44100u32.to_le_bytes());
+        fmt.extend_from_slice(&176_400u32.to_le_bytes());
+        fmt.extend_from_slice(&[4, 0, 16, 0]);
+
+        let mut data = b"RIFF\x00\x00\x00\x00WAVE".to_vec();
+        data.extend_from_slice(b"fmt \x10\x00\x00\x00");
+        data.extend_from_slice(&fmt);
+        data.extend_from_slice(b"data");
+        data.extend_from_slice(&352_800u32.to_le_bytes());
+        data.extend_from_slice(&vec![0; 352_800]);
+        data.extend_from_slice(b"LIST");
+        data.extend_from_slice(&(info.len() as u32).to_le_bytes());
+        data.extend_from_slice(&info);
+
+        let tags = Tags::read(&mut Cursor::new(data)).unwrap();
+
+        assert_eq!(tags.metadata.title.as_deref(), Some("Title"));
+        assert_eq!(tags.metadata.artist.as_deref(), Some("Artist"));
+        assert_eq!(tags.metadata.duration, Some(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn test_read_untagged() {
+        let tags = Tags::read(&mut Cursor::new(vec![0u8; 64])).unwrap();
+
+        assert!(tags.metadata.title.is_none());
+        assert!(tags.artwork.is_none());
+    }
+}
@@ -0,0 +1,240 @@
+// an AirPlay receiver on the loopback interface that accepts every request, shared by the unit and the integration tests
+#![allow(dead_code)]
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::future::{join, Abortable, AbortHandle};
+use futures::prelude::*;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+// how long a test waits for the client before failing
+const WAIT: Duration = Duration::from_secs(10);
+
+// how long nothing has to arrive for the client to be considered done
+const QUIET: Duration = Duration::from_millis(200);
+
+pub struct Request {
+    pub method: String,
+    pub uri: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|header| header.0.eq_ignore_ascii_case(name)).map(|header| header.1.as_str())
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioPacket {
+    // the marker bit is set on the first packet after RECORD and every FLUSH
+    pub first: bool,
+    pub seq: u16,
+    pub timestamp: u32,
+    pub payload: Vec<u8>,
+}
+
+pub struct Receiver {
+    pub addr: SocketAddr,
+
+    requests: mpsc::UnboundedReceiver<Request>,
+    audio: mpsc::UnboundedReceiver<AudioPacket>,
+
+    connections: Arc<Mutex<Vec<AbortHandle>>>,
+    abort_handle: AbortHandle,
+
+    // never read from, but a closed port would fail the sockets of the client
+    control: UdpSocket,
+    timing: UdpSocket,
+}
+
+impl Receiver {
+    pub async fn start() -> Receiver {
+        Receiver::start_with_latency(None).await
+    }
+
+    // answers RECORD with the given Audio-Latency
+    pub async fn start_with_latency(audio_latency: Option<u32>) -> Receiver {
+        // tokio's own bind trips over the socket address layout of newer compilers
+        let listener = TcpListener::from_std(std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let audio = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let control = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let timing = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+
+        let transport = format!(
+            "RTP/AVP/UDP;unicast;mode=record;server_port={};control_port={};timing_port={}",
+            audio.local_addr().unwrap().port(),
+            control.local_addr().unwrap().port(),
+            timing.local_addr().unwrap().port(),
+        );
+
+        let (request_sender, requests) = mpsc::unbounded_channel();
+        let (audio_sender, audio_packets) = mpsc::unbounded_channel();
+        let connections = Arc::new(Mutex::new(Vec::new()));
+
+        let serving = join(accept(listener, transport, audio_latency, request_sender, Arc::clone(&connections)), receive_audio(audio, audio_sender));
+
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+        tokio::spawn(Abortable::new(serving, abort_registration).map(|_| {}));
+
+        Receiver { addr, requests, audio: audio_packets, connections, abort_handle, control, timing }
+    }
+
+    // the next request with the given method, skipping keepalives and everything else before it
+    pub async fn request(&mut self, method: &str) -> Request {
+        loop {
+            let request = timeout(WAIT, self.requests.recv()).await
+                .unwrap_or_else(|_| panic!("no {} request in time", method))
+                .unwrap_or_else(|| panic!("receiver stopped before a {} request", method));
+
+            if request.method == method {
+                return request;
+            }
+        }
+    }
+
+    // every request until the client goes quiet
+    pub async fn requests(&mut self) -> Vec<Request> {
+        let mut requests = Vec::new();
+
+        while let Ok(Some(request)) = timeout(QUIET, self.requests.recv()).await {
+            requests.push(request);
+        }
+
+        requests
+    }
+
+    pub async fn audio(&mut self) -> AudioPacket {
+        timeout(WAIT, self.audio.recv()).await.expect("no audio packet in time").expect("receiver stopped")
+    }
+
+    // every audio packet until the client goes quiet
+    pub async fn audio_packets(&mut self) -> Vec<AudioPacket> {
+        let mut packets = Vec::new();
+
+        while let Ok(Some(packet)) = timeout(QUIET, self.audio.recv()).await {
+            packets.push(packet);
+        }
+
+        packets
+    }
+
+    // closes every connection accepted so far, like a rebooting receiver, new ones are still accepted
+    pub fn hang_up(&self) {
+        for connection in self.connections.lock().unwrap().drain(..) {
+            connection.abort();
+        }
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        self.hang_up();
+        self.abort_handle.abort();
+    }
+}
+
+// accepts the connection but never answers, returns what it was sent once the client hung up
+pub fn stalling() -> (SocketAddr, JoinHandle<String>) {
+    let mut listener = TcpListener::from_std(std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let receiver = tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut requests = String::new();
+        socket.read_to_string(&mut requests).await.unwrap();
+        requests
+    });
+
+    (addr, receiver)
+}
+
+async fn accept(mut listener: TcpListener, transport: String, audio_latency: Option<u32>, requests: mpsc::UnboundedSender<Request>, connections: Arc<Mutex<Vec<AbortHandle>>>) {
+    while let Ok((socket, _)) = listener.accept().await {
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+        connections.lock().unwrap().push(abort_handle);
+
+        let serving = serve(socket, transport.clone(), audio_latency, requests.clone());
+        tokio::spawn(Abortable::new(serving, abort_registration).map(|_| {}));
+    }
+}
+
+async fn serve(socket: TcpStream, transport: String, audio_latency: Option<u32>, requests: mpsc::UnboundedSender<Request>) {
+    let mut socket = BufReader::new(socket);
+
+    while let Some(request) = read_request(&mut socket).await {
+        let mut response = format!("RTSP/1.0 200 OK\r\nCSeq: {}\r\n", request.header("CSeq").unwrap_or("0"));
+
+        match (request.method.as_str(), audio_latency) {
+            ("SETUP", _) => response.push_str(&format!("Transport: {}\r\nSession: 1\r\n", transport)),
+            ("RECORD", Some(latency)) => response.push_str(&format!("Audio-Latency: {}\r\n", latency)),
+            _ => {},
+        }
+
+        response.push_str("\r\n");
+
+        // handed over before answering, so the request is there once the client returns
+        let _ = requests.send(request);
+
+        if socket.get_mut().write_all(response.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn read_request(socket: &mut BufReader<TcpStream>) -> Option<Request> {
+    let mut line = String::new();
+    if socket.read_line(&mut line).await.ok()? == 0 { return None; }
+
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_owned();
+    let uri = parts.next()?.to_owned();
+    let mut headers = Vec::new();
+
+    loop {
+        let mut line = String::new();
+        if socket.read_line(&mut line).await.ok()? == 0 { return None; }
+
+        let line = line.trim_end();
+        if line.is_empty() { break; }
+
+        let colon = line.find(':')?;
+        headers.push((line[..colon].to_owned(), line[colon + 1..].trim().to_owned()));
+    }
+
+    let content_length = headers.iter().find(|header| header.0.eq_ignore_ascii_case("Content-Length")).map_or(0, |header| header.1.parse().unwrap());
+    let mut body = vec![0u8; content_length];
+    socket.read_exact(&mut body).await.ok()?;
+
+    Some(Request { method, uri, headers, body })
+}
+
+async fn receive_audio(mut socket: UdpSocket, packets: mpsc::UnboundedSender<AudioPacket>) {
+    let mut buffer = [0u8; 2048];
+
+    while let Ok((n, _)) = socket.recv_from(&mut buffer).await {
+        if n < 12 { continue; }
+
+        let packet = AudioPacket {
+            first: buffer[1] & 0x80 != 0,
+            seq: u16::from_be_bytes([buffer[2], buffer[3]]),
+            timestamp: u32::from_be_bytes([buffer[4], buffer[5], buffer[6], buffer[7]]),
+            payload: buffer[12..n].to_vec(),
+        };
+
+        if packets.send(packet).is_err() { return; }
+    }
+}
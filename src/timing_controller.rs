@@ -19,7 +19,9 @@ impl TimingController {
     pub fn start(socket: UdpSocket) -> TimingController {
         let (abort_handle, abort_registration) = AbortHandle::new_pair();
 
-        let future = run(socket).map(|result| { result.unwrap(); });
+        let future = run(socket).map(|result| {
+            if let Err(err) = result { error!("timing stopped: {}", err); }
+        });
         let future = Abortable::new(future, abort_registration).map(|_| {});
 
         tokio::spawn(future);
@@ -34,7 +36,13 @@ impl TimingController {
     }
 }
 
-async fn run(mut socket: UdpSocket) -> Result<(), Box<dyn std::error::Error>> {
+impl Drop for TimingController {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+async fn run(mut socket: UdpSocket) -> Result<(), std::io::Error> {
     // FIXME: `connected` should come from the UdpSocket
     let mut connected = false;
 
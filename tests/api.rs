@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::time::Duration;
+
+use futures::executor::block_on;
+
+use raop_play::codec::{self, AudioEncoder, PcmCodec};
+use raop_play::input;
+use raop_play::test_receiver::Receiver;
+use raop_play::{AudioFormat, Frames, MetaDataValue, RaopClient, RaopParams, SampleRate, TrackMetadata, Volume, MAX_SAMPLES_PER_CHUNK};
+
+// hands samples on unchanged, in a coding of its own
+struct PassthroughEncoder {
+    encoded: usize,
+}
+
+impl AudioEncoder for PassthroughEncoder {
+    fn name(&self) -> &str { "passthrough" }
+    fn chunk_length(&self) -> Frames { Frames::new(4) }
+    fn sample_rate(&self) -> SampleRate { SampleRate::Hz48000 }
+    fn sample_size(&self) -> u32 { 16 }
+    fn channels(&self) -> u8 { 1 }
+    fn sdp(&self) -> String { "m=audio 0 RTP/AVP 96\r\na=rtpmap:96 L16/48000/1\r\n".to_owned() }
+
+    fn encode_chunk(&mut self, sample: &[u8]) -> Vec<u8> {
+        self.encoded += 1;
+        sample.to_vec()
+    }
+}
+
+#[test]
+fn test_params_from_txt_record() {
+    let txt = [("cn", "0,1"), ("et", "0"), ("sr", "44100"), ("ss", "24")].iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect::<HashMap<_, _>>();
+
+    let params = RaopParams::from_txt_record(&txt).unwrap();
+    assert_eq!(params.codec().name(), "ALAC");
+    assert_eq!(params.codec().sample_size(), 24);
+    assert_eq!(params.codec().chunk_length(), MAX_SAMPLES_PER_CHUNK);
+}
+
+#[test]
+fn test_custom_encoder() {
+    let mut params = RaopParams::new();
+    params.set_codec(Box::new(PassthroughEncoder { encoded: 0 }));
+
+    let codec = params.codec();
+    assert_eq!(codec.name(), "passthrough");
+    assert_eq!(codec.input_format(), AudioFormat::from_name("s16le", 1).unwrap());
+    assert!(codec.fallbacks().is_empty());
+}
+
+#[test]
+fn test_stream_input() {
+    // stereo s16le at 48 kHz, mixed down to the mono format of the encoder
+    let data = vec![0x00, 0x01, 0x00, 0x03, 0x00, 0xFF, 0x00, 0xFD, 0x00, 0x7F, 0x00, 0x7F];
+    let input = input::from_reader(Box::new(Cursor::new(data)), AudioFormat::from_name("s16le", 2).unwrap(), 48000, None).unwrap();
+    input::check_format(input.format()).unwrap();
+
+    let mut encoder = PassthroughEncoder { encoded: 0 };
+    let mut chunks = input::spawn(input, encoder.input_format(), encoder.chunk_length(), 4);
+    let mut encoded = Vec::new();
+
+    while let Some(chunk) = block_on(chunks.recv()) {
+        encoded.extend(encoder.encode_chunk(&chunk.unwrap()));
+    }
+
+    assert_eq!(encoded, vec![0x00, 0x02, 0x00, 0xFE, 0x00, 0x7F]);
+    assert_eq!(encoder.encoded, 1);
+}
+
+#[test]
+fn test_builtin_encoders() {
+    let mut pcm = PcmCodec::new(Frames::new(352), SampleRate::Hz44100, 16, 2);
+    assert_eq!(pcm.encode_chunk(&[0x01, 0x02, 0x03, 0x04]), vec![0x02, 0x01, 0x04, 0x03]);
+
+    let alac = codec::new_encoder(true, Frames::new(352), SampleRate::Hz44100, 16, 2).unwrap();
+    assert!(alac.sdp().contains("AppleLossless"));
+    assert_eq!(alac.fallbacks().len(), 1);
+}
+
+#[test]
+fn test_meta_data() {
+    let meta_data = TrackMetadata { title: Some("Song".to_owned()), track_number: Some(3), ..TrackMetadata::new() };
+    let item = meta_data.to_listing_item();
+
+    match item.find(b"minm").map(|item| item.value()) {
+        Some(MetaDataValue::String(title)) => assert_eq!(title, "Song"),
+        _ => panic!("expected the title in the listing"),
+    }
+
+    assert!(item.find(b"asar").is_none());
+}
+
+#[test]
+fn test_volume() {
+    assert_eq!(Volume::from_percent(100).into_f32(), 0.0);
+    assert_eq!(Volume::from_percent(0).into_f32(), -144.0);
+}
+
+#[tokio::test]
+async fn test_connect_and_stream() {
+    let mut receiver = Receiver::start().await;
+
+    let mut client = RaopClient::connect(RaopParams::new(), receiver.addr).await.unwrap();
+
+    assert!(receiver.request("ANNOUNCE").await.text().contains("a=rtpmap:96 L16/44100/2"));
+    receiver.request("SETUP").await;
+    receiver.request("RECORD").await;
+
+    let chunk = vec![0u8; 352 * 4];
+    let mut playtime = Duration::new(0, 0);
+
+    for _ in 0..3 {
+        client.accept_frames().await.unwrap();
+        client.send_chunk(&chunk, &mut playtime).await.unwrap();
+    }
+
+    let packets = [receiver.audio().await, receiver.audio().await, receiver.audio().await];
+    assert!(packets[0].first);
+
+    for (previous, packet) in packets.iter().zip(&packets[1..]) {
+        assert!(!packet.first);
+        assert_eq!(packet.seq, previous.seq.wrapping_add(1));
+        assert_eq!(packet.timestamp, previous.timestamp.wrapping_add(352));
+    }
+
+    client.teardown().await.unwrap();
+    receiver.request("TEARDOWN").await;
+}